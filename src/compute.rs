@@ -1,7 +1,12 @@
 //! This module abstracts how to compute values out of nodes.
 
 use crate::core_syntax::{ComputValue, Expr, ExprBuilder, ExprNode, Ident, Operator};
-use std::{cell::RefCell, collections::BTreeMap};
+use crate::profile::{oper_type, Pass, ProfileReport, Profiler};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet},
+    time::Instant,
+};
 
 impl AsRef<Ident> for Ident {
     fn as_ref(&self) -> &Ident {
@@ -20,7 +25,7 @@ where
     }
 }
 
-/// "Freezes" the expression tree by taking ownership of the expression builder.
+/// Holds the expression tree (taking ownership of the expression builder) and the numeric state of each node.
 /// All the methods use `&self` instead of `&mut self` because the underlying code heavily relies on `RefCell` anyway.
 pub struct ComputGraph<'a, F, OP1, OP2>
 where
@@ -29,7 +34,109 @@ where
     OP2: Operator,
 {
     ast: RefCell<BTreeMap<Ident, Node<F, OP1, OP2>>>,
+    /// Kept so the graph can be extended with new expressions.
+    eb: ExprBuilder<F, OP1, OP2>,
     calculator: &'a dyn Calculator<OP1, OP2, F>,
+    /// Set when profiling is enabled with [ComputGraph::enable_profiling].
+    profiler: RefCell<Option<Profiler>>,
+    /// Nodes marked with [Expr::checkpoint]. If there are any checkpoints, the primals of the other computed
+    /// nodes are dropped in forward and rematerialized in backward.
+    checkpoints: BTreeSet<Ident>,
+    /// Number of nested `forward` calls, to tell apart the forward called by the user from the forward called by
+    /// the calculator for the arguments.
+    forward_depth: Cell<usize>,
+    /// Per node, the number of nodes still to be computed in the current forward that use the node as an argument.
+    pending_consumers: RefCell<BTreeMap<Ident, usize>>,
+    /// Primals recomputed during backward, dropped again when backward reaches a checkpoint.
+    rematerialized: RefCell<BTreeSet<Ident>>,
+    memory: RefCell<MemoryReport>,
+    /// Set with [ComputGraph::enable_anomaly_detection].
+    detect_anomaly: Cell<bool>,
+    /// Nodes whose backward is running, the innermost last. Used to tell which node produced an adjoin.
+    backward_stack: RefCell<Vec<Ident>>,
+    hooks: RefCell<Vec<Hook<'a, F>>>,
+    next_hook_id: Cell<usize>,
+    /// Number of [ComputGraph::accumulate] calls since the last [ComputGraph::zero_grad].
+    accumulated: u32,
+    unused_params: UnusedParams,
+}
+
+/// What [ComputGraph::step] does with the parameters that got no gradient, e.g. because they are not reachable from
+/// the loss.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UnusedParams {
+    /// Leave the parameter as it is.
+    Skip,
+    /// Update the parameter with a zero gradient.
+    #[default]
+    Zero,
+    /// Fail the step.
+    Error,
+}
+
+/// A parameter of the graph, as returned by [ComputGraph::parameters].
+#[derive(Debug, Clone)]
+pub struct ParamInfo<F> {
+    pub ident: Ident,
+    pub name: Option<String>,
+    pub value: F,
+    /// Adjoin accumulated since the last update, or None if there was no backward pass.
+    pub adjoin: Option<F>,
+    /// Number of scalar elements of the value.
+    pub n_elements: usize,
+}
+
+/// Values of all the parameters, taken with [ComputGraph::snapshot_params].
+#[derive(Debug, Clone)]
+pub struct ParamSnapshot<F>(BTreeMap<Ident, F>);
+
+impl<F> ParamSnapshot<F> {
+    pub fn get(&self, ident: &Ident) -> Option<&F> {
+        self.0.get(ident)
+    }
+}
+
+/// Which nodes a hook is attached to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookTarget {
+    Node(Ident),
+    /// All the nodes of the operator type, like `Conv2d` or `PowI` (the same as in [ProfileReport]).
+    Operator(String),
+}
+
+/// Returned by [ComputGraph::add_hook], to remove the hook later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookId(usize);
+
+type HookCallback<'a, F> = Box<dyn FnMut(&Ident, &F) -> Option<F> + 'a>;
+
+struct Hook<'a, F> {
+    id: HookId,
+    target: HookTarget,
+    pass: Pass,
+    callback: HookCallback<'a, F>,
+}
+
+/// Memory held by the primals of the computed (`Ary1`, `Ary2`) nodes, in number of scalar elements. The
+/// variables, parameters and constants are not included since their memory does not depend on the graph evaluation.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MemoryReport {
+    pub live_elements: usize,
+    pub peak_elements: usize,
+    /// Number of times backward needed a primal that was dropped after forward. Each time, the primal is
+    /// recomputed from the nearest checkpoints.
+    pub recomputed: usize,
+}
+
+impl MemoryReport {
+    fn alloc(&mut self, elements: usize) {
+        self.live_elements += elements;
+        self.peak_elements = self.peak_elements.max(self.live_elements);
+    }
+
+    fn free(&mut self, elements: usize) {
+        self.live_elements -= elements;
+    }
 }
 
 /// Node holds the abstract syntax tree structure, and all the numeric data related to the node in the computation graph.
@@ -91,7 +198,37 @@ where
         }
     }
 
-    fn primal_or_const(&self) -> Option<&F> {
+    /// Arguments of the node, empty for leaf nodes.
+    pub(crate) fn args(&self) -> Vec<Ident> {
+        match self {
+            Node::Ary1 { arg1, .. } => vec![*arg1],
+            Node::Ary2 { arg1, arg2, .. } => vec![*arg1, *arg2],
+            _ => vec![],
+        }
+    }
+
+    /// Drop primal of a computed node, return the number of elements freed.
+    fn take_computed_primal(&mut self) -> usize {
+        match self {
+            Node::Ary1 { tensors, .. } | Node::Ary2 { tensors, .. } => {
+                tensors.primal.take().map(|p| p.n_elements()).unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Operator type used in profiling, like `Mul`, or the kind of the leaf node, like `Parameter`.
+    fn oper_type(&self) -> String {
+        match self {
+            Node::Const(_) => "Const".to_owned(),
+            Node::Variable { .. } => "Variable".to_owned(),
+            Node::Parameter { .. } => "Parameter".to_owned(),
+            Node::Ary1 { oper, .. } => oper_type(oper),
+            Node::Ary2 { oper, .. } => oper_type(oper),
+        }
+    }
+
+    pub(crate) fn primal_or_const(&self) -> Option<&F> {
         match self {
             Node::Const(value) => Some(value),
             Node::Variable { tensors, .. } => tensors.primal.as_ref(),
@@ -132,17 +269,59 @@ where
     OP1: Operator,
     OP2: Operator,
 {
-    /// Take ownership of the expression builder, because it "freezes" the expression. The expression, as represented
-    /// by the internal data structures of the expression builder, cannot change from now on.
+    /// Take ownership of the expression builder. The expressions built so far become the nodes of the graph. The
+    /// graph can be extended with new expressions later with [ComputGraph::extend].
     pub fn new<F2: ComputValue>(
         eb: ExprBuilder<F2, OP1, OP2>,
         calculator: &'a dyn Calculator<OP1, OP2, F2>,
     ) -> ComputGraph<'a, F2, OP1, OP2> {
-        let mut ast: BTreeMap<Ident, Node<F2, OP1, OP2>> = BTreeMap::new();
-        // Translate the expression tree constructed by the user to the one used internally with state-per-node.
+        let mut cg = ComputGraph {
+            ast: RefCell::new(BTreeMap::new()),
+            eb,
+            calculator,
+            profiler: RefCell::new(None),
+            checkpoints: BTreeSet::new(),
+            forward_depth: Cell::new(0),
+            pending_consumers: RefCell::new(BTreeMap::new()),
+            rematerialized: RefCell::new(BTreeSet::new()),
+            memory: RefCell::new(MemoryReport::default()),
+            detect_anomaly: Cell::new(false),
+            backward_stack: RefCell::new(Vec::new()),
+            hooks: RefCell::new(Vec::new()),
+            next_hook_id: Cell::new(0),
+            accumulated: 0,
+            unused_params: UnusedParams::default(),
+        };
+        cg.add_new_nodes();
+        cg
+    }
+
+    /// Build new expressions against a live graph, e.g. an evaluation metric, a regularization term or a new head
+    /// for an already trained model. The closure gets the expression builder of the graph, and can continue the
+    /// existing nodes with [ExprBuilder::expr]. The existing nodes keep their state (parameter values, primals and
+    /// adjoins). Return the idents of the new nodes from the closure.
+    pub fn extend<R>(&mut self, build: impl FnOnce(&ExprBuilder<F, OP1, OP2>) -> R) -> R {
+        let result = build(&self.eb);
+        self.add_new_nodes();
+        result
+    }
+
+    /// The expression builder holding the expressions of the graph, e.g. to print the node with [ExprBuilder::expr].
+    pub fn expr_builder(&self) -> &ExprBuilder<F, OP1, OP2> {
+        &self.eb
+    }
+
+    /// Translate the expression tree constructed by the user to the one used internally with state-per-node. Only the
+    /// nodes not translated yet are added.
+    fn add_new_nodes(&mut self) {
+        let eb = &self.eb;
+        let mut ast = self.ast.borrow_mut();
         for (ident, expr_node) in eb.id_to_node.borrow().iter() {
+            if ast.contains_key(ident) {
+                continue;
+            }
             let tensors = Tensors::default();
-            let new_node: Node<F2, OP1, OP2> = match expr_node {
+            let new_node: Node<F, OP1, OP2> = match expr_node {
                 ExprNode::Const(value) => Node::Const(value.clone()),
                 ExprNode::Parameter(name_id, initial_value) => Node::Parameter {
                     name: name_id.and_then(|id| eb.get_name(&id)),
@@ -169,15 +348,9 @@ where
                     tensors,
                 },
             };
-            if let Some(_) = ast.insert(*ident, new_node) {
-                panic!("Bug. ast node already set.")
-            }
-        }
-
-        ComputGraph {
-            ast: RefCell::new(ast),
-            calculator,
+            ast.insert(*ident, new_node);
         }
+        self.checkpoints = eb.checkpoints.borrow().clone();
     }
 
     /// Set variable once, panic if the variable was already set.
@@ -223,11 +396,32 @@ where
         }
     }
 
+    /// Set parameter (primal) to some value. Do not fail if the parameter is already set, return the old primal.
+    pub fn reset_primal_of_parameter(&mut self, ident: &dyn AsRef<Ident>, value: F) -> Option<F> {
+        let ident = ident.as_ref();
+        let mut ast = self.ast.borrow_mut();
+        let node = ast.get_mut(ident).unwrap();
+        if let Node::Parameter { tensors, .. } = node {
+            tensors.primal.replace(value)
+        } else {
+            panic!("Node is not a Parameter!")
+        }
+    }
+
     pub fn get_node(&self, ident: &Ident) -> Node<F, OP1, OP2> {
-        let ast = self.ast.borrow();
-        ast.get(ident)
-            .expect(format!("No node for ident {}", ident).as_str())
-            .clone()
+        let start = self.profile_enter();
+        let node = {
+            let ast = self.ast.borrow();
+            ast.get(ident)
+                .unwrap_or_else(|| panic!("No node for ident {}", ident))
+                .clone()
+        };
+        if let Some(start) = start {
+            if let Some(profiler) = self.profiler.borrow_mut().as_mut() {
+                profiler.exit_get_node(start);
+            }
+        }
+        node
     }
 
     pub fn get_name(&self, ident: &Ident) -> Option<String> {
@@ -250,28 +444,111 @@ where
         {
             let mut ast = self.ast.borrow_mut();
             for (_, node) in ast.iter_mut() {
-                match node {
-                    Node::Const(_) => (),
-                    Node::Variable { tensors, .. } => {
-                        tensors.primal.take();
+                if let Node::Variable { tensors, .. } = node {
+                    tensors.primal.take();
+                }
+            }
+        }
+        self.reset_computed_primals();
+    }
+
+    pub(crate) fn reset_computed_primals(&mut self) {
+        {
+            let mut ast = self.ast.borrow_mut();
+            let mut memory = self.memory.borrow_mut();
+            for (_, node) in ast.iter_mut() {
+                memory.free(node.take_computed_primal());
+            }
+        }
+        self.rematerialized.borrow_mut().clear();
+    }
+
+    /// Start accumulating gradients from scratch: reset the adjoins of all the nodes. The first step of the training
+    /// lifecycle `zero_grad`, `accumulate` (per input), `step`.
+    pub fn zero_grad(&mut self) {
+        for (_, node) in self.ast.borrow_mut().iter_mut() {
+            if let Some(tensors) = node.tensors_as_mut() {
+                tensors.adjoin.take();
+            }
+        }
+        self.accumulated = 0;
+    }
+
+    /// Run forward and backward of `loss` for the current input, adding the gradients to the ones accumulated since
+    /// [ComputGraph::zero_grad]. The computed primals are reset afterwards, so the next input can be set with
+    /// [ComputGraph::reset_primal_of_variable]. Under [UnusedParams::Zero] the parameters without gradient get a
+    /// zero adjoin. Return the loss.
+    pub fn accumulate(&mut self, loss: &dyn AsRef<Ident>) -> F {
+        let value = self.forward(loss);
+        self.backward(loss);
+        if self.unused_params == UnusedParams::Zero {
+            for (_, node) in self.ast.borrow_mut().iter_mut() {
+                if let Node::Parameter { tensors, .. } = node {
+                    if tensors.adjoin.is_none() {
+                        let zero = tensors.primal.clone().map(|primal| primal * 0.0);
+                        tensors.adjoin = zero.map(|zero| (zero, 1));
                     }
-                    Node::Parameter { .. } => (),
-                    Node::Ary1 { tensors, .. } => {
-                        tensors.primal.take();
+                }
+            }
+        }
+        self.reset_computed_primals();
+        self.accumulated += 1;
+        value
+    }
+
+    /// Move the parameters against the gradient averaged over the [ComputGraph::accumulate] calls. The parameters
+    /// that got no gradient are handled according to [ComputGraph::set_unused_params]. On error no parameter is
+    /// changed. The gradients are kept until [ComputGraph::zero_grad].
+    pub fn step(&mut self, learning_rate: f32) -> Result<(), String> {
+        let mut ast = self.ast.borrow_mut();
+        if self.unused_params == UnusedParams::Error {
+            let unused: Vec<String> = ast
+                .iter()
+                .filter_map(|(ident, node)| match node {
+                    Node::Parameter { name, tensors } if tensors.adjoin.is_none() => {
+                        Some(name.clone().unwrap_or(format!("{}", ident)))
                     }
-                    Node::Ary2 { tensors, .. } => {
-                        tensors.primal.take();
+                    _ => None,
+                })
+                .collect();
+            if !unused.is_empty() {
+                return Err(format!("No gradient for parameters {}", unused.join(", ")));
+            }
+        }
+        let scale = learning_rate / self.accumulated.max(1) as f32;
+        for (ident, node) in ast.iter_mut() {
+            if let Node::Parameter { tensors, .. } = node {
+                let primal = tensors
+                    .primal
+                    .take()
+                    .unwrap_or_else(|| panic!("primal missing for parameter {}", ident));
+                let adjoin = match (&tensors.adjoin, self.unused_params) {
+                    (Some((adjoin, _)), _) => adjoin.clone(),
+                    (None, UnusedParams::Zero) => primal.clone() * 0.0,
+                    (None, _) => {
+                        tensors.primal.replace(primal);
+                        continue;
                     }
-                }
+                };
+                // -1.0 because Add and Mul is implemented but Sub not necessarily.
+                tensors.primal.replace(primal + adjoin * -1.0 * scale);
             }
         }
+        Ok(())
+    }
+
+    /// Set what [ComputGraph::step] does with the parameters without gradient. The default is [UnusedParams::Zero].
+    pub fn set_unused_params(&mut self, policy: UnusedParams) {
+        self.unused_params = policy;
     }
 
     /// Reset the internal state (variable primals, adjoins). Do not clean parameters.
     pub fn reset_state_for_next_epoch(&mut self) {
         {
             let mut ast = self.ast.borrow_mut();
+            let mut memory = self.memory.borrow_mut();
             for (_ident, node) in ast.iter_mut() {
+                memory.free(node.take_computed_primal());
                 match node {
                     Node::Const(_) => (),
                     Node::Variable { tensors, .. } => {
@@ -289,6 +566,7 @@ where
                 }
             }
         }
+        self.rematerialized.borrow_mut().clear();
     }
 
     pub fn update_params_lr(&mut self, learning_rate: f32) {
@@ -314,10 +592,11 @@ where
                     .primal
                     .as_mut()
                     .expect(format!("primal missing for variable {:?}", name).as_str());
-                (adjoin, adjoin_update_cnt) = tensors
-                    .adjoin
-                    .take()
-                    .expect(format!("adjoin missing for variable {:?}", name).as_str());
+                // A parameter not reachable from the loss has zero gradient.
+                (adjoin, adjoin_update_cnt) = match tensors.adjoin.take() {
+                    Some(adjoin) => adjoin,
+                    None => continue,
+                };
             } else {
                 panic!("Expected Parameter!")
             }
@@ -343,7 +622,26 @@ where
             }
         }
 
+        let is_top_level = self.forward_depth.get() == 0;
+        if is_top_level && self.is_rematerializing() {
+            self.count_pending_consumers(ident);
+        }
+        self.forward_depth.set(self.forward_depth.get() + 1);
+        let start = self.profile_enter();
         let calculated_primal = self.calculator.forward(self, ident);
+        self.profile_exit_node(start, Pass::Forward, ident, &calculated_primal);
+        self.forward_depth.set(self.forward_depth.get() - 1);
+        if self.detect_anomaly.get() && !calculated_primal.is_finite() {
+            panic!(
+                "Anomaly detected in forward: primal {} of node {} is not finite.\n{}",
+                calculated_primal,
+                ident,
+                self.describe_node(ident)
+            );
+        }
+        let calculated_primal = self
+            .run_hooks(Pass::Forward, ident, &calculated_primal)
+            .unwrap_or(calculated_primal);
 
         {
             // Insert calculated primal to ast tree.
@@ -359,10 +657,89 @@ where
             if let Some(old) = old {
                 panic!("The value for {} already set to {}", ident, old)
             }
+            self.memory
+                .borrow_mut()
+                .alloc(calculated_primal.n_elements());
+        }
+        if self.is_rematerializing() {
+            self.release_args(ident);
+            if is_top_level {
+                self.pending_consumers.borrow_mut().clear();
+            }
         }
         calculated_primal
     }
 
+    fn is_rematerializing(&self) -> bool {
+        !self.checkpoints.is_empty()
+    }
+
+    /// For the nodes that are about to be computed by forward of `root`, count how many times each node is used
+    /// as an argument. Do not descend into the nodes that already have primals, since those are not recomputed.
+    fn count_pending_consumers(&self, root: &Ident) {
+        let ast = self.ast.borrow();
+        let mut pending = self.pending_consumers.borrow_mut();
+        pending.clear();
+        let mut visited: BTreeSet<Ident> = BTreeSet::new();
+        let mut stack = vec![*root];
+        while let Some(ident) = stack.pop() {
+            if !visited.insert(ident) {
+                continue;
+            }
+            let node = ast.get(&ident).expect("Bug: node is missing!");
+            if node.primal_or_const().is_some() {
+                continue;
+            }
+            for arg in node.args() {
+                *pending.entry(arg).or_insert(0) += 1;
+                stack.push(arg);
+            }
+        }
+    }
+
+    /// The node was just computed, so its arguments have one consumer less. Drop the primals of the arguments
+    /// that are not needed by forward anymore, unless they are checkpoints.
+    fn release_args(&self, ident: &Ident) {
+        let mut ast = self.ast.borrow_mut();
+        let args = ast.get(ident).expect("Bug: node is missing!").args();
+        let mut pending = self.pending_consumers.borrow_mut();
+        for arg in args {
+            let count = if let Some(count) = pending.get_mut(&arg) {
+                *count -= 1;
+                *count
+            } else {
+                continue;
+            };
+            if count == 0 && !self.checkpoints.contains(&arg) {
+                let node = ast.get_mut(&arg).expect("Bug: node is missing!");
+                self.memory.borrow_mut().free(node.take_computed_primal());
+            }
+        }
+    }
+
+    /// Drop the primals recomputed during backward.
+    fn drop_rematerialized(&self) {
+        let mut ast = self.ast.borrow_mut();
+        let mut memory = self.memory.borrow_mut();
+        for ident in self.rematerialized.borrow_mut().iter() {
+            let node = ast.get_mut(ident).expect("Bug: node is missing!");
+            memory.free(node.take_computed_primal());
+        }
+        self.rematerialized.borrow_mut().clear();
+    }
+
+    /// Memory held by the primals of the computed nodes. Use it to see the effect of [Expr::checkpoint].
+    pub fn memory_report(&self) -> MemoryReport {
+        *self.memory.borrow()
+    }
+
+    /// Start tracking the peak memory from the current memory usage.
+    pub fn reset_peak_memory(&self) {
+        let mut memory = self.memory.borrow_mut();
+        memory.peak_elements = memory.live_elements;
+        memory.recomputed = 0;
+    }
+
     /// Implement reverse mode of automatic gradient. The procedure is as follows:
     /// 1. Each Node has child nodes, e.g. for node Y that is X1+X2, the X nodes are children.
     ///    For a Y node, consider the contribution of Y to each of its children X.
@@ -374,7 +751,123 @@ where
     pub fn backward(&self, ident: &dyn AsRef<Ident>) {
         let ident = ident.as_ref();
         let adjoin = F::default_adjoin(self.forward(ident));
-        self.calculator.backward(self, ident, &adjoin);
+        self.propagate_adjoin(ident, &adjoin);
+        if self.is_rematerializing() {
+            self.drop_rematerialized();
+        }
+    }
+
+    /// Add the (partial) adjoin to the node, and let the calculator propagate it further to the node's arguments.
+    /// The calculators call `propagate_adjoin` for the arguments of the node in [Calculator::backward].
+    pub fn propagate_adjoin(&self, ident: &Ident, adjoin: &F) {
+        if self.detect_anomaly.get() && !adjoin.is_finite() {
+            let producer = match self.backward_stack.borrow().last() {
+                Some(producer) => format!(
+                    "It was produced by the backward of node {}.\n{}",
+                    producer,
+                    self.describe_node(producer)
+                ),
+                None => "It is the initial adjoin.".to_owned(),
+            };
+            panic!(
+                "Anomaly detected in backward: adjoin {} of node {} is not finite. {}",
+                adjoin, ident, producer
+            );
+        }
+        let replaced = self.run_hooks(Pass::Backward, ident, adjoin);
+        let adjoin = replaced.as_ref().unwrap_or(adjoin);
+        let start = self.profile_enter();
+        self.add_adjoin(ident, adjoin);
+        if self.checkpoints.contains(ident) {
+            // The rematerialized primals from above the checkpoint can be recomputed if needed again.
+            self.drop_rematerialized();
+        }
+        self.backward_stack.borrow_mut().push(*ident);
+        self.calculator.backward(self, ident, adjoin);
+        self.backward_stack.borrow_mut().pop();
+        self.profile_exit_node(start, Pass::Backward, ident, adjoin);
+    }
+
+    /// Check every primal and adjoin as it is produced, and panic on the first one that is NaN or infinite. The panic
+    /// message names the offending node, its operator, the values of its inputs and its expression.
+    pub fn enable_anomaly_detection(&self) {
+        self.detect_anomaly.set(true);
+    }
+
+    pub fn disable_anomaly_detection(&self) {
+        self.detect_anomaly.set(false);
+    }
+
+    /// Register a callback run with the value produced for the target nodes:
+    /// - in [Pass::Forward], with the primal computed by the calculator (the leaf nodes are not computed so the
+    ///   hooks are not run for them),
+    /// - in [Pass::Backward], with each adjoin that reaches the node, before it is accumulated and propagated to the
+    ///   arguments.
+    ///
+    /// If the callback returns a value, the value replaces the primal or the adjoin, e.g. to scale the gradient of the
+    /// whole subgraph. The hooks are run in the order they were added. A hook must not call the graph.
+    pub fn add_hook(
+        &self,
+        target: HookTarget,
+        pass: Pass,
+        callback: impl FnMut(&Ident, &F) -> Option<F> + 'a,
+    ) -> HookId {
+        let id = HookId(self.next_hook_id.get());
+        self.next_hook_id.set(id.0 + 1);
+        self.hooks.borrow_mut().push(Hook {
+            id,
+            target,
+            pass,
+            callback: Box::new(callback),
+        });
+        id
+    }
+
+    pub fn remove_hook(&self, id: HookId) {
+        self.hooks.borrow_mut().retain(|hook| hook.id != id);
+    }
+
+    /// Run the hooks matching the node, return the replaced value if any hook replaced it.
+    fn run_hooks(&self, pass: Pass, ident: &Ident, value: &F) -> Option<F> {
+        let mut hooks = self.hooks.borrow_mut();
+        if hooks.is_empty() {
+            return None;
+        }
+        let oper_type = self.ast.borrow().get(ident)?.oper_type();
+        let mut replaced: Option<F> = None;
+        for hook in hooks.iter_mut().filter(|hook| hook.pass == pass) {
+            let matches = match &hook.target {
+                HookTarget::Node(target) => target == ident,
+                HookTarget::Operator(target) => *target == oper_type,
+            };
+            if matches {
+                let current = replaced.as_ref().unwrap_or(value);
+                if let Some(new_value) = (hook.callback)(ident, current) {
+                    replaced = Some(new_value);
+                }
+            }
+        }
+        replaced
+    }
+
+    /// Operator, input values and expression of the node, for the anomaly reports.
+    fn describe_node(&self, ident: &Ident) -> String {
+        let ast = self.ast.borrow();
+        let node = ast.get(ident).expect("Bug: node is missing!");
+        let inputs: Vec<String> = node
+            .args()
+            .iter()
+            .map(|arg| match ast.get(arg).and_then(|n| n.primal_or_const()) {
+                Some(primal) => format!("{} = {}", arg, primal),
+                None => format!("{} = <not computed>", arg),
+            })
+            .collect();
+        format!(
+            "  operator: {}\n  inputs: {}\n  expression: {}",
+            node.oper_type(),
+            inputs.join(", "),
+            self.eb.expr(ident)
+        )
     }
 
     /// Call `add_adjoin` to update adjoin for a node with partial adjoin.
@@ -396,12 +889,59 @@ where
         tensors_ref.adjoin.replace(updated_adjoin);
     }
 
+    /// Start recording wall time, call count and allocated elements of each node in forward and backward passes.
+    /// The measurements recorded so far (if any) are discarded.
+    pub fn enable_profiling(&self) {
+        self.profiler.replace(Some(Profiler::default()));
+    }
+
+    pub fn disable_profiling(&self) {
+        self.profiler.replace(None);
+    }
+
+    /// Return aggregated measurements, or None if profiling is not enabled.
+    pub fn profile_report(&self) -> Option<ProfileReport> {
+        self.profiler.borrow().as_ref().map(|p| p.report())
+    }
+
+    fn profile_enter(&self) -> Option<Instant> {
+        self.profiler.borrow_mut().as_mut().map(|p| p.enter())
+    }
+
+    fn profile_exit_node(&self, start: Option<Instant>, pass: Pass, ident: &Ident, value: &F) {
+        let start = if let Some(start) = start {
+            start
+        } else {
+            return;
+        };
+        let oper = self
+            .ast
+            .borrow()
+            .get(ident)
+            .expect("Bug: node is missing in profiling!")
+            .oper_type();
+        if let Some(profiler) = self.profiler.borrow_mut().as_mut() {
+            profiler.exit_node(start, pass, *ident, oper, value.n_elements());
+        }
+    }
+
+    /// Return primal of the node. With checkpoints, the primal dropped after forward is recomputed.
     pub fn primal(&self, ident: &Ident) -> F {
-        let ast = self.ast.borrow();
-        let node = ast.get(ident).unwrap();
-        node.primal_or_const()
-            .expect(format!("Primal or const missing for {}", &ident).as_str())
-            .clone()
+        {
+            let ast = self.ast.borrow();
+            let node = ast.get(ident).unwrap();
+            if let Some(primal) = node.primal_or_const() {
+                return primal.clone();
+            }
+            let is_computed = matches!(node, Node::Ary1 { .. } | Node::Ary2 { .. });
+            if !(is_computed && self.is_rematerializing()) {
+                panic!("Primal or const missing for {}", &ident)
+            }
+        }
+        let primal = self.forward(ident);
+        self.rematerialized.borrow_mut().insert(*ident);
+        self.memory.borrow_mut().recomputed += 1;
+        primal
     }
 
     /// If returns None it means that either the node type does not have adjoin (Const), or there
@@ -413,6 +953,61 @@ where
         let (adjoin, _) = tensors.adjoin.clone()?;
         Some(adjoin)
     }
+
+    /// All the parameters of the graph, in the order they were created.
+    pub fn parameters(&self) -> impl Iterator<Item = ParamInfo<F>> {
+        let ast = self.ast.borrow();
+        let params: Vec<ParamInfo<F>> = ast
+            .iter()
+            .filter_map(|(ident, node)| match node {
+                Node::Parameter { name, tensors } => {
+                    let value = tensors
+                        .primal
+                        .clone()
+                        .unwrap_or_else(|| panic!("primal missing for parameter {}", ident));
+                    Some(ParamInfo {
+                        ident: *ident,
+                        name: name.clone(),
+                        n_elements: value.n_elements(),
+                        value,
+                        adjoin: tensors.adjoin.as_ref().map(|(adjoin, _)| adjoin.clone()),
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        params.into_iter()
+    }
+
+    /// Total number of scalar elements of all the parameters, i.e. the size of the model.
+    pub fn parameter_count(&self) -> usize {
+        self.parameters().map(|p| p.n_elements).sum()
+    }
+
+    /// Take the values of all the parameters, e.g. to evaluate trial values and go back with
+    /// [ComputGraph::restore_params].
+    pub fn snapshot_params(&self) -> ParamSnapshot<F> {
+        ParamSnapshot(self.parameters().map(|p| (p.ident, p.value)).collect())
+    }
+
+    /// Set the parameters to the values of the snapshot. The computed primals are reset, since they depend on
+    /// the parameters.
+    pub fn restore_params(&mut self, snapshot: &ParamSnapshot<F>) {
+        for (ident, value) in snapshot.0.iter() {
+            self.reset_primal_of_parameter(ident, value.clone());
+        }
+        self.reset_computed_primals();
+    }
+
+    /// Find a variable or a named parameter by its name.
+    pub fn find(&self, name: &str) -> Option<Ident> {
+        let ast = self.ast.borrow();
+        ast.iter().find_map(|(ident, node)| match node {
+            Node::Variable { name: n, .. } if n == name => Some(*ident),
+            Node::Parameter { name: Some(n), .. } if n == name => Some(*ident),
+            _ => None,
+        })
+    }
 }
 
 /// Take node and return a calculated value.
@@ -430,19 +1025,94 @@ where
     /// be set beforehand with `set_variable`. It's ok to `panic` on Node::Variable.
     fn forward(&self, cg: &ComputGraph<F, OP1, OP2>, ident: &Ident) -> F;
 
-    /// The implementation of the backward pass is responsible for updating partial adjoins though ComputGraph.
+    /// The implementation of the backward pass is responsible for propagating partial adjoins to the arguments of the
+    /// node with [ComputGraph::propagate_adjoin]. The `adjoin` of the node itself is already accumulated.
     fn backward(&self, cg: &ComputGraph<F, OP1, OP2>, ident: &Ident, adjoin: &F);
 }
 
+/// Calculate a single operation out of the values of its arguments. Unlike [Calculator], [OperCalculator] does not
+/// need the graph, so it can also be used by [Tape](crate::tape::Tape). Every [OperCalculator] is a [Calculator].
+pub trait OperCalculator<OP1, OP2, F>
+where
+    F: ComputValue,
+    OP1: Operator,
+    OP2: Operator,
+{
+    fn forward_ary1(&self, oper: &OP1, a: &F) -> F;
+
+    fn forward_ary2(&self, oper: &OP2, a: &F, b: &F) -> F;
+
+    /// Return the partial adjoin of the argument `a`, given the `primal` and the `adjoin` of the node.
+    fn backward_ary1(&self, oper: &OP1, a: &F, primal: &F, adjoin: &F) -> F;
+
+    /// Return the partial adjoins of the arguments `a` and `b`, given the `primal` and the `adjoin` of the node.
+    fn backward_ary2(&self, oper: &OP2, a: &F, b: &F, primal: &F, adjoin: &F) -> (F, F);
+}
+
+impl<C, OP1, OP2, F> Calculator<OP1, OP2, F> for C
+where
+    C: OperCalculator<OP1, OP2, F>,
+    F: ComputValue,
+    OP1: Operator,
+    OP2: Operator,
+{
+    fn forward(&self, cg: &ComputGraph<F, OP1, OP2>, ident: &Ident) -> F {
+        match cg.get_node(ident) {
+            Node::Const(value) => value,
+            // Variable and Parameter should have been already returned by ComputGraph.
+            Node::Variable { name, .. } => panic!("Variable not set in .forward(): {}", name),
+            Node::Parameter { name, .. } => panic!("Parameter not set in .forward(): {:?}", name),
+            Node::Ary1 { oper, arg1, .. } => {
+                let a = cg.forward(&arg1);
+                self.forward_ary1(&oper, &a)
+            }
+            Node::Ary2 {
+                oper, arg1, arg2, ..
+            } => {
+                let a = cg.forward(&arg1);
+                let b = cg.forward(&arg2);
+                self.forward_ary2(&oper, &a, &b)
+            }
+        }
+    }
+
+    fn backward(&self, cg: &ComputGraph<F, OP1, OP2>, ident: &Ident, adjoin: &F) {
+        match cg.get_node(ident) {
+            Node::Const(_) | Node::Variable { .. } | Node::Parameter { .. } => (),
+            Node::Ary1 { oper, arg1, .. } => {
+                let a = cg.primal(&arg1);
+                let primal = cg.primal(ident);
+                let adjoin_a = self.backward_ary1(&oper, &a, &primal, adjoin);
+                cg.propagate_adjoin(&arg1, &adjoin_a);
+            }
+            Node::Ary2 {
+                oper, arg1, arg2, ..
+            } => {
+                let a = cg.primal(&arg1);
+                let b = cg.primal(&arg2);
+                let primal = cg.primal(ident);
+                let (adjoin_a, adjoin_b) = self.backward_ary2(&oper, &a, &b, &primal, adjoin);
+                cg.propagate_adjoin(&arg1, &adjoin_a);
+                cg.propagate_adjoin(&arg2, &adjoin_b);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::ComputGraph;
+    use std::cell::RefCell;
+
+    use approx_eq::assert_approx_eq;
+
+    use super::{ComputGraph, HookTarget, UnusedParams};
     use crate::{
-        core_syntax::ExprBuilder,
+        core_syntax::{ExprBuilder, Ident},
         float::{
             calculator::FloatCalculator,
             syntax::{FloatOperAry1, FloatOperAry2},
         },
+        profile::Pass,
     };
 
     #[test]
@@ -482,6 +1152,295 @@ mod tests {
         assert_eq!(cg.adjoin(&x1), Some(-4.0)); // not sure if this value is ok
     }
 
+    #[test]
+    fn profiling() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let y = (x * x).sin() * x;
+
+        let [x, y] = [x, y].map(|p| p.ident);
+        let mut cg = ComputGraph::<f32, FloatOperAry1, FloatOperAry2>::new(eb, &FloatCalculator);
+        assert!(cg.profile_report().is_none());
+        cg.enable_profiling();
+        for x_inp in [1.0, 2.0, 3.0] {
+            cg.reset_state_for_next_input();
+            cg.reset_primal_of_variable(&x, x_inp);
+            cg.forward(&y);
+            cg.backward(&y);
+        }
+        let report = cg.profile_report().unwrap();
+
+        let calls = |pass: Pass, oper: &str| {
+            report
+                .by_operator
+                .iter()
+                .find(|p| p.pass == pass && p.oper == oper)
+                .map(|p| p.stat.calls)
+        };
+        assert_eq!(calls(Pass::Forward, "Mul"), Some(6));
+        assert_eq!(calls(Pass::Forward, "Sin"), Some(3));
+        assert_eq!(calls(Pass::Forward, "Variable"), None);
+        assert_eq!(calls(Pass::Backward, "Mul"), Some(6));
+        assert_eq!(calls(Pass::Backward, "Variable"), Some(9));
+        assert_eq!(report.by_node.len(), 3 + 4);
+        assert!(report.get_node.calls > 0);
+        assert!(format!("{}", report).contains("Sin"));
+    }
+
+    #[test]
+    fn checkpoints_bound_memory() {
+        let run = |checkpoint_every: Option<usize>| {
+            let eb = new_eb();
+            let x = eb.new_variable("x");
+            let mut y = x;
+            for i in 1..=20 {
+                y = (y * x).sin();
+                if checkpoint_every.is_some_and(|n| i % n == 0) {
+                    y = y.checkpoint();
+                }
+            }
+            let [x, y] = [x, y].map(|p| p.ident);
+            let mut cg =
+                ComputGraph::<f32, FloatOperAry1, FloatOperAry2>::new(eb, &FloatCalculator);
+            cg.set_variable(&x, 0.9);
+            let primal = cg.forward(&y);
+            let forward_memory = cg.memory_report();
+            cg.reset_peak_memory();
+            cg.backward(&y);
+            (
+                primal,
+                cg.adjoin(&x).unwrap(),
+                forward_memory,
+                cg.memory_report(),
+            )
+        };
+
+        let (primal, adjoin, forward_memory, backward_memory) = run(None);
+        assert_eq!(forward_memory.peak_elements, 40);
+        assert_eq!(backward_memory.peak_elements, 40);
+        assert_eq!(backward_memory.recomputed, 0);
+
+        let (ck_primal, ck_adjoin, ck_forward_memory, ck_backward_memory) = run(Some(5));
+        assert_eq!(ck_primal, primal);
+        assert_eq!(ck_adjoin, adjoin);
+        // Only the checkpoints and the nodes currently computed are kept.
+        assert!(ck_forward_memory.peak_elements <= 4 + 2);
+        assert_eq!(ck_forward_memory.live_elements, 4);
+        assert!(ck_backward_memory.peak_elements < 20);
+        assert!(ck_backward_memory.recomputed > 0);
+        assert_eq!(ck_backward_memory.live_elements, 4);
+    }
+
+    #[test]
+    fn extend() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let a = eb.new_named_parameter("a", 2.0);
+        let y = a * x;
+        let [x, a, y] = [x, a, y].map(|e| e.ident);
+        let mut cg = ComputGraph::<f32, FloatOperAry1, FloatOperAry2>::new(eb, &FloatCalculator);
+        cg.set_variable(&x, 3.0);
+        assert_eq!(cg.forward(&y), 6.0);
+        cg.backward(&y);
+        cg.update_params_lr(0.1);
+
+        // Add a regularization term to the trained graph.
+        let (t, loss) = cg.extend(|eb| {
+            let t = eb.new_variable("t");
+            let loss = (eb.expr(&y) - t).powi(2) + eb.expr(&a).powi(2);
+            (t.ident, loss.ident)
+        });
+        assert_eq!(
+            "(pow2(((a * x) - t)) + pow2(a))",
+            format!("{}", cg.expr_builder().expr(&loss))
+        );
+        // The state of the existing nodes survives.
+        let a_value = cg.primal(&a);
+        assert_eq!(a_value, 2.0 - 0.1 * 3.0);
+        assert_eq!(cg.primal(&y), 6.0);
+
+        cg.reset_state_for_next_input();
+        cg.reset_primal_of_variable(&x, 1.0);
+        cg.set_variable(&t, 1.0);
+        assert_eq!(cg.forward(&loss), (a_value - 1.0).powi(2) + a_value.powi(2));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Anomaly detected in forward: primal NaN of node _3 is not finite.\n  \
+                               operator: Ln\n  inputs: _2 = -1\n  expression: ln((x * a))"
+    )]
+    fn anomaly_forward() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let a = eb.new_named_parameter("a", 1.0);
+        let y = (x * a).ln().sin();
+        let [x, y] = [x, y].map(|e| e.ident);
+        let mut cg = ComputGraph::<f32, FloatOperAry1, FloatOperAry2>::new(eb, &FloatCalculator);
+        cg.enable_anomaly_detection();
+        cg.set_variable(&x, -1.0);
+        cg.forward(&y);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Anomaly detected in backward: adjoin NaN of node _1 is not finite. \
+                               It was produced by the backward of node _2.\n  \
+                               operator: Pow\n  inputs: _0 = 0, _1 = 2\n  expression: (x^b)"
+    )]
+    fn anomaly_backward() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let b = eb.new_named_parameter("b", 2.0);
+        let y = x.pow(b);
+        let [x, y] = [x, y].map(|e| e.ident);
+        let mut cg = ComputGraph::<f32, FloatOperAry1, FloatOperAry2>::new(eb, &FloatCalculator);
+        cg.enable_anomaly_detection();
+        cg.set_variable(&x, 0.0);
+        assert_eq!(cg.forward(&y), 0.0);
+        cg.backward(&y);
+    }
+
+    #[test]
+    fn hooks() {
+        let captured: RefCell<Vec<(Ident, f32)>> = RefCell::new(Vec::new());
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let a = eb.new_named_parameter("a", 3.0);
+        let h = (x * a).sin();
+        let y = h * h;
+        let [x, a, h, y] = [x, a, h, y].map(|e| e.ident);
+        let mut cg = ComputGraph::<f32, FloatOperAry1, FloatOperAry2>::new(eb, &FloatCalculator);
+
+        // Capture the activation.
+        let capture = cg.add_hook(HookTarget::Node(h), Pass::Forward, |ident, primal| {
+            captured.borrow_mut().push((*ident, *primal));
+            None
+        });
+        // Scale the gradient flowing through all the multiplications.
+        cg.add_hook(
+            HookTarget::Operator("Mul".to_owned()),
+            Pass::Backward,
+            |_, adjoin| Some(adjoin * 0.5),
+        );
+        cg.set_variable(&x, 0.1);
+        cg.forward(&y);
+        cg.backward(&y);
+        let h_value = (0.1_f32 * 3.0).sin();
+        assert_eq!(*captured.borrow(), vec![(h, h_value)]);
+        // dy/dh = 2h, scaled at y; dh/da = cos(0.3) * x, scaled again at (x * a).
+        let expected = 0.5 * 2.0 * h_value * 0.3_f32.cos() * 0.5 * 0.1;
+        assert_approx_eq!(cg.adjoin(&a).unwrap() as f64, expected as f64, 1e-6);
+
+        cg.remove_hook(capture);
+        cg.reset_state_for_next_input();
+        cg.set_variable(&x, 0.2);
+        cg.forward(&y);
+        assert_eq!(captured.borrow().len(), 1);
+    }
+
+    #[test]
+    fn parameters() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let a = eb.new_named_parameter("a", 2.0);
+        let b = eb.new_parameter(1.0);
+        let y = a * x + b;
+        let [x, a, b, y] = [x, a, b, y].map(|e| e.ident);
+        let mut cg = ComputGraph::<f32, FloatOperAry1, FloatOperAry2>::new(eb, &FloatCalculator);
+        assert_eq!(cg.find("x"), Some(x));
+        assert_eq!(cg.find("a"), Some(a));
+        assert_eq!(cg.find("b"), None);
+        assert_eq!(cg.parameter_count(), 2);
+
+        cg.set_variable(&x, 3.0);
+        cg.forward(&y);
+        cg.backward(&y);
+        let params: Vec<(Ident, Option<String>, f32, Option<f32>)> = cg
+            .parameters()
+            .map(|p| (p.ident, p.name, p.value, p.adjoin))
+            .collect();
+        assert_eq!(
+            params,
+            vec![
+                (a, Some("a".to_owned()), 2.0, Some(3.0)),
+                (b, None, 1.0, Some(1.0))
+            ]
+        );
+
+        let snapshot = cg.snapshot_params();
+        cg.update_params_lr(0.5);
+        cg.reset_state_for_next_input();
+        cg.set_variable(&x, 3.0);
+        assert_eq!(cg.forward(&y), 0.5 * 3.0 + 0.5);
+        cg.restore_params(&snapshot);
+        assert_eq!(snapshot.get(&a), Some(&2.0));
+        assert_eq!(cg.forward(&y), 7.0);
+    }
+
+    #[test]
+    fn accumulate_then_update_params_lr() {
+        let eb = new_eb();
+        let a = eb.new_named_parameter("a", 2.0);
+        let unused = eb.new_named_parameter("unused", 5.0);
+        let loss = a * a;
+        let [a, unused, loss] = [a, unused, loss].map(|e| e.ident);
+        let mut cg = ComputGraph::<f32, FloatOperAry1, FloatOperAry2>::new(eb, &FloatCalculator);
+
+        cg.accumulate(&loss);
+        cg.update_params_lr(0.1);
+        // The gradient 4 is divided by its 2 updates, one per operand of `a * a`.
+        assert_eq!(cg.primal(&a), 2.0 - 0.1 * 4.0 / 2.0);
+        // The stored zero gradient leaves the unused parameter as it is, not NaN.
+        assert_eq!(cg.primal(&unused), 5.0);
+    }
+
+    #[test]
+    fn gradient_lifecycle() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let a = eb.new_named_parameter("a", 1.0);
+        let unused = eb.new_named_parameter("unused", 5.0);
+        let loss = a * x;
+        let _ = unused * x;
+        let [x, a, unused, loss] = [x, a, unused, loss].map(|e| e.ident);
+        let mut cg = ComputGraph::<f32, FloatOperAry1, FloatOperAry2>::new(eb, &FloatCalculator);
+
+        cg.zero_grad();
+        for x_inp in [1.0, 3.0] {
+            cg.reset_primal_of_variable(&x, x_inp);
+            assert_eq!(cg.accumulate(&loss), x_inp);
+        }
+        assert_eq!(cg.adjoin(&a), Some(4.0));
+        // The default is to treat the unused parameter as having zero gradient.
+        assert_eq!(cg.adjoin(&unused), Some(0.0));
+        let adjoins: Vec<_> = cg.parameters().map(|p| p.adjoin).collect();
+        assert_eq!(adjoins, [Some(4.0), Some(0.0)]);
+        cg.step(0.5).unwrap();
+        assert_eq!(cg.primal(&a), 1.0 - 0.5 * 2.0);
+        assert_eq!(cg.primal(&unused), 5.0);
+
+        cg.set_unused_params(UnusedParams::Error);
+        cg.zero_grad();
+        cg.reset_primal_of_variable(&x, 1.0);
+        cg.accumulate(&loss);
+        assert_eq!(
+            cg.step(0.5),
+            Err("No gradient for parameters unused".to_owned())
+        );
+        assert_eq!(cg.primal(&a), 0.0);
+
+        // Unlike under Zero, the unused parameter is left without gradient.
+        cg.set_unused_params(UnusedParams::Skip);
+        cg.zero_grad();
+        cg.reset_primal_of_variable(&x, 1.0);
+        cg.accumulate(&loss);
+        assert_eq!(cg.adjoin(&unused), None);
+        cg.step(0.5).unwrap();
+        assert_eq!(cg.primal(&a), -0.5);
+        assert_eq!(cg.primal(&unused), 5.0);
+    }
+
     fn new_eb() -> ExprBuilder<f32, FloatOperAry1, FloatOperAry2> {
         ExprBuilder::<f32, FloatOperAry1, FloatOperAry2>::new()
     }
@@ -2,7 +2,7 @@
 //! and build a computation graph out of those expressions. The core syntax is generic and does not impose
 //! type of variables underlying computation (like f32 vs f64) or what operations are actually implemented (like addition, or logarithm).
 use std::cell::RefCell;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::{self, Display};
 use std::ops;
 
@@ -16,6 +16,15 @@ pub trait ComputValue<V = f32>:
     + ops::Mul<V, Output = Self>
     + ops::Add<Self, Output = Self>
 {
+    /// Number of scalar elements held by the value, used for profiling.
+    fn n_elements(&self) -> usize {
+        1
+    }
+
+    /// False if any element is NaN or infinite, used for anomaly detection.
+    fn is_finite(&self) -> bool {
+        true
+    }
 }
 
 /// Returns an initial adjoin for a type (a "1").
@@ -124,6 +133,14 @@ where
         let ident = self.eb.register_node(node);
         Expr { ident, eb: self.eb }
     }
+
+    /// Mark the expression as a checkpoint boundary. When a graph has checkpoints, the primals of the nodes
+    /// between the checkpoints are dropped after forward, and recomputed from the nearest checkpoints during
+    /// backward. This trades compute for memory.
+    pub fn checkpoint(&self) -> Expr<'a, F, OP1, OP2> {
+        self.eb.checkpoints.borrow_mut().insert(self.ident);
+        *self
+    }
 }
 
 impl<'a, F, OP1, OP2> Expr<'a, F, OP1, OP2>
@@ -175,6 +192,150 @@ where
     }
 }
 
+/// A value that can be used as a constant in an expression, like `2.0.as_const(&eb)`. Any value that converts
+/// into the computed value is a constant, so `f32` works for both the float and the matrix expressions.
+pub trait AsConst<F, OP1, OP2>
+where
+    F: ComputValue,
+    OP1: Operator,
+    OP2: Operator,
+{
+    /// Produce a constant out of the value.
+    #[allow(clippy::wrong_self_convention)]
+    fn as_const(self, eb: &ExprBuilder<F, OP1, OP2>) -> Expr<'_, F, OP1, OP2>;
+}
+
+impl<T, F, OP1, OP2> AsConst<F, OP1, OP2> for T
+where
+    T: Into<F>,
+    F: ComputValue,
+    OP1: Operator,
+    OP2: Operator,
+{
+    fn as_const(self, eb: &ExprBuilder<F, OP1, OP2>) -> Expr<'_, F, OP1, OP2> {
+        eb.register_node_get_expr(ExprNode::Const(self.into()))
+    }
+}
+
+/// Implement the arithmetic operators for the expressions of a backend: `-a`, and `a op b`, `a op= b` for the
+/// listed binary operators, where `a` and `b` are expressions, references to expressions or `f32` constants.
+/// The constants are registered as [ExprNode::Const] nodes.
+macro_rules! impl_expr_ops {
+    ($value:ty, $op1:ty, $op2:ty, neg: $neg:expr,
+        $($trait:ident::$method:ident, $assign_trait:ident::$assign_method:ident => $oper:expr;)*) => {
+        impl<'a> std::ops::Neg for $crate::core_syntax::Expr<'a, $value, $op1, $op2> {
+            type Output = Self;
+
+            fn neg(self) -> Self::Output {
+                let node = $crate::core_syntax::ExprNode::Ary1($neg, self.ident);
+                self.register_and_continue_expr(node)
+            }
+        }
+
+        impl<'a> std::ops::Neg for &$crate::core_syntax::Expr<'a, $value, $op1, $op2> {
+            type Output = $crate::core_syntax::Expr<'a, $value, $op1, $op2>;
+
+            fn neg(self) -> Self::Output {
+                -*self
+            }
+        }
+
+        $(
+        impl<'a> std::ops::$trait for $crate::core_syntax::Expr<'a, $value, $op1, $op2> {
+            type Output = Self;
+
+            fn $method(self, rhs: Self) -> Self::Output {
+                let node = $crate::core_syntax::ExprNode::Ary2($oper, self.ident, rhs.ident);
+                self.register_and_continue_expr(node)
+            }
+        }
+
+        impl<'a> std::ops::$trait<&$crate::core_syntax::Expr<'a, $value, $op1, $op2>>
+            for $crate::core_syntax::Expr<'a, $value, $op1, $op2>
+        {
+            type Output = Self;
+
+            fn $method(self, rhs: &Self) -> Self::Output {
+                std::ops::$trait::$method(self, *rhs)
+            }
+        }
+
+        impl<'a> std::ops::$trait<$crate::core_syntax::Expr<'a, $value, $op1, $op2>>
+            for &$crate::core_syntax::Expr<'a, $value, $op1, $op2>
+        {
+            type Output = $crate::core_syntax::Expr<'a, $value, $op1, $op2>;
+
+            fn $method(self, rhs: $crate::core_syntax::Expr<'a, $value, $op1, $op2>) -> Self::Output {
+                std::ops::$trait::$method(*self, rhs)
+            }
+        }
+
+        impl<'a> std::ops::$trait for &$crate::core_syntax::Expr<'a, $value, $op1, $op2> {
+            type Output = $crate::core_syntax::Expr<'a, $value, $op1, $op2>;
+
+            fn $method(self, rhs: Self) -> Self::Output {
+                std::ops::$trait::$method(*self, *rhs)
+            }
+        }
+
+        impl<'a> std::ops::$trait<f32> for $crate::core_syntax::Expr<'a, $value, $op1, $op2> {
+            type Output = Self;
+
+            fn $method(self, rhs: f32) -> Self::Output {
+                let rhs = $crate::core_syntax::AsConst::as_const(rhs, self.eb);
+                std::ops::$trait::$method(self, rhs)
+            }
+        }
+
+        impl<'a> std::ops::$trait<f32> for &$crate::core_syntax::Expr<'a, $value, $op1, $op2> {
+            type Output = $crate::core_syntax::Expr<'a, $value, $op1, $op2>;
+
+            fn $method(self, rhs: f32) -> Self::Output {
+                std::ops::$trait::$method(*self, rhs)
+            }
+        }
+
+        impl<'a> std::ops::$trait<$crate::core_syntax::Expr<'a, $value, $op1, $op2>> for f32 {
+            type Output = $crate::core_syntax::Expr<'a, $value, $op1, $op2>;
+
+            fn $method(self, rhs: $crate::core_syntax::Expr<'a, $value, $op1, $op2>) -> Self::Output {
+                let lhs = $crate::core_syntax::AsConst::as_const(self, rhs.eb);
+                std::ops::$trait::$method(lhs, rhs)
+            }
+        }
+
+        impl<'a> std::ops::$trait<&$crate::core_syntax::Expr<'a, $value, $op1, $op2>> for f32 {
+            type Output = $crate::core_syntax::Expr<'a, $value, $op1, $op2>;
+
+            fn $method(self, rhs: &$crate::core_syntax::Expr<'a, $value, $op1, $op2>) -> Self::Output {
+                std::ops::$trait::$method(self, *rhs)
+            }
+        }
+
+        impl<'a> std::ops::$assign_trait for $crate::core_syntax::Expr<'a, $value, $op1, $op2> {
+            fn $assign_method(&mut self, rhs: Self) {
+                *self = std::ops::$trait::$method(*self, rhs);
+            }
+        }
+
+        impl<'a> std::ops::$assign_trait<&$crate::core_syntax::Expr<'a, $value, $op1, $op2>>
+            for $crate::core_syntax::Expr<'a, $value, $op1, $op2>
+        {
+            fn $assign_method(&mut self, rhs: &Self) {
+                *self = std::ops::$trait::$method(*self, *rhs);
+            }
+        }
+
+        impl<'a> std::ops::$assign_trait<f32> for $crate::core_syntax::Expr<'a, $value, $op1, $op2> {
+            fn $assign_method(&mut self, rhs: f32) {
+                *self = std::ops::$trait::$method(*self, rhs);
+            }
+        }
+        )*
+    };
+}
+pub(crate) use impl_expr_ops;
+
 /// Expression builder holds state of the syntax tree, at the time when the user builds the
 /// expressions. For example, `y=a*x+b` will hold y, a, x and b and relations between them.
 /// Expression builder does not know how to calculate any of those, it just manages the
@@ -193,6 +354,8 @@ where
     pub(super) id_to_node: RefCell<BTreeMap<Ident, ExprNode<F, OP1, OP2>>>,
     id_to_name: RefCell<BTreeMap<NameId, String>>,
     name_set: RefCell<HashSet<String>>,
+    /// Nodes marked with [Expr::checkpoint].
+    pub(super) checkpoints: RefCell<BTreeSet<Ident>>,
 }
 
 impl<'a, F, OP1, OP2> ExprBuilder<F, OP1, OP2>
@@ -206,6 +369,7 @@ where
             id_to_node: RefCell::new(BTreeMap::new()),
             id_to_name: RefCell::new(BTreeMap::new()),
             name_set: RefCell::new(HashSet::new()),
+            checkpoints: RefCell::new(BTreeSet::new()),
         }
     }
 
@@ -243,6 +407,18 @@ where
         Expr { ident, eb: self }
     }
 
+    /// Expression of an already registered node, e.g. to build new expressions on top of a node of
+    /// a [ComputGraph](crate::compute::ComputGraph) with [ComputGraph::extend](crate::compute::ComputGraph::extend).
+    pub fn expr(&'a self, ident: &dyn AsRef<Ident>) -> Expr<'a, F, OP1, OP2> {
+        let ident = *ident.as_ref();
+        assert!(
+            self.id_to_node.borrow().contains_key(&ident),
+            "No node for ident {}",
+            ident
+        );
+        Expr { ident, eb: self }
+    }
+
     pub fn get_name(&self, name_id: &NameId) -> Option<String> {
         let id_to_name = self.id_to_name.borrow();
         id_to_name.get(name_id).map(|s| s.to_owned())
@@ -1,150 +1,172 @@
-use crate::{
-    compute::{Calculator, ComputGraph, Node},
-    core_syntax::Ident,
-};
+use crate::compute::OperCalculator;
 
 use super::syntax::FloatOperAry1;
 use super::syntax::FloatOperAry2;
 
 pub struct FloatCalculator;
 
-impl Calculator<FloatOperAry1, FloatOperAry2, f32> for FloatCalculator {
-    fn forward(&self, cg: &ComputGraph<f32, FloatOperAry1, FloatOperAry2>, ident: &Ident) -> f32 {
-        let node = cg.get_node(ident);
-        match node {
-            Node::Const(value) => value,
-            // Variable and Parameter should have been already returned by ComputGraph.
-            Node::Variable { name, .. } => panic!("Parameter not set in .forward(): {}", name),
-            Node::Parameter { name, .. } => panic!("Parameter not set in .forward(): {:?}", name),
-            Node::Ary1 {
-                oper: op, arg1: a, ..
-            } => match op {
-                FloatOperAry1::Cos => {
-                    let a = cg.forward(&a);
-                    a.cos()
+impl OperCalculator<FloatOperAry1, FloatOperAry2, f32> for FloatCalculator {
+    fn forward_ary1(&self, oper: &FloatOperAry1, a: &f32) -> f32 {
+        let a = *a;
+        match oper {
+            FloatOperAry1::Cos => a.cos(),
+            FloatOperAry1::Sin => a.sin(),
+            FloatOperAry1::Ln => a.ln(),
+            FloatOperAry1::PowI(b) => a.powi(*b),
+            FloatOperAry1::Relu => {
+                if a <= 0.0 {
+                    0.0
+                } else {
+                    a
                 }
-                FloatOperAry1::Sin => {
-                    let a = cg.forward(&a);
-                    a.sin()
-                }
-                FloatOperAry1::Ln => {
-                    let a = cg.forward(&a);
-                    a.ln()
-                }
-                FloatOperAry1::PowI(b) => {
-                    let a = cg.forward(&a);
-                    a.powi(b)
-                }
-                FloatOperAry1::Relu => {
-                    let a = cg.forward(&a);
-                    if a <= 0.0 {
-                        0.0
-                    } else {
-                        a
-                    }
-                }
-            },
-            Node::Ary2 {
-                oper: op,
-                arg1: a,
-                arg2: b,
-                ..
-            } => match op {
-                FloatOperAry2::Add => {
-                    let a = cg.forward(&a);
-                    let b = cg.forward(&b);
-                    a + b
+            }
+            FloatOperAry1::Exp => a.exp(),
+            FloatOperAry1::Tanh => a.tanh(),
+            FloatOperAry1::Sigmoid => sigmoid(a),
+            // Stable for large |a|: ln(1 + e^a) = max(a, 0) + ln(1 + e^-|a|)
+            FloatOperAry1::Softplus => a.max(0.0) + (-a.abs()).exp().ln_1p(),
+            FloatOperAry1::Sqrt => a.sqrt(),
+            FloatOperAry1::Abs => a.abs(),
+            FloatOperAry1::Neg => -a,
+            FloatOperAry1::LeakyRelu(alpha) => {
+                if a <= 0.0 {
+                    alpha * a
+                } else {
+                    a
                 }
-                FloatOperAry2::Sub => {
-                    let a = cg.forward(&a);
-                    let b = cg.forward(&b);
-                    a - b
+            }
+            FloatOperAry1::Elu => {
+                if a <= 0.0 {
+                    a.exp_m1()
+                } else {
+                    a
                 }
-                FloatOperAry2::Mul => {
-                    let a = cg.forward(&a);
-                    let b = cg.forward(&b);
-                    a * b
+            }
+            FloatOperAry1::Gelu => 0.5 * a * (1.0 + gelu_tanh_arg(a).tanh()),
+        }
+    }
+
+    fn forward_ary2(&self, oper: &FloatOperAry2, a: &f32, b: &f32) -> f32 {
+        let (a, b) = (*a, *b);
+        match oper {
+            FloatOperAry2::Add => a + b,
+            FloatOperAry2::Sub => a - b,
+            FloatOperAry2::Mul => a * b,
+            FloatOperAry2::Pow => a.powf(b),
+            FloatOperAry2::Gt => mask(a > b),
+            FloatOperAry2::Lt => mask(a < b),
+            FloatOperAry2::Where => {
+                if b != 0.0 {
+                    a
+                } else {
+                    0.0
                 }
-                FloatOperAry2::Pow => {
-                    let a = cg.forward(&a);
-                    let b = cg.forward(&b);
-                    a.powf(b)
+            }
+            FloatOperAry2::WhereNot => {
+                if b == 0.0 {
+                    a
+                } else {
+                    0.0
                 }
             }
-            .into(),
+            FloatOperAry2::Div => a / b,
+            FloatOperAry2::Min => a.min(b),
+            FloatOperAry2::Max => a.max(b),
+            FloatOperAry2::Atan2 => a.atan2(b),
+            FloatOperAry2::Loss(loss) => loss.value(a, b),
         }
     }
 
-    fn backward(
+    fn backward_ary1(&self, oper: &FloatOperAry1, a: &f32, primal: &f32, adjoin: &f32) -> f32 {
+        let (a, primal) = (*a, *primal);
+        match oper {
+            FloatOperAry1::Sin => adjoin * a.cos(),
+            FloatOperAry1::Cos => adjoin * -a.sin(),
+            FloatOperAry1::Ln => adjoin * (1.0 / a),
+            FloatOperAry1::PowI(b) => adjoin * ((*b as f32) * a.powi(b - 1)),
+            FloatOperAry1::Relu => {
+                let da: f32 = if a <= 0.0 { 0.0 } else { 1.0 };
+                adjoin * da
+            }
+            FloatOperAry1::Exp => adjoin * primal,
+            FloatOperAry1::Tanh => adjoin * (1.0 - primal * primal),
+            FloatOperAry1::Sigmoid => adjoin * (primal * (1.0 - primal)),
+            FloatOperAry1::Softplus => adjoin * sigmoid(a),
+            FloatOperAry1::Sqrt => adjoin * (0.5 / primal),
+            FloatOperAry1::Abs => adjoin * (mask(a > 0.0) - mask(a < 0.0)),
+            FloatOperAry1::Neg => -adjoin,
+            FloatOperAry1::LeakyRelu(alpha) => {
+                let da: f32 = if a <= 0.0 { *alpha } else { 1.0 };
+                adjoin * da
+            }
+            FloatOperAry1::Elu => {
+                let da: f32 = if a <= 0.0 { a.exp() } else { 1.0 };
+                adjoin * da
+            }
+            FloatOperAry1::Gelu => {
+                let t = gelu_tanh_arg(a).tanh();
+                let dt = (1.0 - t * t) * GELU_C * (1.0 + 3.0 * GELU_K * a * a);
+                adjoin * (0.5 * (1.0 + t) + 0.5 * a * dt)
+            }
+        }
+    }
+
+    fn backward_ary2(
         &self,
-        cg: &ComputGraph<f32, FloatOperAry1, FloatOperAry2>,
-        ident: &Ident,
+        oper: &FloatOperAry2,
+        a: &f32,
+        b: &f32,
+        _primal: &f32,
         adjoin: &f32,
-    ) {
-        cg.add_adjoin(ident, adjoin);
-        let node = cg.get_node(ident);
-        match node {
-            Node::Const(_) => (),
-            Node::Variable { .. } => (),
-            Node::Parameter { .. } => (),
-            Node::Ary1 {
-                oper: op, arg1: v1, ..
-            } => match op {
-                FloatOperAry1::Sin => {
-                    let v1_p = cg.primal(&v1);
-                    let v1_ad = v1_p.cos();
-                    self.backward(cg, &v1, &(adjoin * v1_ad));
-                }
-                FloatOperAry1::Cos => {
-                    let v1_p = cg.primal(&v1);
-                    let v1_ad = -1.0 * v1_p.sin();
-                    self.backward(cg, &v1, &(adjoin * v1_ad));
-                }
-                FloatOperAry1::Ln => {
-                    let v1_p = cg.primal(&v1);
-                    let v1_ad = 1.0 / v1_p;
-                    self.backward(cg, &v1, &(adjoin * v1_ad));
-                }
-                FloatOperAry1::PowI(b) => {
-                    let a = cg.primal(&v1);
-                    self.backward(cg, &v1, &(adjoin * ((b as f32) * a.powi(b - 1))));
-                }
-                FloatOperAry1::Relu => {
-                    let v1_p = cg.primal(&v1);
-                    let v1_ad: f32 = if v1_p <= 0.0 { 0.0 } else { 1.0 };
-                    self.backward(cg, &v1, &(adjoin * v1_ad));
-                }
-            },
-            Node::Ary2 {
-                oper: op,
-                arg1: v1,
-                arg2: v2,
-                ..
-            } => match op {
-                FloatOperAry2::Add => {
-                    self.backward(cg, &v1, adjoin);
-                    self.backward(cg, &v2, adjoin);
-                }
-                FloatOperAry2::Sub => {
-                    self.backward(cg, &v1, adjoin);
-                    self.backward(cg, &v2, &(adjoin * -1.0));
-                }
-                FloatOperAry2::Mul => {
-                    let v1_p = cg.primal(&v1);
-                    let v2_p = cg.primal(&v2);
-                    self.backward(cg, &v1, &(adjoin * v2_p));
-                    self.backward(cg, &v2, &(adjoin * v1_p));
-                }
-                FloatOperAry2::Pow => {
-                    // For y=a^b, the derivatives are:
-                    // dy/da = b*a^(b-1)
-                    // dy/db = (a^b)*ln(a)
-                    let a = cg.primal(&v1);
-                    let b = cg.primal(&v2);
-                    self.backward(cg, &v1, &(adjoin * (b * a.powf(b - 1.0))));
-                    self.backward(cg, &v2, &(adjoin * (a.powf(b) * a.ln())));
-                }
-            },
+    ) -> (f32, f32) {
+        let (a, b) = (*a, *b);
+        match oper {
+            FloatOperAry2::Add => (*adjoin, *adjoin),
+            FloatOperAry2::Sub => (*adjoin, adjoin * -1.0),
+            FloatOperAry2::Mul => (adjoin * b, adjoin * a),
+            FloatOperAry2::Pow => {
+                // For y=a^b, the derivatives are:
+                // dy/da = b*a^(b-1)
+                // dy/db = (a^b)*ln(a)
+                (
+                    adjoin * (b * a.powf(b - 1.0)),
+                    adjoin * (a.powf(b) * a.ln()),
+                )
+            }
+            FloatOperAry2::Gt | FloatOperAry2::Lt => (0.0, 0.0),
+            FloatOperAry2::Where => (adjoin * mask(b != 0.0), 0.0),
+            FloatOperAry2::WhereNot => (adjoin * mask(b == 0.0), 0.0),
+            FloatOperAry2::Div => (adjoin / b, adjoin * (-a / (b * b))),
+            FloatOperAry2::Min => (adjoin * mask(a <= b), adjoin * mask(a > b)),
+            FloatOperAry2::Max => (adjoin * mask(a >= b), adjoin * mask(a < b)),
+            FloatOperAry2::Atan2 => {
+                let r2 = a * a + b * b;
+                (adjoin * (b / r2), adjoin * (-a / r2))
+            }
+            FloatOperAry2::Loss(loss) => {
+                let (da, db) = loss.derivative(a, b);
+                (adjoin * da, adjoin * db)
+            }
         }
     }
 }
+
+/// sqrt(2/pi)
+pub(crate) const GELU_C: f32 = 0.797_884_6;
+pub(crate) const GELU_K: f32 = 0.044_715;
+
+fn gelu_tanh_arg(a: f32) -> f32 {
+    GELU_C * (a + GELU_K * a * a * a)
+}
+
+fn sigmoid(a: f32) -> f32 {
+    1.0 / (1.0 + (-a).exp())
+}
+
+fn mask(cond: bool) -> f32 {
+    if cond {
+        1.0
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,528 @@
+//! Generate standalone Rust source code out of a (trained) float graph.
+//!
+//! The generated function takes the variables as `f32` arguments (in the order the variables were created) and
+//! returns the output. All the operators are inlined and the parameters are baked in as constants, so the
+//! generated code does not depend on this library.
+use std::{collections::HashSet, fmt::Write};
+
+use crate::{
+    compute::{ComputGraph, Node},
+    core_syntax::Ident,
+    losses::{Loss, EPS},
+    tape::{Instruction, SlotKind, Tape},
+};
+
+use super::{
+    calculator::{FloatCalculator, GELU_C, GELU_K},
+    syntax::{FloatOperAry1, FloatOperAry2},
+};
+
+pub enum Opts<'a> {
+    /// Name of the generated function, `model` by default.
+    FnName(&'a str),
+    /// Also generate `<name>_grad` function that returns the output and the gradient w.r.t. the arguments.
+    Gradient,
+}
+
+/// Generate Rust source code of a function computing `output`.
+pub fn to_rust(
+    cg: &ComputGraph<f32, FloatOperAry1, FloatOperAry2>,
+    output: &dyn AsRef<Ident>,
+    opts: &[Opts],
+) -> String {
+    let mut fn_name = "model";
+    let mut gradient = false;
+    for opt in opts {
+        match opt {
+            Opts::FnName(v) => fn_name = v,
+            Opts::Gradient => gradient = true,
+        }
+    }
+
+    let tape = Tape::new(cg, output, &FloatCalculator);
+    // The generated locals are named first, so the arguments are renamed on a clash with them, and not vice versa.
+    let mut used = HashSet::new();
+    let mut names: Vec<String> = (0..tape.n_slots())
+        .map(|slot| match cg.get_node(&tape.ident(slot)) {
+            Node::Variable { .. } => String::new(),
+            _ => unique_name(format!("v{}", slot), &mut used),
+        })
+        .collect();
+    for (slot, local) in names.iter_mut().enumerate() {
+        if let Node::Variable { name, .. } = cg.get_node(&tape.ident(slot)) {
+            *local = unique_name(arg_name(&name), &mut used);
+        }
+    }
+    let args: Vec<usize> = (0..tape.n_slots())
+        .filter(|slot| tape.slot_kind(*slot) == SlotKind::Variable)
+        .collect();
+    let signature = args
+        .iter()
+        .map(|slot| format!("{}: f32", names[*slot]))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let mut forward = String::new();
+    for (slot, local) in names.iter().enumerate() {
+        let ident = tape.ident(slot);
+        match cg.get_node(&ident) {
+            Node::Const(value) => {
+                writeln!(forward, "    let {}: f32 = {};", local, literal(value)).unwrap();
+            }
+            Node::Parameter { name, .. } => {
+                let value = tape
+                    .primal(&ident)
+                    .unwrap_or_else(|| panic!("Parameter {} is not set!", ident));
+                let comment = name.map(|n| format!(" // {}", n)).unwrap_or_default();
+                writeln!(
+                    forward,
+                    "    let {}: f32 = {};{}",
+                    local,
+                    literal(value),
+                    comment
+                )
+                .unwrap();
+            }
+            _ => (),
+        }
+    }
+    let mut last = None;
+    for instruction in tape.instructions() {
+        let (out, expr) = match instruction {
+            Instruction::Ary1 { oper, arg1, out } => (*out, forward_ary1(oper, &names[*arg1])),
+            Instruction::Ary2 {
+                oper,
+                arg1,
+                arg2,
+                out,
+            } => (*out, forward_ary2(oper, &names[*arg1], &names[*arg2])),
+        };
+        writeln!(forward, "    let {} = {};", names[out], expr).unwrap();
+        last = Some((out, expr));
+    }
+    let output = &names[tape.output_slot()];
+
+    let mut code = String::new();
+    writeln!(code, "// Generated by rs-autograd. Do not edit.").unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "pub fn {}({}) -> f32 {{", fn_name, signature).unwrap();
+    match last {
+        // Return the last expression directly instead of binding it first.
+        Some((out, expr)) if out == tape.output_slot() => {
+            let last_line = format!("    let {} = {};\n", output, expr);
+            code.push_str(forward.strip_suffix(&last_line).unwrap());
+            writeln!(code, "    {}", expr).unwrap();
+        }
+        _ => {
+            code.push_str(&forward);
+            writeln!(code, "    {}", output).unwrap();
+        }
+    }
+    writeln!(code, "}}").unwrap();
+
+    if gradient {
+        // The adjoins are tracked only for the slots that depend on the arguments.
+        let mut tracked = vec![false; tape.n_slots()];
+        for slot in args.iter() {
+            tracked[*slot] = true;
+        }
+        for instruction in tape.instructions() {
+            match instruction {
+                Instruction::Ary1 { arg1, out, .. } => tracked[*out] = tracked[*arg1],
+                Instruction::Ary2 {
+                    arg1, arg2, out, ..
+                } => tracked[*out] = tracked[*arg1] || tracked[*arg2],
+            }
+        }
+        let grad_names: Vec<String> = names
+            .iter()
+            .map(|name| unique_name(format!("g_{}", name), &mut used))
+            .collect();
+        let grad_name = |slot: usize| &grad_names[slot];
+
+        writeln!(code).unwrap();
+        writeln!(
+            code,
+            "pub fn {}_grad({}) -> (f32, [f32; {}]) {{",
+            fn_name,
+            signature,
+            args.len()
+        )
+        .unwrap();
+        code.push_str(&forward);
+        for (slot, _) in tracked.iter().enumerate().filter(|(_, t)| **t) {
+            if slot == tape.output_slot() {
+                writeln!(code, "    let {} = 1.0_f32;", grad_name(slot)).unwrap();
+            } else {
+                writeln!(code, "    let mut {} = 0.0_f32;", grad_name(slot)).unwrap();
+            }
+        }
+        for instruction in tape.instructions().iter().rev() {
+            match instruction {
+                Instruction::Ary1 { oper, arg1, out } => {
+                    if tracked[*arg1] {
+                        let da = backward_ary1(oper, &names[*arg1]);
+                        writeln!(
+                            code,
+                            "    {} += {} * ({});",
+                            grad_name(*arg1),
+                            grad_name(*out),
+                            da
+                        )
+                        .unwrap();
+                    }
+                }
+                Instruction::Ary2 {
+                    oper,
+                    arg1,
+                    arg2,
+                    out,
+                } => {
+                    let (da, db) = backward_ary2(oper, &names[*arg1], &names[*arg2]);
+                    for (arg, d) in [(arg1, da), (arg2, db)] {
+                        if tracked[*arg] {
+                            writeln!(
+                                code,
+                                "    {} += {} * ({});",
+                                grad_name(*arg),
+                                grad_name(*out),
+                                d
+                            )
+                            .unwrap();
+                        }
+                    }
+                }
+            }
+        }
+        let grads = args
+            .iter()
+            .map(|slot| grad_name(*slot).as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        writeln!(code, "    ({}, [{}])", output, grads).unwrap();
+        writeln!(code, "}}").unwrap();
+    }
+    code
+}
+
+/// Make a valid Rust identifier out of the variable name. Keywords get a `_` suffix.
+fn arg_name(name: &str) -> String {
+    let mut s: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if s.is_empty() || s.starts_with(|c: char| c.is_ascii_digit()) {
+        s.insert(0, '_');
+    }
+    if s == "_" || KEYWORDS.contains(&s.as_str()) {
+        s.push('_');
+    }
+    s
+}
+
+/// Rust keywords, strict and reserved, that cannot be used as a name.
+const KEYWORDS: &[&str] = &[
+    "Self", "abstract", "as", "async", "await", "become", "box", "break", "const", "continue",
+    "crate", "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if",
+    "impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// `name`, or `name_2`, `name_3`... if it's already `used`. The result is added to `used`.
+fn unique_name(name: String, used: &mut HashSet<String>) -> String {
+    let mut unique = name.clone();
+    let mut i = 1;
+    while used.contains(&unique) {
+        i += 1;
+        unique = format!("{}_{}", name, i);
+    }
+    used.insert(unique.clone());
+    unique
+}
+
+fn literal(value: f32) -> String {
+    if value.is_nan() {
+        "f32::NAN".to_owned()
+    } else if value == f32::INFINITY {
+        "f32::INFINITY".to_owned()
+    } else if value == f32::NEG_INFINITY {
+        "f32::NEG_INFINITY".to_owned()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn forward_ary1(oper: &FloatOperAry1, a: &str) -> String {
+    match oper {
+        FloatOperAry1::Cos => format!("{}.cos()", a),
+        FloatOperAry1::Sin => format!("{}.sin()", a),
+        FloatOperAry1::Ln => format!("{}.ln()", a),
+        FloatOperAry1::PowI(b) => format!("{}.powi({})", a, b),
+        FloatOperAry1::Relu => format!("if {a} <= 0.0 {{ 0.0 }} else {{ {a} }}"),
+        FloatOperAry1::Exp => format!("{}.exp()", a),
+        FloatOperAry1::Tanh => format!("{}.tanh()", a),
+        FloatOperAry1::Sigmoid => sigmoid(a),
+        FloatOperAry1::Softplus => format!("{a}.max(0.0) + (-{a}.abs()).exp().ln_1p()"),
+        FloatOperAry1::Sqrt => format!("{}.sqrt()", a),
+        FloatOperAry1::Abs => format!("{}.abs()", a),
+        FloatOperAry1::Neg => format!("-{}", a),
+        FloatOperAry1::LeakyRelu(alpha) => format!(
+            "if {a} <= 0.0 {{ {} * {a} }} else {{ {a} }}",
+            literal(*alpha)
+        ),
+        FloatOperAry1::Elu => format!("if {a} <= 0.0 {{ {a}.exp_m1() }} else {{ {a} }}"),
+        FloatOperAry1::Gelu => format!("0.5 * {a} * (1.0 + {}.tanh())", gelu_tanh_arg(a)),
+    }
+}
+
+fn forward_ary2(oper: &FloatOperAry2, a: &str, b: &str) -> String {
+    match oper {
+        FloatOperAry2::Add => format!("{} + {}", a, b),
+        FloatOperAry2::Sub => format!("{} - {}", a, b),
+        FloatOperAry2::Mul => format!("{} * {}", a, b),
+        FloatOperAry2::Pow => format!("{}.powf({})", a, b),
+        FloatOperAry2::Gt => format!("if {} > {} {{ 1.0 }} else {{ 0.0 }}", a, b),
+        FloatOperAry2::Lt => format!("if {} < {} {{ 1.0 }} else {{ 0.0 }}", a, b),
+        FloatOperAry2::Where => format!("if {} != 0.0 {{ {} }} else {{ 0.0 }}", b, a),
+        FloatOperAry2::WhereNot => format!("if {} == 0.0 {{ {} }} else {{ 0.0 }}", b, a),
+        FloatOperAry2::Div => format!("{} / {}", a, b),
+        FloatOperAry2::Min => format!("{}.min({})", a, b),
+        FloatOperAry2::Max => format!("{}.max({})", a, b),
+        FloatOperAry2::Atan2 => format!("{}.atan2({})", a, b),
+        FloatOperAry2::Loss(loss) => forward_loss(loss, a, b),
+    }
+}
+
+/// Derivative of the operator w.r.t. the argument, the same as in [FloatCalculator].
+fn backward_ary1(oper: &FloatOperAry1, a: &str) -> String {
+    match oper {
+        FloatOperAry1::Sin => format!("{}.cos()", a),
+        FloatOperAry1::Cos => format!("-{}.sin()", a),
+        FloatOperAry1::Ln => format!("1.0 / {}", a),
+        FloatOperAry1::PowI(b) => format!("{} * {}.powi({})", literal(*b as f32), a, b - 1),
+        FloatOperAry1::Relu => format!("if {} <= 0.0 {{ 0.0 }} else {{ 1.0 }}", a),
+        FloatOperAry1::Exp => format!("{}.exp()", a),
+        FloatOperAry1::Tanh => format!("1.0 - {}.tanh().powi(2)", a),
+        FloatOperAry1::Sigmoid => format!("{{ let s = {}; s * (1.0 - s) }}", sigmoid(a)),
+        FloatOperAry1::Softplus => sigmoid(a),
+        FloatOperAry1::Sqrt => format!("0.5 / {}.sqrt()", a),
+        FloatOperAry1::Abs => {
+            format!("if {a} > 0.0 {{ 1.0 }} else if {a} < 0.0 {{ -1.0 }} else {{ 0.0 }}")
+        }
+        FloatOperAry1::Neg => "-1.0".to_owned(),
+        FloatOperAry1::LeakyRelu(alpha) => {
+            format!("if {} <= 0.0 {{ {} }} else {{ 1.0 }}", a, literal(*alpha))
+        }
+        FloatOperAry1::Elu => format!("if {a} <= 0.0 {{ {a}.exp() }} else {{ 1.0 }}"),
+        FloatOperAry1::Gelu => format!(
+            "{{ let t = {}.tanh(); 0.5 * (1.0 + t) + 0.5 * {a} * (1.0 - t * t) * {} * (1.0 + {} * {a} * {a}) }}",
+            gelu_tanh_arg(a),
+            literal(GELU_C),
+            literal(3.0 * GELU_K),
+        ),
+    }
+}
+
+fn backward_ary2(oper: &FloatOperAry2, a: &str, b: &str) -> (String, String) {
+    match oper {
+        FloatOperAry2::Add => ("1.0".to_owned(), "1.0".to_owned()),
+        FloatOperAry2::Sub => ("1.0".to_owned(), "-1.0".to_owned()),
+        FloatOperAry2::Mul => (b.to_owned(), a.to_owned()),
+        FloatOperAry2::Pow => (
+            format!("{b} * {a}.powf({b} - 1.0)"),
+            format!("{a}.powf({b}) * {a}.ln()"),
+        ),
+        FloatOperAry2::Gt | FloatOperAry2::Lt => ("0.0".to_owned(), "0.0".to_owned()),
+        FloatOperAry2::Where => (
+            format!("if {} != 0.0 {{ 1.0 }} else {{ 0.0 }}", b),
+            "0.0".to_owned(),
+        ),
+        FloatOperAry2::WhereNot => (
+            format!("if {} == 0.0 {{ 1.0 }} else {{ 0.0 }}", b),
+            "0.0".to_owned(),
+        ),
+        FloatOperAry2::Div => (format!("1.0 / {b}"), format!("-{a} / ({b} * {b})")),
+        FloatOperAry2::Min => (
+            format!("if {a} <= {b} {{ 1.0 }} else {{ 0.0 }}"),
+            format!("if {a} > {b} {{ 1.0 }} else {{ 0.0 }}"),
+        ),
+        FloatOperAry2::Max => (
+            format!("if {a} >= {b} {{ 1.0 }} else {{ 0.0 }}"),
+            format!("if {a} < {b} {{ 1.0 }} else {{ 0.0 }}"),
+        ),
+        FloatOperAry2::Atan2 => (
+            format!("{b} / ({a} * {a} + {b} * {b})"),
+            format!("-{a} / ({a} * {a} + {b} * {b})"),
+        ),
+        FloatOperAry2::Loss(loss) => backward_loss(loss, a, b),
+    }
+}
+
+/// The same as [Loss::value].
+fn forward_loss(loss: &Loss, y: &str, t: &str) -> String {
+    match loss {
+        Loss::Mse => format!("({} - {}).powi(2)", y, t),
+        Loss::Mae => format!("({} - {}).abs()", y, t),
+        Loss::Huber(delta) => format!(
+            "{{ let d = {y} - {t}; if d.abs() <= {delta} {{ 0.5 * d * d }} else {{ {delta} * (d.abs() - 0.5 * {delta}) }} }}",
+            delta = literal(*delta)
+        ),
+        Loss::LogCosh => format!(
+            "{{ let d = ({} - {}).abs(); d + (-2.0 * d).exp().ln_1p() - std::f32::consts::LN_2 }}",
+            y, t
+        ),
+        Loss::BceWithLogits => format!("{y}.max(0.0) - {y} * {t} + (-{y}.abs()).exp().ln_1p()"),
+        Loss::CrossEntropy => format!("-{} * {}.max({}).ln()", t, y, literal(EPS)),
+    }
+}
+
+/// The same as [Loss::derivative].
+fn backward_loss(loss: &Loss, y: &str, t: &str) -> (String, String) {
+    let d = format!("({} - {})", y, t);
+    match loss {
+        Loss::Mse => (format!("2.0 * {}", d), format!("-2.0 * {}", d)),
+        Loss::Mae => {
+            let sign = format!(
+                "{{ let d = {d}; if d > 0.0 {{ 1.0 }} else if d < 0.0 {{ -1.0 }} else {{ 0.0 }} }}"
+            );
+            (sign.clone(), format!("-{}", sign))
+        }
+        Loss::Huber(delta) => {
+            let clamped = format!("{}.clamp(-{delta}, {delta})", d, delta = literal(*delta));
+            (clamped.clone(), format!("-{}", clamped))
+        }
+        Loss::LogCosh => (format!("{}.tanh()", d), format!("-{}.tanh()", d)),
+        Loss::BceWithLogits => (format!("{} - {}", sigmoid(y), t), format!("-{}", y)),
+        Loss::CrossEntropy => (
+            format!(
+                "if {y} < {eps} {{ 0.0 }} else {{ -{} / {y} }}",
+                t,
+                y = y,
+                eps = literal(EPS)
+            ),
+            format!("-{}.max({}).ln()", y, literal(EPS)),
+        ),
+    }
+}
+
+fn sigmoid(a: &str) -> String {
+    format!("1.0 / (1.0 + (-{}).exp())", a)
+}
+
+fn gelu_tanh_arg(a: &str) -> String {
+    format!(
+        "({} * ({a} + {} * {a}.powi(3)))",
+        literal(GELU_C),
+        literal(GELU_K)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{arg_name, literal, to_rust, Opts};
+    use crate::{
+        compute::ComputGraph,
+        core_syntax::ExprBuilder,
+        float::{
+            calculator::FloatCalculator,
+            syntax::{FloatOperAry1, FloatOperAry2},
+        },
+    };
+
+    #[test]
+    fn names_and_literals() {
+        assert_eq!("x_1", arg_name("x 1"));
+        assert_eq!("_1x", arg_name("1x"));
+        assert_eq!("v_1", arg_name("v_1"));
+        assert_eq!("fn_", arg_name("fn"));
+        assert_eq!("__", arg_name(""));
+        assert_eq!("-2.5", literal(-2.5));
+        assert_eq!("1e-7", literal(1e-7));
+        assert_eq!("f32::NEG_INFINITY", literal(f32::NEG_INFINITY));
+    }
+
+    #[test]
+    fn generate() {
+        let eb = ExprBuilder::new();
+        let x = eb.new_variable("x");
+        let p = eb.new_named_parameter("p", 0.5);
+        let y = ((x * p).sin() + x).ident;
+        let cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+        let code = to_rust(&cg, &y, &[Opts::FnName("f"), Opts::Gradient]);
+        let expected = "// Generated by rs-autograd. Do not edit.
+
+pub fn f(x: f32) -> f32 {
+    let v1: f32 = 0.5; // p
+    let v2 = x * v1;
+    let v3 = v2.sin();
+    v3 + x
+}
+
+pub fn f_grad(x: f32) -> (f32, [f32; 1]) {
+    let v1: f32 = 0.5; // p
+    let v2 = x * v1;
+    let v3 = v2.sin();
+    let v4 = v3 + x;
+    let mut g_x = 0.0_f32;
+    let mut g_v2 = 0.0_f32;
+    let mut g_v3 = 0.0_f32;
+    let g_v4 = 1.0_f32;
+    g_v3 += g_v4 * (1.0);
+    g_x += g_v4 * (1.0);
+    g_v2 += g_v3 * (v2.cos());
+    g_x += g_v2 * (v1);
+    (v4, [g_x])
+}
+";
+        assert_eq!(expected, code);
+    }
+
+    #[test]
+    fn clashing_names() {
+        let eb = ExprBuilder::<f32, FloatOperAry1, FloatOperAry2>::new();
+        let a = eb.new_variable("x 1");
+        let b = eb.new_variable("x_1");
+        let c = eb.new_variable("fn");
+        let d = eb.new_variable("g_v5");
+        let e = eb.new_variable("v5");
+        let y = (a * b + c * d + e).ident;
+        let cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+        let code = to_rust(&cg, &y, &[Opts::FnName("f"), Opts::Gradient]);
+        let expected = "// Generated by rs-autograd. Do not edit.
+
+pub fn f(x_1: f32, x_1_2: f32, fn_: f32, g_v5: f32, v5_2: f32) -> f32 {
+    let v5 = x_1 * x_1_2;
+    let v6 = fn_ * g_v5;
+    let v7 = v5 + v6;
+    v7 + v5_2
+}
+
+pub fn f_grad(x_1: f32, x_1_2: f32, fn_: f32, g_v5: f32, v5_2: f32) -> (f32, [f32; 5]) {
+    let v5 = x_1 * x_1_2;
+    let v6 = fn_ * g_v5;
+    let v7 = v5 + v6;
+    let v8 = v7 + v5_2;
+    let mut g_x_1 = 0.0_f32;
+    let mut g_x_1_2 = 0.0_f32;
+    let mut g_fn_ = 0.0_f32;
+    let mut g_g_v5 = 0.0_f32;
+    let mut g_v5_2 = 0.0_f32;
+    let mut g_v5_3 = 0.0_f32;
+    let mut g_v6 = 0.0_f32;
+    let mut g_v7 = 0.0_f32;
+    let g_v8 = 1.0_f32;
+    g_v7 += g_v8 * (1.0);
+    g_v5_2 += g_v8 * (1.0);
+    g_v5_3 += g_v7 * (1.0);
+    g_v6 += g_v7 * (1.0);
+    g_fn_ += g_v6 * (g_v5);
+    g_g_v5 += g_v6 * (fn_);
+    g_x_1 += g_v5_3 * (x_1_2);
+    g_x_1_2 += g_v5_3 * (x_1);
+    (v8, [g_x_1, g_x_1_2, g_fn_, g_g_v5, g_v5_2])
+}
+";
+        assert_eq!(expected, code);
+    }
+}
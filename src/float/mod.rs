@@ -1,3 +1,5 @@
 //! An exemplary implementation for float type
 pub mod calculator;
+pub mod codegen;
+pub mod solver;
 pub mod syntax;
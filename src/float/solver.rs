@@ -0,0 +1,465 @@
+//! Find roots and stationary points of float expressions, using the exact derivatives from
+//! [ComputGraph::backward].
+//!
+//! The unknowns are variables (or parameters) of the graph, all the other variables must be set before solving.
+//! When the solver returns, the unknowns are set to the solution.
+//!
+//! - [newton] finds a root of `f(x)`, with a fallback to bisection when a bracket is given.
+//! - [stationary] finds a point where `f'(x) = 0`.
+//! - [newton_system] solves a system of equations `F(x) = 0`, with the Jacobian built out of one backward pass
+//!   per equation.
+use crate::{
+    compute::{ComputGraph, Node},
+    core_syntax::Ident,
+};
+
+use super::syntax::{FloatOperAry1, FloatOperAry2};
+
+type FloatGraph<'a> = ComputGraph<'a, f32, FloatOperAry1, FloatOperAry2>;
+
+pub enum Opts {
+    /// The solver stops when the residual or the step (relative to the unknown) is below the tolerance.
+    /// `1e-6` by default.
+    Tolerance(f32),
+    /// Maximum number of iterations, 50 by default.
+    MaxIter(usize),
+    /// An interval containing the solution, the function must have different signs at the ends. A Newton step
+    /// that leaves the bracket is replaced by bisection. Ignored by [newton_system].
+    Bracket(f32, f32),
+}
+
+/// Why the solver stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Converged,
+    MaxIter,
+    /// The derivative is zero and there is no bracket to fall back to.
+    ZeroDerivative,
+    /// The Jacobian of the system cannot be inverted.
+    SingularJacobian,
+    /// The function is NaN or infinite at the current point.
+    NotFinite,
+    /// The function has the same sign at both ends of the bracket.
+    InvalidBracket,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    pub stop: StopReason,
+    pub iterations: usize,
+    /// `|f(x)|` at the solution, or the norm of `F(x)` for a system.
+    pub residual: f32,
+    /// Length of the last step.
+    pub step: f32,
+    /// Number of iterations that fell back to bisection.
+    pub bisections: usize,
+    /// The residual at the start of each iteration.
+    pub history: Vec<f32>,
+}
+
+impl Diagnostics {
+    pub fn converged(&self) -> bool {
+        self.stop == StopReason::Converged
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Solution<T> {
+    pub x: T,
+    pub diagnostics: Diagnostics,
+}
+
+/// Find `x` such that `f(x) = 0`, starting at `x0`.
+pub fn newton(
+    cg: &mut FloatGraph,
+    f: &dyn AsRef<Ident>,
+    x: &dyn AsRef<Ident>,
+    x0: f32,
+    opts: &[Opts],
+) -> Solution<f32> {
+    let (f, x) = (*f.as_ref(), *x.as_ref());
+    solve_scalar(|v| value_and_derivative(cg, &f, &x, v), x0, opts)
+}
+
+/// Find `x` such that `f'(x) = 0`, starting at `x0`. The second derivative is a central difference of the exact
+/// first derivatives. The point can be a minimum, maximum or a saddle, check the sign of the second derivative
+/// if it matters.
+pub fn stationary(
+    cg: &mut FloatGraph,
+    f: &dyn AsRef<Ident>,
+    x: &dyn AsRef<Ident>,
+    x0: f32,
+    opts: &[Opts],
+) -> Solution<f32> {
+    let (f, x) = (*f.as_ref(), *x.as_ref());
+    solve_scalar(
+        |v| {
+            let h = 1e-3 * (1.0 + v.abs());
+            let (_, d) = value_and_derivative(cg, &f, &x, v);
+            let (_, d_plus) = value_and_derivative(cg, &f, &x, v + h);
+            let (_, d_minus) = value_and_derivative(cg, &f, &x, v - h);
+            // Leave the graph at `v`.
+            value_and_derivative(cg, &f, &x, v);
+            (d, (d_plus - d_minus) / (2.0 * h))
+        },
+        x0,
+        opts,
+    )
+}
+
+/// Solve the system `equations(unknowns) = 0`, starting at `x0`. There must be as many equations as unknowns.
+/// A step that increases the residual is halved, down to 1/1000 of the Newton step.
+pub fn newton_system(
+    cg: &mut FloatGraph,
+    equations: &[Ident],
+    unknowns: &[Ident],
+    x0: &[f32],
+    opts: &[Opts],
+) -> Solution<Vec<f32>> {
+    assert_eq!(
+        equations.len(),
+        unknowns.len(),
+        "Expected as many equations as unknowns"
+    );
+    assert_eq!(
+        unknowns.len(),
+        x0.len(),
+        "Expected a start value per unknown"
+    );
+    let (tolerance, max_iter, _) = parse_opts(opts);
+
+    let mut x = x0.to_vec();
+    let mut values = evaluate_system(cg, equations, unknowns, &x);
+    let mut diagnostics = Diagnostics::new(norm(&values));
+    while diagnostics.stop == StopReason::MaxIter && diagnostics.iterations < max_iter {
+        diagnostics.history.push(diagnostics.residual);
+        if !diagnostics.residual.is_finite() {
+            diagnostics.stop = StopReason::NotFinite;
+            break;
+        }
+        if diagnostics.residual <= tolerance {
+            diagnostics.stop = StopReason::Converged;
+            break;
+        }
+        diagnostics.iterations += 1;
+
+        let jacobian: Vec<Vec<f32>> = equations
+            .iter()
+            .map(|eq| {
+                cg.zero_grad();
+                cg.backward(eq);
+                unknowns
+                    .iter()
+                    .map(|u| cg.adjoin(u).unwrap_or(0.0))
+                    .collect()
+            })
+            .collect();
+        let rhs: Vec<f32> = values.iter().map(|v| -v).collect();
+        let Some(dx) = solve_linear(jacobian, rhs) else {
+            diagnostics.stop = StopReason::SingularJacobian;
+            break;
+        };
+
+        let mut t = 1.0;
+        let (mut x_new, mut values_new): (Vec<f32>, Vec<f32>);
+        loop {
+            x_new = x.iter().zip(dx.iter()).map(|(x, d)| x + t * d).collect();
+            values_new = evaluate_system(cg, equations, unknowns, &x_new);
+            if norm(&values_new) <= diagnostics.residual || t < 1e-3 {
+                break;
+            }
+            t *= 0.5;
+        }
+        diagnostics.step = t * norm(&dx);
+        diagnostics.residual = norm(&values_new);
+        (x, values) = (x_new, values_new);
+        if diagnostics.residual <= tolerance || diagnostics.step <= tolerance * (1.0 + norm(&x)) {
+            diagnostics.stop = StopReason::Converged;
+        }
+    }
+    Solution { x, diagnostics }
+}
+
+impl Diagnostics {
+    fn new(residual: f32) -> Diagnostics {
+        Diagnostics {
+            stop: StopReason::MaxIter,
+            iterations: 0,
+            residual,
+            step: 0.0,
+            bisections: 0,
+            history: Vec::new(),
+        }
+    }
+}
+
+fn parse_opts(opts: &[Opts]) -> (f32, usize, Option<(f32, f32)>) {
+    let mut tolerance = 1e-6;
+    let mut max_iter = 50;
+    let mut bracket = None;
+    for opt in opts {
+        match opt {
+            Opts::Tolerance(v) => tolerance = *v,
+            Opts::MaxIter(v) => max_iter = *v,
+            Opts::Bracket(a, b) => bracket = Some((*a, *b)),
+        }
+    }
+    (tolerance, max_iter, bracket)
+}
+
+/// Safeguarded Newton iteration on `eval(x) = (g(x), g'(x))`.
+fn solve_scalar(mut eval: impl FnMut(f32) -> (f32, f32), x0: f32, opts: &[Opts]) -> Solution<f32> {
+    let (tolerance, max_iter, bracket) = parse_opts(opts);
+    let mut x = x0;
+    // The ends of the bracket, and the value of `g` at `lo`.
+    let mut bracket = match bracket {
+        Some((lo, hi)) => {
+            let (g_lo, _) = eval(lo);
+            let (g_hi, _) = eval(hi);
+            if g_lo * g_hi > 0.0 || !(g_lo * g_hi).is_finite() {
+                let (g, _) = eval(x0);
+                let mut diagnostics = Diagnostics::new(g.abs());
+                diagnostics.stop = StopReason::InvalidBracket;
+                return Solution { x, diagnostics };
+            }
+            if !(lo.min(hi)..=lo.max(hi)).contains(&x) {
+                x = 0.5 * (lo + hi);
+            }
+            Some((lo, hi, g_lo))
+        }
+        None => None,
+    };
+
+    let (mut g, mut dg) = eval(x);
+    let mut diagnostics = Diagnostics::new(g.abs());
+    while diagnostics.stop == StopReason::MaxIter && diagnostics.iterations < max_iter {
+        diagnostics.history.push(diagnostics.residual);
+        if !g.is_finite() {
+            diagnostics.stop = StopReason::NotFinite;
+            break;
+        }
+        if g.abs() <= tolerance {
+            diagnostics.stop = StopReason::Converged;
+            break;
+        }
+        diagnostics.iterations += 1;
+
+        let mut x_new = x - g / dg;
+        if let Some((lo, hi, g_lo)) = bracket.as_mut() {
+            // Keep the sign change inside the bracket.
+            if g.signum() == g_lo.signum() {
+                (*lo, *g_lo) = (x, g);
+            } else {
+                *hi = x;
+            }
+            let inside = x_new > lo.min(*hi) && x_new < lo.max(*hi);
+            if dg == 0.0 || !x_new.is_finite() || !inside {
+                x_new = 0.5 * (*lo + *hi);
+                diagnostics.bisections += 1;
+            }
+        } else if dg == 0.0 || !x_new.is_finite() {
+            diagnostics.stop = StopReason::ZeroDerivative;
+            break;
+        }
+
+        diagnostics.step = (x_new - x).abs();
+        x = x_new;
+        (g, dg) = eval(x);
+        diagnostics.residual = g.abs();
+        // Checked after the update too, so the last allowed iteration can still converge.
+        if diagnostics.residual <= tolerance || diagnostics.step <= tolerance * (1.0 + x.abs()) {
+            diagnostics.stop = StopReason::Converged;
+        }
+    }
+    Solution { x, diagnostics }
+}
+
+/// Set the unknown and return the value of `f` and its derivative w.r.t. the unknown.
+fn value_and_derivative(cg: &mut FloatGraph, f: &Ident, x: &Ident, value: f32) -> (f32, f32) {
+    set_unknown(cg, x, value);
+    cg.zero_grad();
+    let y = cg.forward(f);
+    cg.backward(f);
+    (y, cg.adjoin(x).unwrap_or(0.0))
+}
+
+fn evaluate_system(
+    cg: &mut FloatGraph,
+    equations: &[Ident],
+    unknowns: &[Ident],
+    x: &[f32],
+) -> Vec<f32> {
+    for (u, v) in unknowns.iter().zip(x.iter()) {
+        set_unknown(cg, u, *v);
+    }
+    equations.iter().map(|eq| cg.forward(eq)).collect()
+}
+
+fn set_unknown(cg: &mut FloatGraph, ident: &Ident, value: f32) {
+    match cg.get_node(ident) {
+        Node::Variable { .. } => {
+            cg.reset_primal_of_variable(ident, value);
+        }
+        Node::Parameter { .. } => {
+            cg.reset_primal_of_parameter(ident, value);
+        }
+        node => panic!(
+            "Unknown {} must be a variable or a parameter, got {:?}",
+            ident, node
+        ),
+    }
+    cg.reset_computed_primals();
+}
+
+fn norm(v: &[f32]) -> f32 {
+    v.iter().map(|v| v * v).sum::<f32>().sqrt()
+}
+
+/// Solve `a x = b` with Gaussian elimination and partial pivoting. Return None if `a` is singular.
+fn solve_linear(mut a: Vec<Vec<f32>>, mut b: Vec<f32>) -> Option<Vec<f32>> {
+    let n = b.len();
+    let scale = a
+        .iter()
+        .flatten()
+        .fold(0.0_f32, |m, v| m.max(v.abs()))
+        .max(f32::MIN_POSITIVE);
+    for col in 0..n {
+        let pivot = (col..n).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col].abs() <= 1e-6 * scale {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..n {
+            let factor = a[row][col] / a[col][col];
+            let (top, bottom) = a.split_at_mut(row);
+            for (v, p) in bottom[0][col..].iter_mut().zip(&top[col][col..]) {
+                *v -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let s: f32 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - s) / a[row][row];
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{newton, newton_system, solve_linear, stationary, Opts, StopReason};
+    use crate::{
+        compute::ComputGraph,
+        core_syntax::ExprBuilder,
+        float::{
+            calculator::FloatCalculator,
+            syntax::{FloatOperAry1, FloatOperAry2},
+        },
+    };
+
+    #[test]
+    fn linear() {
+        let x = solve_linear(vec![vec![0.0, 2.0], vec![1.0, 1.0]], vec![4.0, 3.0]).unwrap();
+        assert_eq!(vec![1.0, 2.0], x);
+        assert_eq!(
+            None,
+            solve_linear(vec![vec![1.0, 2.0], vec![2.0, 4.0]], vec![1.0, 1.0])
+        );
+    }
+
+    #[test]
+    fn root() {
+        // Implicit equation x = cos(x), with a parameter set beforehand.
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let a = eb.new_variable("a");
+        let f = x - (a * x).cos();
+        let [x, a, f] = [x, a, f].map(|e| e.ident);
+        let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+        cg.set_variable(&a, 1.0);
+
+        let solution = newton(&mut cg, &f, &x, 0.0, &[]);
+        assert!(solution.diagnostics.converged(), "{:?}", solution);
+        assert!((solution.x - 0.739_085_1).abs() < 1e-5);
+        assert!(solution.diagnostics.iterations < 10);
+        assert_eq!(solution.x, cg.primal(&x));
+    }
+
+    #[test]
+    fn converged_on_last_iteration() {
+        // A single Newton step solves the linear equation exactly.
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let f = x * 2.0 - 3.0;
+        let [x, f] = [x, f].map(|e| e.ident);
+        let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+
+        let solution = newton(&mut cg, &f, &x, 0.0, &[Opts::MaxIter(1)]);
+        assert_eq!(StopReason::Converged, solution.diagnostics.stop);
+        assert_eq!(1, solution.diagnostics.iterations);
+        assert_eq!(1.5, solution.x);
+    }
+
+    #[test]
+    fn bracket_fallback() {
+        // Newton alone diverges from x0=2 on atan(x), bisection keeps it in the bracket.
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let f = x.atan2(eb.new_parameter(1.0));
+        let [x, f] = [x, f].map(|e| e.ident);
+        let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+
+        let diverged = newton(&mut cg, &f, &x, 2.0, &[Opts::MaxIter(5)]);
+        assert!(!diverged.diagnostics.converged());
+
+        let solution = newton(&mut cg, &f, &x, 2.0, &[Opts::Bracket(-1.0, 3.0)]);
+        assert!(solution.diagnostics.converged(), "{:?}", solution);
+        assert!(solution.x.abs() < 1e-5);
+        assert!(solution.diagnostics.bisections > 0);
+
+        let invalid = newton(&mut cg, &f, &x, 2.0, &[Opts::Bracket(1.0, 3.0)]);
+        assert_eq!(StopReason::InvalidBracket, invalid.diagnostics.stop);
+    }
+
+    #[test]
+    fn minimum() {
+        // (x - 1.5)^2 + sin(x) has a minimum near 1.2.
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let f = (x - 1.5).powi(2) + x.sin();
+        let [x, f] = [x, f].map(|e| e.ident);
+        let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+
+        let solution = stationary(&mut cg, &f, &x, 0.0, &[Opts::Tolerance(1e-5)]);
+        assert!(solution.diagnostics.converged(), "{:?}", solution);
+        let x = solution.x;
+        assert!((2.0 * (x - 1.5) + x.cos()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn system() {
+        // Intersection of the unit circle and the line y = x, in the first quadrant.
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let y = eb.new_variable("y");
+        let circle = x.powi(2) + y.powi(2) - 1.0;
+        let line = y - x;
+        let [x, y, circle, line] = [x, y, circle, line].map(|e| e.ident);
+        let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+
+        let solution = newton_system(&mut cg, &[circle, line], &[x, y], &[1.0, 0.0], &[]);
+        assert!(solution.diagnostics.converged(), "{:?}", solution);
+        let expected = 0.5_f32.sqrt();
+        assert!((solution.x[0] - expected).abs() < 1e-5);
+        assert!((solution.x[1] - expected).abs() < 1e-5);
+
+        let singular = newton_system(&mut cg, &[circle, line], &[x, y], &[0.0, 0.0], &[]);
+        assert_eq!(StopReason::SingularJacobian, singular.diagnostics.stop);
+    }
+
+    fn new_eb() -> ExprBuilder<f32, FloatOperAry1, FloatOperAry2> {
+        ExprBuilder::new()
+    }
+}
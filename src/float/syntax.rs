@@ -1,9 +1,13 @@
 //! Implement concrete autograd operations for f32 type.
 
 use std::fmt;
-use std::ops;
 
-use crate::core_syntax::{ComputValue, DefaultAdjoin, Expr, ExprBuilder, ExprNode, Operator};
+pub use crate::core_syntax::AsConst;
+use crate::core_syntax::{impl_expr_ops, ComputValue, DefaultAdjoin, Expr, ExprNode, Operator};
+use crate::losses::Loss;
+use crate::render::{
+    Notation, RenderOperator, PRECEDENCE_ADD, PRECEDENCE_CMP, PRECEDENCE_MUL, PRECEDENCE_SELECT,
+};
 
 #[derive(Copy, Clone, Debug)]
 pub enum FloatOperAry1 {
@@ -13,6 +17,21 @@ pub enum FloatOperAry1 {
     /// Power to constant integer value.
     PowI(i32),
     Relu,
+    Exp,
+    Tanh,
+    /// 1 / (1 + e^-a)
+    Sigmoid,
+    /// ln(1 + e^a), a smooth version of [FloatOperAry1::Relu].
+    Softplus,
+    Sqrt,
+    Abs,
+    Neg,
+    /// `a` for positive `a`, `alpha * a` otherwise.
+    LeakyRelu(f32),
+    /// `a` for positive `a`, `e^a - 1` otherwise.
+    Elu,
+    /// Gaussian error linear unit, in the tanh approximation.
+    Gelu,
 }
 
 // Bespoke set of Ary2 operations
@@ -23,9 +42,30 @@ pub enum FloatOperAry2 {
     Mul,
     /// Power to other expression.
     Pow,
+    /// 1 if a > b, 0 otherwise. The gradient is zero.
+    Gt,
+    /// 1 if a < b, 0 otherwise. The gradient is zero.
+    Lt,
+    /// `a` if the condition `b` is non-zero, 0 otherwise. The gradient flows only to `a`.
+    Where,
+    /// `a` if the condition `b` is zero, 0 otherwise. The gradient flows only to `a`.
+    WhereNot,
+    Div,
+    /// The smaller argument. On a tie the gradient flows to `a`.
+    Min,
+    /// The larger argument. On a tie the gradient flows to `a`.
+    Max,
+    /// Four-quadrant arctangent of `a / b`, like [f32::atan2].
+    Atan2,
+    /// Loss of the prediction `a` w.r.t. the target `b`.
+    Loss(Loss),
 }
 
-impl ComputValue for f32 {}
+impl ComputValue for f32 {
+    fn is_finite(&self) -> bool {
+        f32::is_finite(*self)
+    }
+}
 impl DefaultAdjoin for f32 {
     fn default_adjoin(_: Self) -> Self {
         1.0
@@ -41,6 +81,16 @@ impl fmt::Display for FloatOperAry1 {
             FloatOperAry1::Ln => "ln".to_owned(),
             FloatOperAry1::PowI(p) => format!("pow{}", p),
             FloatOperAry1::Relu => "relu".to_owned(),
+            FloatOperAry1::Exp => "exp".to_owned(),
+            FloatOperAry1::Tanh => "tanh".to_owned(),
+            FloatOperAry1::Sigmoid => "sigmoid".to_owned(),
+            FloatOperAry1::Softplus => "softplus".to_owned(),
+            FloatOperAry1::Sqrt => "sqrt".to_owned(),
+            FloatOperAry1::Abs => "abs".to_owned(),
+            FloatOperAry1::Neg => "neg".to_owned(),
+            FloatOperAry1::LeakyRelu(alpha) => format!("leaky_relu_{}", alpha),
+            FloatOperAry1::Elu => "elu".to_owned(),
+            FloatOperAry1::Gelu => "gelu".to_owned(),
         };
         write!(f, "{}", s)
     }
@@ -55,39 +105,123 @@ impl fmt::Display for FloatOperAry2 {
             FloatOperAry2::Mul => " * ",
             FloatOperAry2::Sub => " - ",
             FloatOperAry2::Pow => "^",
+            FloatOperAry2::Gt => " > ",
+            FloatOperAry2::Lt => " < ",
+            FloatOperAry2::Where => " if ",
+            FloatOperAry2::WhereNot => " unless ",
+            FloatOperAry2::Div => " / ",
+            FloatOperAry2::Min => " min ",
+            FloatOperAry2::Max => " max ",
+            FloatOperAry2::Atan2 => " atan2 ",
+            FloatOperAry2::Loss(loss) => return write!(f, " {} ", loss),
         };
         write!(f, "{}", s)
     }
 }
 
-type ExprFloat<'a> = Expr<'a, f32, FloatOperAry1, FloatOperAry2>;
-
-impl<'a> ops::Add for ExprFloat<'a> {
-    type Output = ExprFloat<'a>;
-
-    fn add(self, rhs: Self) -> Self::Output {
-        let node = ExprNode::Ary2(FloatOperAry2::Add, self.ident, rhs.ident);
-        self.register_and_continue_expr(node)
+impl RenderOperator for FloatOperAry1 {
+    fn notation(&self) -> Notation {
+        let (latex, mathml) = match self {
+            FloatOperAry1::Cos => ("\\cos", "cos"),
+            FloatOperAry1::Sin => ("\\sin", "sin"),
+            FloatOperAry1::Ln => ("\\ln", "ln"),
+            FloatOperAry1::PowI(p) => return Notation::PowI(*p),
+            FloatOperAry1::Relu => ("\\operatorname{relu}", "relu"),
+            FloatOperAry1::Exp => ("\\exp", "exp"),
+            FloatOperAry1::Tanh => ("\\tanh", "tanh"),
+            FloatOperAry1::Sigmoid => ("\\sigma", "&#x3C3;"),
+            FloatOperAry1::Softplus => ("\\operatorname{softplus}", "softplus"),
+            FloatOperAry1::Sqrt => return Notation::Sqrt,
+            FloatOperAry1::Abs => ("\\operatorname{abs}", "abs"),
+            FloatOperAry1::Neg => ("-", "&#x2212;"),
+            FloatOperAry1::LeakyRelu(_) => ("\\operatorname{leakyrelu}", "leakyrelu"),
+            FloatOperAry1::Elu => ("\\operatorname{elu}", "elu"),
+            FloatOperAry1::Gelu => ("\\operatorname{gelu}", "gelu"),
+        };
+        Notation::Function { latex, mathml }
     }
 }
 
-impl<'a> ops::Sub for ExprFloat<'a> {
-    type Output = ExprFloat<'a>;
-
-    fn sub(self, rhs: Self) -> Self::Output {
-        let node = ExprNode::Ary2(FloatOperAry2::Sub, self.ident, rhs.ident);
-        self.register_and_continue_expr(node)
+impl RenderOperator for FloatOperAry2 {
+    fn notation(&self) -> Notation {
+        match self {
+            FloatOperAry2::Add => Notation::Infix {
+                latex: "+",
+                mathml: "+",
+                precedence: PRECEDENCE_ADD,
+                associative: true,
+            },
+            FloatOperAry2::Sub => Notation::Infix {
+                latex: "-",
+                mathml: "&#x2212;",
+                precedence: PRECEDENCE_ADD,
+                associative: false,
+            },
+            FloatOperAry2::Mul => Notation::Infix {
+                latex: "\\cdot",
+                mathml: "&#x22C5;",
+                precedence: PRECEDENCE_MUL,
+                associative: true,
+            },
+            FloatOperAry2::Pow => Notation::Pow,
+            FloatOperAry2::Gt => Notation::Infix {
+                latex: ">",
+                mathml: "&gt;",
+                precedence: PRECEDENCE_CMP,
+                associative: false,
+            },
+            FloatOperAry2::Lt => Notation::Infix {
+                latex: "<",
+                mathml: "&lt;",
+                precedence: PRECEDENCE_CMP,
+                associative: false,
+            },
+            FloatOperAry2::Where => Notation::Infix {
+                latex: "\\text{ if }",
+                mathml: "if",
+                precedence: PRECEDENCE_SELECT,
+                associative: false,
+            },
+            FloatOperAry2::WhereNot => Notation::Infix {
+                latex: "\\text{ unless }",
+                mathml: "unless",
+                precedence: PRECEDENCE_SELECT,
+                associative: false,
+            },
+            FloatOperAry2::Div => Notation::Infix {
+                latex: "/",
+                mathml: "/",
+                precedence: PRECEDENCE_MUL,
+                associative: false,
+            },
+            FloatOperAry2::Min => Notation::Function {
+                latex: "\\min",
+                mathml: "min",
+            },
+            FloatOperAry2::Max => Notation::Function {
+                latex: "\\max",
+                mathml: "max",
+            },
+            FloatOperAry2::Atan2 => Notation::Function {
+                latex: "\\operatorname{atan2}",
+                mathml: "atan2",
+            },
+            FloatOperAry2::Loss(loss) => {
+                let (latex, mathml) = loss.notation();
+                Notation::Function { latex, mathml }
+            }
+        }
     }
 }
 
-impl<'a> ops::Mul for ExprFloat<'a> {
-    type Output = ExprFloat<'a>;
+type ExprFloat<'a> = Expr<'a, f32, FloatOperAry1, FloatOperAry2>;
 
-    fn mul(self, rhs: Self) -> Self::Output {
-        let node = ExprNode::Ary2(FloatOperAry2::Mul, self.ident, rhs.ident);
-        self.register_and_continue_expr(node)
-    }
-}
+impl_expr_ops!(f32, FloatOperAry1, FloatOperAry2, neg: FloatOperAry1::Neg,
+    Add::add, AddAssign::add_assign => FloatOperAry2::Add;
+    Sub::sub, SubAssign::sub_assign => FloatOperAry2::Sub;
+    Mul::mul, MulAssign::mul_assign => FloatOperAry2::Mul;
+    Div::div, DivAssign::div_assign => FloatOperAry2::Div;
+);
 
 impl<'a> ExprFloat<'a> {
     pub fn cos(&self) -> ExprFloat<'a> {
@@ -122,6 +256,99 @@ impl<'a> ExprFloat<'a> {
         self.register_and_continue_expr(node)
     }
 
+    pub fn exp(&self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary1(FloatOperAry1::Exp, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn tanh(&self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary1(FloatOperAry1::Tanh, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn sigmoid(&self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary1(FloatOperAry1::Sigmoid, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn softplus(&self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary1(FloatOperAry1::Softplus, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn sqrt(&self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary1(FloatOperAry1::Sqrt, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn abs(&self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary1(FloatOperAry1::Abs, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn leaky_relu(&self, alpha: f32) -> ExprFloat<'a> {
+        let node = ExprNode::Ary1(FloatOperAry1::LeakyRelu(alpha), self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn elu(&self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary1(FloatOperAry1::Elu, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn gelu(&self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary1(FloatOperAry1::Gelu, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// atan2(self, other), the angle of the point `(other, self)`.
+    pub fn atan2(&self, other: Self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary2(FloatOperAry2::Atan2, self.ident, other.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// Loss of the prediction `self` w.r.t. the `target`. Panic on a non-positive [Loss::Huber] delta.
+    pub fn loss(&self, target: Self, loss: Loss) -> ExprFloat<'a> {
+        loss.validate();
+        let node = ExprNode::Ary2(FloatOperAry2::Loss(loss), self.ident, target.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// 1 where `self > other`, 0 elsewhere.
+    pub fn gt(&self, other: Self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary2(FloatOperAry2::Gt, self.ident, other.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// 1 where `self < other`, 0 elsewhere.
+    pub fn lt(&self, other: Self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary2(FloatOperAry2::Lt, self.ident, other.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// Use `self` as a condition: `a` where the condition is non-zero, `b` elsewhere. The gradient is routed
+    /// through the chosen branch, and is zero for the condition.
+    pub fn select(&self, a: Self, b: Self) -> ExprFloat<'a> {
+        let a = ExprNode::Ary2(FloatOperAry2::Where, a.ident, self.ident);
+        let b = ExprNode::Ary2(FloatOperAry2::WhereNot, b.ident, self.ident);
+        self.register_and_continue_expr(a) + self.register_and_continue_expr(b)
+    }
+
+    pub fn min(&self, other: Self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary2(FloatOperAry2::Min, self.ident, other.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn max(&self, other: Self) -> ExprFloat<'a> {
+        let node = ExprNode::Ary2(FloatOperAry2::Max, self.ident, other.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// Limit the value to `[low, high]`.
+    pub fn clamp(&self, low: Self, high: Self) -> ExprFloat<'a> {
+        self.max(low).min(high)
+    }
+
     /// Linear regression `y=ax+b` with `a` and `b` being latent parameters, not stated explicitly.
     pub fn linreg(&self) -> ExprFloat<'a> {
         let x = *self;
@@ -132,18 +359,6 @@ impl<'a> ExprFloat<'a> {
     }
 }
 
-pub trait AsConst {
-    /// Produce a constant out of a float.
-    fn as_const(self, eb: &ExprBuilder<f32, FloatOperAry1, FloatOperAry2>) -> ExprFloat;
-}
-
-impl AsConst for f32 {
-    fn as_const(self, eb: &ExprBuilder<f32, FloatOperAry1, FloatOperAry2>) -> ExprFloat {
-        let node = ExprNode::Const(self);
-        eb.register_node_get_expr(node)
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::{AsConst, FloatOperAry1, FloatOperAry2};
@@ -176,6 +391,19 @@ mod tests {
         assert_eq!("(x + 2)", format!("{}", y));
     }
 
+    #[test]
+    fn scalar_ops() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let y = 3.0 * (x - 2.0).relu() + 1.0 / -x;
+        assert_eq!("((3 * relu((x - 2))) + (1 / neg(x)))", format!("{}", y));
+
+        let mut z = &x * &y;
+        z -= x;
+        z /= 2.0;
+        assert_eq!(format!("(((x * {}) - x) / 2)", y), format!("{}", z));
+    }
+
     #[test]
     fn powi() {
         let eb = new_eb();
@@ -184,6 +412,33 @@ mod tests {
         assert_eq!("pow2(x)", format!("{}", y));
     }
 
+    #[test]
+    fn select() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let a = eb.new_variable("a");
+        let y = x.gt(a).select(x, a);
+        assert_eq!("((x if (x > a)) + (a unless (x > a)))", format!("{}", y));
+        assert_eq!(
+            "\\left(x \\text{ if } x > a\\right) + \\left(a \\text{ unless } x > a\\right)",
+            y.to_latex()
+        );
+    }
+
+    #[test]
+    fn elementwise_math() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let a = eb.new_variable("a");
+        let y = (-x).sigmoid() / x.atan2(a).max(a);
+        assert_eq!("(sigmoid(neg(x)) / ((x atan2 a) max a))", format!("{}", y));
+        assert_eq!(
+            "\\sigma\\left(-\\left(x\\right)\\right) / \\max\\left(\\operatorname{atan2}\\left(x, a\\right), a\\right)",
+            y.to_latex()
+        );
+        assert_eq!("leaky_relu_0.1(x)", format!("{}", x.leaky_relu(0.1)));
+    }
+
     #[ignore]
     #[test]
     fn l2_norm() {
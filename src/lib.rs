@@ -2,4 +2,9 @@ pub mod compute;
 pub mod core_syntax;
 pub mod float;
 pub mod gradient_descent;
+pub mod losses;
+pub mod minimize;
 pub mod nar;
+pub mod profile;
+pub mod render;
+pub mod tape;
@@ -0,0 +1,168 @@
+//! Loss functions, available as a single fused operator for the float and the matrix expressions, like
+//! `y.loss(t, Loss::Mse)`.
+//!
+//! A loss compares a prediction `y` with a target `t`. The element-wise value and derivatives are defined
+//! here, and the backends apply them to their values. The matrix backend then reduces the elements to a single
+//! value, see [Reduction].
+use std::fmt;
+
+/// Probabilities are clamped to at least `EPS` before taking the logarithm in [Loss::CrossEntropy].
+pub(crate) const EPS: f32 = 1e-7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Loss {
+    /// Squared error `(y - t)^2`.
+    Mse,
+    /// Absolute error `|y - t|`, also known as L1.
+    Mae,
+    /// Squared error for `|y - t| <= delta`, absolute error beyond, so the outliers have a limited gradient.
+    /// The `delta` must be positive.
+    Huber(f32),
+    /// `ln(cosh(y - t))`, a smooth version of [Loss::Mae].
+    LogCosh,
+    /// Binary cross-entropy of a logit `y` (before sigmoid) and a label `t` in `[0, 1]`.
+    BceWithLogits,
+    /// Multi-class cross-entropy `-t * ln(y)` of predicted probabilities `y` and target probabilities `t`.
+    /// For a matrix, the classes are along the last axis.
+    CrossEntropy,
+}
+
+/// How the matrix backend reduces the element-wise loss to a single value.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Reduction {
+    /// Average over the samples. For [Loss::CrossEntropy], the classes along the last axis are summed first.
+    #[default]
+    Mean,
+    Sum,
+}
+
+impl Loss {
+    /// Panic on invalid parameters of the loss. Called when the loss is added to an expression.
+    pub(crate) fn validate(&self) {
+        if let Loss::Huber(delta) = self {
+            assert!(*delta > 0.0, "Huber delta must be positive, got {}", delta);
+        }
+    }
+
+    /// Value of the loss of a single element.
+    pub fn value(&self, y: f32, t: f32) -> f32 {
+        let d = y - t;
+        match self {
+            Loss::Mse => d * d,
+            Loss::Mae => d.abs(),
+            Loss::Huber(delta) => {
+                if d.abs() <= *delta {
+                    0.5 * d * d
+                } else {
+                    delta * (d.abs() - 0.5 * delta)
+                }
+            }
+            // Stable for large |d|: ln(cosh(d)) = |d| + ln(1 + e^(-2|d|)) - ln(2)
+            Loss::LogCosh => d.abs() + (-2.0 * d.abs()).exp().ln_1p() - std::f32::consts::LN_2,
+            // Stable for large |y|: max(y, 0) - y*t + ln(1 + e^-|y|)
+            Loss::BceWithLogits => y.max(0.0) - y * t + (-y.abs()).exp().ln_1p(),
+            Loss::CrossEntropy => -t * y.max(EPS).ln(),
+        }
+    }
+
+    /// Partial derivatives of the loss of a single element w.r.t. `y` and `t`.
+    pub fn derivative(&self, y: f32, t: f32) -> (f32, f32) {
+        let d = y - t;
+        let dd = match self {
+            Loss::Mse => 2.0 * d,
+            Loss::Mae => sign(d),
+            Loss::Huber(delta) => d.clamp(-delta, *delta),
+            Loss::LogCosh => d.tanh(),
+            Loss::BceWithLogits => return (1.0 / (1.0 + (-y).exp()) - t, -y),
+            // The loss is constant in `y` where it is clamped.
+            Loss::CrossEntropy => {
+                let dy = if y < EPS { 0.0 } else { -t / y };
+                return (dy, -y.max(EPS).ln());
+            }
+        };
+        (dd, -dd)
+    }
+
+    /// The LaTeX and MathML names of the loss.
+    pub(crate) fn notation(&self) -> (&'static str, &'static str) {
+        match self {
+            Loss::Mse => ("\\operatorname{mse}", "mse"),
+            Loss::Mae => ("\\operatorname{mae}", "mae"),
+            Loss::Huber(_) => ("\\operatorname{huber}", "huber"),
+            Loss::LogCosh => ("\\operatorname{logcosh}", "logcosh"),
+            Loss::BceWithLogits => ("\\operatorname{bce}", "bce"),
+            Loss::CrossEntropy => ("\\operatorname{ce}", "ce"),
+        }
+    }
+}
+
+impl fmt::Display for Loss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Loss::Mse => write!(f, "mse"),
+            Loss::Mae => write!(f, "mae"),
+            Loss::Huber(delta) => write!(f, "huber_{}", delta),
+            Loss::LogCosh => write!(f, "log_cosh"),
+            Loss::BceWithLogits => write!(f, "bce_logits"),
+            Loss::CrossEntropy => write!(f, "cross_entropy"),
+        }
+    }
+}
+
+fn sign(d: f32) -> f32 {
+    if d > 0.0 {
+        1.0
+    } else if d < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Loss;
+
+    #[test]
+    fn derivatives() {
+        let h = 1e-3;
+        let losses = [
+            Loss::Mse,
+            Loss::Mae,
+            Loss::Huber(0.5),
+            Loss::LogCosh,
+            Loss::BceWithLogits,
+            Loss::CrossEntropy,
+        ];
+        for loss in losses {
+            for (y, t) in [(0.3, 0.9), (2.0, 0.25), (0.8, 0.1)] {
+                let (dy, dt) = loss.derivative(y, t);
+                let fd_y = (loss.value(y + h, t) - loss.value(y - h, t)) / (2.0 * h);
+                let fd_t = (loss.value(y, t + h) - loss.value(y, t - h)) / (2.0 * h);
+                assert!((dy - fd_y).abs() < 1e-2, "{} dy {} vs {}", loss, dy, fd_y);
+                assert!((dt - fd_t).abs() < 1e-2, "{} dt {} vs {}", loss, dt, fd_t);
+            }
+        }
+
+        // Below the clamp the cross-entropy does not depend on `y`, so neither does its gradient.
+        let (y, t, h) = (1e-9, 1.0, 1e-10);
+        let fd_y =
+            (Loss::CrossEntropy.value(y + h, t) - Loss::CrossEntropy.value(y - h, t)) / (2.0 * h);
+        assert_eq!(fd_y, 0.0);
+        assert_eq!(Loss::CrossEntropy.derivative(y, t).0, 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Huber delta must be positive, got -1")]
+    fn huber_negative_delta() {
+        Loss::Huber(-1.0).validate();
+    }
+
+    #[test]
+    fn stable() {
+        assert!(Loss::BceWithLogits.value(100.0, 1.0) < 1e-30);
+        assert_eq!(100.0, Loss::BceWithLogits.value(100.0, 0.0));
+        assert!((Loss::LogCosh.value(200.0, 0.0) - (200.0 - 2.0_f32.ln())).abs() < 1e-3);
+        assert!(Loss::CrossEntropy.value(0.0, 1.0).is_finite());
+    }
+}
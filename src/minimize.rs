@@ -0,0 +1,408 @@
+//! Full-batch minimization of a loss w.r.t. a set of parameters.
+//!
+//! Unlike the fixed-step [ComputGraph::update_params_lr], [minimize] chooses the step with a line search, and can
+//! use the curvature (L-BFGS) or the previous directions (conjugate gradient). It is meant for small,
+//! deterministic problems where the loss covers all the data, like fitting a polynomial.
+//!
+//! The parameters are treated as a single flat vector, see [Flatten]. The variables must be set before the call.
+//! Trial points are evaluated on the same graph, and the graph is left with the parameters at the best point found.
+use crate::{
+    compute::ComputGraph,
+    core_syntax::{ComputValue, Ident, Operator},
+    nar::syntax::MatrixF32,
+};
+
+/// A value that can be optimized as a flat vector of its elements.
+pub trait Flatten: ComputValue {
+    fn flatten(&self) -> Vec<f32>;
+
+    /// A value of the same shape as `self` with the given elements.
+    fn unflatten(&self, elements: &[f32]) -> Self;
+}
+
+impl Flatten for f32 {
+    fn flatten(&self) -> Vec<f32> {
+        vec![*self]
+    }
+
+    fn unflatten(&self, elements: &[f32]) -> Self {
+        elements[0]
+    }
+}
+
+impl Flatten for MatrixF32 {
+    fn flatten(&self) -> Vec<f32> {
+        match self {
+            MatrixF32::M(m) => m.iter().cloned().collect(),
+            MatrixF32::V(v) => vec![*v],
+        }
+    }
+
+    fn unflatten(&self, elements: &[f32]) -> Self {
+        match self {
+            MatrixF32::M(m) => MatrixF32::new_m(
+                ndarray::ArrayD::from_shape_vec(m.raw_dim(), elements.to_vec())
+                    .expect("Bug: number of elements does not match the shape"),
+            ),
+            MatrixF32::V(_) => MatrixF32::V(elements[0]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    /// Limited-memory BFGS keeping the given number of the last updates, typically 5 to 20.
+    Lbfgs(usize),
+    /// Nonlinear conjugate gradient (Polak-Ribière, restarted when the direction is not a descent).
+    ConjugateGradient,
+    GradientDescent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineSearch {
+    /// Backtrack until the loss decreases enough (the Armijo condition).
+    Armijo,
+    /// Bracket a step satisfying the (weak) Wolfe conditions: enough decrease, and the slope flattens enough.
+    /// Needed for L-BFGS to keep its curvature estimate positive.
+    Wolfe,
+}
+
+pub enum Opts {
+    /// Maximum number of iterations, 100 by default.
+    MaxIter(usize),
+    /// Stop when the norm of the gradient is below the value, `1e-5` by default.
+    GradTolerance(f32),
+    /// Stop when the loss decreases less than the value (relative to the loss), `1e-7` by default.
+    Tolerance(f32),
+    /// Wolfe for [Method::Lbfgs] and [Method::ConjugateGradient], Armijo for [Method::GradientDescent] by default.
+    LineSearch(LineSearch),
+}
+
+/// The outcome of [minimize].
+#[derive(Debug, Clone)]
+pub struct Minimized {
+    pub loss: f32,
+    pub iterations: usize,
+    /// Number of forward and backward passes.
+    pub evaluations: usize,
+    /// True if one of the tolerances was met, false if the iterations ran out or the line search failed.
+    pub converged: bool,
+    /// The loss at the start of each iteration.
+    pub history: Vec<f32>,
+}
+
+/// Minimize the single value `loss` w.r.t. the `params`.
+pub fn minimize<F, OP1, OP2>(
+    cg: &mut ComputGraph<F, OP1, OP2>,
+    loss: &dyn AsRef<Ident>,
+    params: &[Ident],
+    method: Method,
+    opts: &[Opts],
+) -> Minimized
+where
+    F: Flatten,
+    OP1: Operator,
+    OP2: Operator,
+{
+    let mut max_iter = 100;
+    let mut grad_tolerance = 1e-5;
+    let mut tolerance = 1e-7;
+    let mut line_search = match method {
+        Method::GradientDescent => LineSearch::Armijo,
+        _ => LineSearch::Wolfe,
+    };
+    for opt in opts {
+        match opt {
+            Opts::MaxIter(v) => max_iter = *v,
+            Opts::GradTolerance(v) => grad_tolerance = *v,
+            Opts::Tolerance(v) => tolerance = *v,
+            Opts::LineSearch(v) => line_search = *v,
+        }
+    }
+    // Curvature condition of Wolfe: L-BFGS accepts long steps, CG needs a more exact search.
+    let c2 = match method {
+        Method::ConjugateGradient => 0.1,
+        _ => 0.9,
+    };
+
+    let mut problem = Problem::new(cg, *loss.as_ref(), params);
+    let mut x = problem.start();
+    let (mut f, mut g) = problem.eval(&x);
+    let mut snapshot = problem.cg.snapshot_params();
+    let mut result = Minimized {
+        loss: f,
+        iterations: 0,
+        evaluations: 0,
+        converged: false,
+        history: Vec::new(),
+    };
+    // (s, y) pairs of L-BFGS, the oldest first.
+    let mut memory: Vec<(Vec<f32>, Vec<f32>)> = Vec::new();
+    let mut prev: Option<(Vec<f32>, Vec<f32>, f32)> = None; // direction, gradient and step of the last iteration
+
+    while result.iterations < max_iter {
+        result.history.push(f);
+        if norm(&g) <= grad_tolerance {
+            result.converged = true;
+            break;
+        }
+        result.iterations += 1;
+
+        let mut d = match method {
+            Method::GradientDescent => scale(&g, -1.0),
+            Method::ConjugateGradient => match &prev {
+                Some((d_prev, g_prev, _)) => {
+                    let y: Vec<f32> = sub(&g, g_prev);
+                    let beta = (dot(&g, &y) / dot(g_prev, g_prev)).max(0.0);
+                    axpy(beta, d_prev, &scale(&g, -1.0))
+                }
+                None => scale(&g, -1.0),
+            },
+            Method::Lbfgs(_) => lbfgs_direction(&memory, &g),
+        };
+        if dot(&d, &g) >= 0.0 {
+            // Not a descent direction, restart.
+            d = scale(&g, -1.0);
+            memory.clear();
+        }
+        let alpha0 = match (&prev, method) {
+            (None, _) => (1.0 / norm(&g)).min(1.0),
+            (Some(_), Method::Lbfgs(_)) => 1.0,
+            // Expect the same decrease as in the last iteration.
+            (Some((d_prev, g_prev, alpha_prev)), _) => {
+                (alpha_prev * dot(g_prev, d_prev) / dot(&g, &d)).clamp(1e-10, 1e10)
+            }
+        };
+
+        let Some((alpha, x_new, f_new, g_new)) =
+            problem.line_search(line_search, c2, &x, f, &g, &d, alpha0)
+        else {
+            // Leave the graph at the best point.
+            problem.cg.restore_params(&snapshot);
+            break;
+        };
+        snapshot = problem.cg.snapshot_params();
+
+        if let Method::Lbfgs(m) = method {
+            let s = sub(&x_new, &x);
+            let y = sub(&g_new, &g);
+            if dot(&s, &y) > 1e-10 {
+                memory.push((s, y));
+                if memory.len() > m {
+                    memory.remove(0);
+                }
+            }
+        }
+        let decrease = f - f_new;
+        prev = Some((d, g, alpha));
+        (x, f, g) = (x_new, f_new, g_new);
+        if decrease <= tolerance * f.abs().max(1.0) {
+            result.converged = true;
+            break;
+        }
+    }
+    result.loss = f;
+    result.evaluations = problem.evaluations;
+    result
+}
+
+struct Problem<'g, 'a, F, OP1, OP2>
+where
+    F: Flatten,
+    OP1: Operator,
+    OP2: Operator,
+{
+    cg: &'g mut ComputGraph<'a, F, OP1, OP2>,
+    loss: Ident,
+    params: Vec<Ident>,
+    /// The values of the parameters before the minimization, to keep the shapes.
+    templates: Vec<F>,
+    evaluations: usize,
+}
+
+impl<'g, 'a, F, OP1, OP2> Problem<'g, 'a, F, OP1, OP2>
+where
+    F: Flatten,
+    OP1: Operator,
+    OP2: Operator,
+{
+    fn new(cg: &'g mut ComputGraph<'a, F, OP1, OP2>, loss: Ident, params: &[Ident]) -> Self {
+        let templates = params.iter().map(|p| cg.primal(p)).collect();
+        Problem {
+            cg,
+            loss,
+            params: params.to_vec(),
+            templates,
+            evaluations: 0,
+        }
+    }
+
+    fn start(&self) -> Vec<f32> {
+        self.templates.iter().flat_map(|t| t.flatten()).collect()
+    }
+
+    /// Set the parameters to `x` and return the loss and its gradient w.r.t. `x`.
+    fn eval(&mut self, x: &[f32]) -> (f32, Vec<f32>) {
+        self.evaluations += 1;
+        let mut offset = 0;
+        for (param, template) in self.params.iter().zip(self.templates.iter()) {
+            let n = template.n_elements();
+            let value = template.unflatten(&x[offset..offset + n]);
+            self.cg.reset_primal_of_parameter(param, value);
+            offset += n;
+        }
+        self.cg.reset_computed_primals();
+        self.cg.zero_grad();
+
+        let loss = self.cg.forward(&self.loss).flatten();
+        assert_eq!(
+            loss.len(),
+            1,
+            "Expected a single value loss, got {:?}",
+            loss
+        );
+        self.cg.backward(&self.loss);
+        let mut grad = Vec::with_capacity(x.len());
+        for (param, template) in self.params.iter().zip(self.templates.iter()) {
+            match self.cg.adjoin(param) {
+                Some(adjoin) => {
+                    let adjoin = adjoin.flatten();
+                    assert_eq!(
+                        adjoin.len(),
+                        template.n_elements(),
+                        "Gradient of parameter {} does not match its shape",
+                        param
+                    );
+                    grad.extend(adjoin);
+                }
+                None => grad.extend(vec![0.0; template.n_elements()]),
+            }
+        }
+        (loss[0], grad)
+    }
+
+    /// Find a step along `d` from `x`. Return the step, the new point, the loss and the gradient at the point,
+    /// or None if no acceptable step was found.
+    #[allow(clippy::too_many_arguments)]
+    fn line_search(
+        &mut self,
+        line_search: LineSearch,
+        c2: f32,
+        x: &[f32],
+        f: f32,
+        g: &[f32],
+        d: &[f32],
+        alpha0: f32,
+    ) -> Option<(f32, Vec<f32>, f32, Vec<f32>)> {
+        let c1 = 1e-4;
+        let slope = dot(g, d);
+        let (mut lo, mut hi) = (0.0_f32, f32::INFINITY);
+        let mut alpha = alpha0;
+        for _ in 0..50 {
+            let x_new = axpy(alpha, d, x);
+            let (f_new, g_new) = self.eval(&x_new);
+            if !f_new.is_finite() || f_new > f + c1 * alpha * slope {
+                hi = alpha;
+            } else if line_search == LineSearch::Wolfe && dot(&g_new, d) < c2 * slope {
+                lo = alpha;
+            } else {
+                return Some((alpha, x_new, f_new, g_new));
+            }
+            alpha = if hi.is_finite() {
+                0.5 * (lo + hi)
+            } else {
+                2.0 * lo
+            };
+        }
+        None
+    }
+}
+
+/// The two-loop recursion: approximate `-H g` where `H` is the inverse Hessian.
+fn lbfgs_direction(memory: &[(Vec<f32>, Vec<f32>)], g: &[f32]) -> Vec<f32> {
+    let mut q = g.to_vec();
+    let mut alphas = Vec::with_capacity(memory.len());
+    for (s, y) in memory.iter().rev() {
+        let rho = 1.0 / dot(y, s);
+        let alpha = rho * dot(s, &q);
+        q = axpy(-alpha, y, &q);
+        alphas.push((rho, alpha));
+    }
+    if let Some((s, y)) = memory.last() {
+        q = scale(&q, dot(s, y) / dot(y, y));
+    }
+    for ((s, y), (rho, alpha)) in memory.iter().zip(alphas.iter().rev()) {
+        let beta = rho * dot(y, &q);
+        q = axpy(alpha - beta, s, &q);
+    }
+    scale(&q, -1.0)
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(a, b)| a * b).sum()
+}
+
+fn norm(a: &[f32]) -> f32 {
+    dot(a, a).sqrt()
+}
+
+fn scale(a: &[f32], k: f32) -> Vec<f32> {
+    a.iter().map(|a| a * k).collect()
+}
+
+fn sub(a: &[f32], b: &[f32]) -> Vec<f32> {
+    a.iter().zip(b.iter()).map(|(a, b)| a - b).collect()
+}
+
+/// `k * a + b`
+fn axpy(k: f32, a: &[f32], b: &[f32]) -> Vec<f32> {
+    a.iter().zip(b.iter()).map(|(a, b)| k * a + b).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{minimize, LineSearch, Method, Opts};
+    use crate::{
+        compute::ComputGraph,
+        core_syntax::ExprBuilder,
+        float::{
+            calculator::FloatCalculator,
+            syntax::{FloatOperAry1, FloatOperAry2},
+        },
+    };
+
+    /// The Rosenbrock function, with the minimum 0 at (1, 1).
+    #[test]
+    fn rosenbrock() {
+        for (method, line_search, max_iter) in [
+            (Method::Lbfgs(10), LineSearch::Wolfe, 100),
+            (Method::ConjugateGradient, LineSearch::Wolfe, 1000),
+            (Method::GradientDescent, LineSearch::Armijo, 20000),
+        ] {
+            let eb = ExprBuilder::<f32, FloatOperAry1, FloatOperAry2>::new();
+            let a = eb.new_named_parameter("a", -1.2);
+            let b = eb.new_named_parameter("b", 1.0);
+            let loss = (1.0 - a).powi(2) + 100.0 * (b - a.powi(2)).powi(2);
+            let [a, b, loss] = [a, b, loss].map(|e| e.ident);
+            let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+
+            let result = minimize(
+                &mut cg,
+                &loss,
+                &[a, b],
+                method,
+                &[
+                    Opts::MaxIter(max_iter),
+                    Opts::LineSearch(line_search),
+                    Opts::Tolerance(0.0),
+                    Opts::GradTolerance(1e-3),
+                ],
+            );
+            assert!(result.converged, "{:?} {:?}", method, result);
+            assert!(result.loss < 1e-4, "{:?} {:?}", method, result);
+            assert!((cg.primal(&a) - 1.0).abs() < 0.02, "{:?}", method);
+            assert!((cg.primal(&b) - 1.0).abs() < 0.04, "{:?}", method);
+            assert_eq!(result.loss, cg.forward(&loss));
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! NumPy-style broadcasting of the element-wise operators.
+//!
+//! The shapes are aligned on the last axis. Two axes are compatible if they are equal or one of them is 1, and
+//! the missing leading axes count as 1. So a `(n, 1)` bias can be added to a `(n, m)` matrix, and a `(m)` row to
+//! each row of it. In the backward, the adjoin has the broadcast shape, and it's summed over the broadcast axes
+//! to get back to the shape of the operand, see [reduce_to_shape].
+use ndarray::{self as nd, Axis};
+
+/// Shape of the result of an element-wise operator on operands of shapes `a` and `b`.
+pub(crate) fn broadcast_shape(a: &[usize], b: &[usize]) -> Result<Vec<usize>, String> {
+    let n = a.len().max(b.len());
+    let dim = |shape: &[usize], i: usize| (i + shape.len()).checked_sub(n).map_or(1, |j| shape[j]);
+    (0..n)
+        .map(|i| match (dim(a, i), dim(b, i)) {
+            (da, db) if da == db || db == 1 => Ok(da),
+            (1, db) => Ok(db),
+            _ => Err(format!("shapes {:?} and {:?} do not match", a, b)),
+        })
+        .collect()
+}
+
+/// Sum the elements of `m` over the axes broadcast from `shape`: the leading axes missing in `shape`, and the
+/// axes of length 1 in `shape`. The result has exactly `shape`.
+pub(crate) fn reduce_to_shape(m: nd::ArrayViewD<'_, f32>, shape: &[usize]) -> nd::ArrayD<f32> {
+    let extra = m.ndim() - shape.len();
+    let mut reduced = m.to_owned();
+    for _ in 0..extra {
+        reduced = reduced.sum_axis(Axis(0));
+    }
+    for (i, &d) in shape.iter().enumerate() {
+        if d == 1 && reduced.len_of(Axis(i)) != 1 {
+            reduced = reduced.sum_axis(Axis(i)).insert_axis(Axis(i));
+        }
+    }
+    reduced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{broadcast_shape, reduce_to_shape};
+    use ndarray::{self as nd, arr1, arr2};
+
+    #[test]
+    fn shapes() {
+        assert_eq!(broadcast_shape(&[3, 4], &[3, 4]), Ok(vec![3, 4]));
+        assert_eq!(broadcast_shape(&[3, 4], &[3, 1]), Ok(vec![3, 4]));
+        assert_eq!(broadcast_shape(&[4], &[3, 4]), Ok(vec![3, 4]));
+        assert_eq!(broadcast_shape(&[2, 1, 4], &[3, 1]), Ok(vec![2, 3, 4]));
+        assert_eq!(
+            broadcast_shape(&[3, 4], &[4, 3]),
+            Err("shapes [3, 4] and [4, 3] do not match".to_owned())
+        );
+    }
+
+    #[test]
+    fn reduce() {
+        let m = nd::ArrayD::from_shape_fn(vec![2, 3], |ix| (ix[0] * 3 + ix[1]) as f32);
+        assert_eq!(reduce_to_shape(m.view(), &[2, 3]), m);
+        assert_eq!(
+            reduce_to_shape(m.view(), &[2, 1]),
+            arr2(&[[3.0], [12.0]]).into_dyn()
+        );
+        assert_eq!(
+            reduce_to_shape(m.view(), &[3]),
+            arr1(&[3.0, 5.0, 7.0]).into_dyn()
+        );
+        assert_eq!(
+            reduce_to_shape(m.view(), &[1, 1]),
+            arr2(&[[15.0]]).into_dyn()
+        );
+    }
+}
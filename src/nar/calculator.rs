@@ -1,10 +1,19 @@
 use std::ops;
 
-use super::syntax::{MatrixF32, NaOperAry1, NaOperAry2};
+use super::syntax::{MatrixF32, NaOperAry1, NaOperAry2, ReduceOp};
 use crate::{
-    compute::{Calculator, ComputGraph, Node},
-    core_syntax::Ident,
-    nar::conv::conv2d,
+    compute::OperCalculator,
+    float::{calculator::FloatCalculator, syntax::FloatOperAry1},
+    losses::{Loss, Reduction},
+    nar::broadcast::{broadcast_shape, reduce_to_shape},
+    nar::conv::{conv2d, conv2d_adjoin},
+    nar::layout::{
+        concat, concat_adjoin, concat_shape, layout_backward, layout_forward, layout_shape,
+    },
+    nar::matmul::{matmul, matmul_adjoin, matmul_shape},
+    nar::pool::{global_pool_axes, pool2d, pool2d_backward, pool2d_shape},
+    nar::reduce::{reduce_backward, reduce_forward},
+    nar::softmax::{n_samples, one_hot_labels, softmax, softmax_backward},
 };
 use ndarray as nd;
 //use nalgebra as _na;
@@ -15,148 +24,447 @@ pub struct MatrixCalculator;
 
 type NdMatrixDynF32 = nd::ArrayBase<nd::OwnedRepr<f32>, nd::Dim<nd::IxDynImpl>>;
 
-impl Calculator<NaOperAry1, NaOperAry2, MatrixF32> for MatrixCalculator {
-    fn forward(
+impl OperCalculator<NaOperAry1, NaOperAry2, MatrixF32> for MatrixCalculator {
+    fn forward_ary1(&self, oper: &NaOperAry1, primal: &MatrixF32) -> MatrixF32 {
+        match oper {
+            NaOperAry1::Relu => primal.relu(),
+            NaOperAry1::PowI(exp) => primal.powi(*exp),
+            NaOperAry1::Sum => match primal {
+                MatrixF32::M(m) => MatrixF32::V(m.as_ref().sum()),
+                MatrixF32::V(_) => primal.clone(),
+            },
+            NaOperAry1::Neg => primal.clone() * -1.0,
+            NaOperAry1::Exp
+            | NaOperAry1::Ln
+            | NaOperAry1::Sin
+            | NaOperAry1::Cos
+            | NaOperAry1::Tanh
+            | NaOperAry1::Sigmoid
+            | NaOperAry1::Sqrt
+            | NaOperAry1::Abs
+            | NaOperAry1::LeakyRelu(_)
+            | NaOperAry1::Softplus => {
+                let oper = scalar_oper(oper);
+                map(primal, |a| FloatCalculator.forward_ary1(&oper, &a))
+            }
+            NaOperAry1::Reduce(op, axes, keepdims) => {
+                let v =
+                    reduce_forward(op, axes.as_slice(), *keepdims, layout_operand(oper, primal));
+                if v.ndim() == 0 {
+                    MatrixF32::V(v.sum())
+                } else {
+                    MatrixF32::new_m(v)
+                }
+            }
+            NaOperAry1::Softmax(axis) => {
+                MatrixF32::new_m(softmax(*axis, false, layout_operand(oper, primal)))
+            }
+            NaOperAry1::LogSoftmax(axis) => {
+                MatrixF32::new_m(softmax(*axis, true, layout_operand(oper, primal)))
+            }
+            NaOperAry1::Pool2d(op, window, stride) => {
+                MatrixF32::new_m(pool2d(op, *window, *stride, layout_operand(oper, primal)))
+            }
+            NaOperAry1::GlobalAvgPool2d => {
+                let a = layout_operand(oper, primal);
+                let axes = global_pool_axes(a.ndim()).unwrap_or_else(|e| panic!("{}", e));
+                let v = reduce_forward(&ReduceOp::Mean, &axes, false, a);
+                if v.ndim() == 0 {
+                    MatrixF32::V(v.sum())
+                } else {
+                    MatrixF32::new_m(v)
+                }
+            }
+            NaOperAry1::Reshape(_)
+            | NaOperAry1::Transpose
+            | NaOperAry1::Permute(_)
+            | NaOperAry1::Flatten
+            | NaOperAry1::InsertAxis(_)
+            | NaOperAry1::Slice(..) => {
+                MatrixF32::new_m(layout_forward(oper, layout_operand(oper, primal)))
+            }
+        }
+    }
+
+    fn forward_ary2(&self, oper: &NaOperAry2, a: &MatrixF32, b: &MatrixF32) -> MatrixF32 {
+        match oper {
+            NaOperAry2::Add => zip_map(a, b, |a, b| a + b),
+            NaOperAry2::Sub => zip_map(a, b, |a, b| a - b),
+            NaOperAry2::MulComp => a * b,
+            NaOperAry2::MatMul => {
+                let (a, b) = matrix_operands(oper, a, b);
+                let v = matmul(a, b);
+                if v.ndim() == 0 {
+                    MatrixF32::V(v.sum())
+                } else {
+                    MatrixF32::new_m(v)
+                }
+            }
+            NaOperAry2::Conv2d => {
+                let primal = a.m().unwrap_or_else(|| {
+                    panic!("Expected matrix as input to Conv2d but got {:?}", a)
+                });
+                let kernel = b.m().unwrap_or_else(|| {
+                    panic!("Expected matrix as a kernel to Conv2d but got {:?}", b)
+                });
+                let primal = nd::CowArray::from(primal);
+                let kernel = nd::CowArray::from(kernel);
+                let v = conv2d(&primal, &kernel);
+                MatrixF32::new_m(v)
+            }
+            NaOperAry2::Gt => zip_map(a, b, |a, b| mask(a > b)),
+            NaOperAry2::Lt => zip_map(a, b, |a, b| mask(a < b)),
+            NaOperAry2::Where => zip_map(a, b, |a, b| if b != 0.0 { a } else { 0.0 }),
+            NaOperAry2::WhereNot => zip_map(a, b, |a, b| if b == 0.0 { a } else { 0.0 }),
+            NaOperAry2::Div => zip_map(a, b, |a, b| a / b),
+            NaOperAry2::Concat(axis) => {
+                let (a, b) = matrix_operands(oper, a, b);
+                MatrixF32::new_m(concat(*axis, a, b))
+            }
+            NaOperAry2::SoftmaxCrossEntropy(reduction) => {
+                let logits = logits_operand(a);
+                let labels = one_hot_labels(logits, b);
+                let sum = -(softmax(logits.ndim() - 1, true, logits) * labels).sum();
+                MatrixF32::V(sum / ce_divisor(reduction, logits))
+            }
+            NaOperAry2::Loss(loss, reduction) => {
+                let sum = match zip_map(a, b, |y, t| loss.value(y, t)) {
+                    MatrixF32::M(m) => m.sum(),
+                    MatrixF32::V(v) => v,
+                };
+                MatrixF32::V(sum / loss_divisor(loss, reduction, a, b))
+            }
+        }
+    }
+
+    fn backward_ary1(
         &self,
-        cg: &ComputGraph<MatrixF32, NaOperAry1, NaOperAry2>,
-        ident: &Ident,
+        oper: &NaOperAry1,
+        a: &MatrixF32,
+        primal: &MatrixF32,
+        adjoin: &MatrixF32,
     ) -> MatrixF32 {
-        let node = cg.get_node(ident);
-        match node {
-            Node::Const(value) => value,
-            Node::Variable { name, .. } => panic!(
-                "Variable {:?} should have been set and already returned by ComputGraph!",
-                name
-            ),
-            Node::Parameter { name, .. } => panic!(
-                "Parameter {:?} should have been set and already returned by ComputGraph!",
-                name,
-            ),
-            Node::Ary1 { oper: op, arg1, .. } => {
-                let primal = cg.forward(&arg1);
-                match op {
-                    NaOperAry1::Relu => match &primal {
-                        MatrixF32::M(m) => MatrixF32::new_m(m.as_ref().clone().relu()),
-                        MatrixF32::V(v) => MatrixF32::V(v.relu()),
-                    },
-                    NaOperAry1::PowI(exp) => primal.powi(exp),
-                    NaOperAry1::Sum => match primal {
-                        MatrixF32::M(m) => MatrixF32::V(m.as_ref().sum()),
-                        MatrixF32::V(_) => primal,
-                    },
-                }
+        match oper {
+            NaOperAry1::Relu => adjoin * &a.backward_relu(),
+            NaOperAry1::PowI(p) => &a.backward_powi(*p) * adjoin,
+            NaOperAry1::Sum => adjoin.clone(),
+            NaOperAry1::Neg => adjoin.clone() * -1.0,
+            NaOperAry1::Exp
+            | NaOperAry1::Ln
+            | NaOperAry1::Sin
+            | NaOperAry1::Cos
+            | NaOperAry1::Tanh
+            | NaOperAry1::Sigmoid
+            | NaOperAry1::Sqrt
+            | NaOperAry1::Abs
+            | NaOperAry1::LeakyRelu(_)
+            | NaOperAry1::Softplus => {
+                // The derivative at each element, times the adjoin.
+                let oper = scalar_oper(oper);
+                let da = zip_map(a, primal, |a, primal| {
+                    FloatCalculator.backward_ary1(&oper, &a, &primal, &1.0)
+                });
+                adjoin * &da
+            }
+            NaOperAry1::Softmax(axis) | NaOperAry1::LogSoftmax(axis) => {
+                let primal = layout_operand(oper, primal);
+                let adjoin = match adjoin {
+                    MatrixF32::M(m) => m.as_ref().clone(),
+                    MatrixF32::V(v) => nd::ArrayD::from_elem(primal.raw_dim(), *v),
+                };
+                let log = matches!(oper, NaOperAry1::LogSoftmax(_));
+                MatrixF32::new_m(softmax_backward(*axis, log, primal, &adjoin))
             }
-            Node::Ary2 {
-                oper: op,
-                arg1: a,
-                arg2: b,
-                ..
-            } => {
-                let a = cg.forward(&a);
-                let b = cg.forward(&b);
-                match op {
-                    NaOperAry2::Add => match (a, b) {
-                        (MatrixF32::M(m1), MatrixF32::M(m2)) => {
-                            MatrixF32::new_m(m1.as_ref() + m2.as_ref())
-                        }
-                        (MatrixF32::M(m1), MatrixF32::V(v2)) => MatrixF32::new_m(m1.as_ref() + v2),
-                        (MatrixF32::V(v1), MatrixF32::M(m2)) => MatrixF32::new_m(m2.as_ref() + v1),
-                        (MatrixF32::V(v1), MatrixF32::V(v2)) => MatrixF32::V(v1 + v2),
-                    },
-                    NaOperAry2::Sub => match (a, b) {
-                        (MatrixF32::M(m1), MatrixF32::M(m2)) => {
-                            MatrixF32::new_m(m1.as_ref() - m2.as_ref())
-                        }
-                        (MatrixF32::M(m1), MatrixF32::V(v2)) => {
-                            MatrixF32::new_m(m1.as_ref() + (-v2))
-                        }
-                        (MatrixF32::V(v1), MatrixF32::M(m2)) => {
-                            let m1 = NdMatrixDynF32::from_elem(m2.shape(), v1);
-                            MatrixF32::new_m(m1 - m2.as_ref())
-                        }
-                        (MatrixF32::V(v1), MatrixF32::V(v2)) => MatrixF32::V(v1 - v2),
-                    },
-                    NaOperAry2::MulComp => &a * &b,
-                    NaOperAry2::Conv2d => {
-                        let primal = a.m().expect(
-                            format!(
-                                "Expected matrix as input to Conv2d {:?} but got {:?}",
-                                cg.get_name(ident),
-                                a
-                            )
-                            .as_str(),
-                        );
-                        let kernel = b.m().expect(
-                            format!(
-                                "Expected matrix as a kernel to Conv2d {:?} but got {:?}",
-                                cg.get_name(ident),
-                                b
-                            )
-                            .as_str(),
-                        );
-                        let primal = nd::CowArray::from(primal);
-                        let kernel = nd::CowArray::from(kernel);
-                        let v = conv2d(&primal, &kernel);
-                        MatrixF32::new_m(v)
+            NaOperAry1::Reduce(op, axes, _) => {
+                let a = layout_operand(oper, a);
+                let adjoin = match adjoin {
+                    MatrixF32::M(m) => m.as_ref().clone(),
+                    MatrixF32::V(v) => {
+                        nd::ArrayD::from_elem(primal.m().map_or(vec![], |p| p.shape().to_vec()), *v)
                     }
-                }
+                };
+                MatrixF32::new_m(reduce_backward(op, axes.as_slice(), a, &adjoin))
+            }
+            NaOperAry1::Pool2d(op, window, stride) => {
+                let a = layout_operand(oper, a);
+                let adjoin = match adjoin {
+                    MatrixF32::M(m) => m.as_ref().clone(),
+                    MatrixF32::V(v) => nd::ArrayD::from_elem(
+                        pool2d_shape(*window, *stride, a.shape()).unwrap(),
+                        *v,
+                    ),
+                };
+                MatrixF32::new_m(pool2d_backward(op, *window, *stride, a, &adjoin))
+            }
+            NaOperAry1::GlobalAvgPool2d => {
+                let a = layout_operand(oper, a);
+                let adjoin = match adjoin {
+                    MatrixF32::M(m) => m.as_ref().clone(),
+                    MatrixF32::V(v) => {
+                        nd::ArrayD::from_elem(primal.m().map_or(vec![], |p| p.shape().to_vec()), *v)
+                    }
+                };
+                let axes = global_pool_axes(a.ndim()).unwrap();
+                MatrixF32::new_m(reduce_backward(&ReduceOp::Mean, &axes, a, &adjoin))
+            }
+            NaOperAry1::Reshape(_)
+            | NaOperAry1::Transpose
+            | NaOperAry1::Permute(_)
+            | NaOperAry1::Flatten
+            | NaOperAry1::InsertAxis(_)
+            | NaOperAry1::Slice(..) => {
+                let a = layout_operand(oper, a);
+                let adjoin = match adjoin {
+                    MatrixF32::M(m) => m.as_ref().clone(),
+                    MatrixF32::V(v) => {
+                        nd::ArrayD::from_elem(layout_shape(oper, a.shape()).unwrap(), *v)
+                    }
+                };
+                MatrixF32::new_m(layout_backward(oper, a, &adjoin))
             }
         }
     }
 
-    fn backward(
+    fn backward_ary2(
         &self,
-        cg: &ComputGraph<MatrixF32, NaOperAry1, NaOperAry2>,
-        ident: &Ident,
+        oper: &NaOperAry2,
+        a: &MatrixF32,
+        b: &MatrixF32,
+        primal: &MatrixF32,
         adjoin: &MatrixF32,
-    ) {
-        cg.add_adjoin(ident, adjoin);
-        let node = cg.get_node(ident);
-        match node {
-            Node::Const(_) => (),
-            Node::Variable { .. } => (),
-            Node::Parameter { .. } => (),
-            Node::Ary1 {
-                oper: op, arg1: v1, ..
-            } => match op {
-                NaOperAry1::Relu => {
-                    let primal = cg.primal(&v1);
-                    let b = primal.backward_relu();
-                    self.backward(cg, &v1, &(adjoin * &b))
-                }
-                NaOperAry1::PowI(p) => {
-                    let a = cg.primal(&v1);
-                    let a = a.backward_powi(p);
-                    let new_adjoin = &a * adjoin;
-                    self.backward(cg, &v1, &new_adjoin);
-                }
-                NaOperAry1::Sum => {
-                    self.backward(cg, &v1, adjoin);
-                }
-            },
-            Node::Ary2 {
-                oper: op,
-                arg1: v1,
-                arg2: v2,
-                ..
-            } => match op {
-                NaOperAry2::Add => {
-                    self.backward(cg, &v1, adjoin);
-                    self.backward(cg, &v2, adjoin);
-                }
-                NaOperAry2::Sub => {
-                    self.backward(cg, &v1, adjoin);
-                    self.backward(cg, &v2, &(adjoin * &MatrixF32::V(-1.0)));
-                }
-                NaOperAry2::MulComp => {
-                    let v1_p = cg.primal(&v1);
-                    let v2_p = cg.primal(&v2);
-                    self.backward(cg, &v1, &(adjoin * &v2_p));
-                    self.backward(cg, &v2, &(adjoin * &v1_p));
-                }
-                NaOperAry2::Conv2d => todo!(),
-            },
+    ) -> (MatrixF32, MatrixF32) {
+        // A single value adjoin of a matrix result stands for each of its elements, which the broadcast operands
+        // have to be summed over.
+        let expanded;
+        let adjoin = match (adjoin, primal) {
+            (MatrixF32::V(v), MatrixF32::M(p)) => {
+                expanded = MatrixF32::new_m(nd::ArrayD::from_elem(p.shape(), *v));
+                &expanded
+            }
+            _ => adjoin,
+        };
+        match oper {
+            NaOperAry2::Add => (
+                unbroadcast(adjoin.clone(), a),
+                unbroadcast(adjoin.clone(), b),
+            ),
+            NaOperAry2::Sub => (
+                unbroadcast(adjoin.clone(), a),
+                unbroadcast(adjoin.clone() * -1.0, b),
+            ),
+            NaOperAry2::MulComp => (unbroadcast(adjoin * b, a), unbroadcast(adjoin * a, b)),
+            NaOperAry2::MatMul => {
+                let (a, b) = matrix_operands(oper, a, b);
+                let adjoin = match adjoin {
+                    MatrixF32::M(m) => m.as_ref().clone(),
+                    MatrixF32::V(v) => {
+                        let shape = matmul_shape(a.shape(), b.shape()).unwrap();
+                        nd::ArrayD::from_elem(shape, *v)
+                    }
+                };
+                let (da, db) = matmul_adjoin(a, b, &adjoin);
+                (MatrixF32::new_m(da), MatrixF32::new_m(db))
+            }
+            NaOperAry2::Conv2d => {
+                let (Some(primal), Some(kernel), Some(adjoin)) = (a.m(), b.m(), adjoin.m()) else {
+                    panic!(
+                        "Expected matrices as input, kernel and adjoin of Conv2d but got {:?}, {:?} and {:?}",
+                        a, b, adjoin
+                    )
+                };
+                let (dv_da, dv_dk) = conv2d_adjoin(&view2(primal), &view2(kernel), &view2(adjoin));
+                (
+                    MatrixF32::new_m(dv_da.into_dyn()),
+                    MatrixF32::new_m(dv_dk.into_dyn()),
+                )
+            }
+            NaOperAry2::Gt | NaOperAry2::Lt => (a.clone() * 0.0, b.clone() * 0.0),
+            NaOperAry2::Where => (
+                unbroadcast(
+                    zip_map(adjoin, b, |adjoin, b| if b != 0.0 { adjoin } else { 0.0 }),
+                    a,
+                ),
+                b.clone() * 0.0,
+            ),
+            NaOperAry2::WhereNot => (
+                unbroadcast(
+                    zip_map(adjoin, b, |adjoin, b| if b == 0.0 { adjoin } else { 0.0 }),
+                    a,
+                ),
+                b.clone() * 0.0,
+            ),
+            NaOperAry2::Div => (
+                unbroadcast(zip_map(adjoin, b, |adjoin, b| adjoin / b), a),
+                unbroadcast(
+                    zip_map(&(adjoin * a), b, |adjoin_a, b| -adjoin_a / (b * b)),
+                    b,
+                ),
+            ),
+            NaOperAry2::Concat(axis) => {
+                let (a, b) = matrix_operands(oper, a, b);
+                let adjoin = match adjoin {
+                    MatrixF32::M(m) => m.as_ref().clone(),
+                    MatrixF32::V(v) => nd::ArrayD::from_elem(
+                        concat_shape(*axis, a.shape(), b.shape()).unwrap(),
+                        *v,
+                    ),
+                };
+                let (da, db) = concat_adjoin(*axis, a, &adjoin);
+                (MatrixF32::new_m(da), MatrixF32::new_m(db))
+            }
+            NaOperAry2::SoftmaxCrossEntropy(reduction) => {
+                let logits = logits_operand(a);
+                let adjoin = adjoin.v().unwrap_or_else(|| {
+                    panic!("Expected single value adjoin of loss, got {:?}", adjoin)
+                });
+                let scale = adjoin / ce_divisor(reduction, logits);
+                let log_p = softmax(logits.ndim() - 1, true, logits);
+                let labels = one_hot_labels(logits, b);
+                let d_logits = (log_p.mapv(f32::exp) - &labels) * scale;
+                // Only the probabilities get a gradient, the class indices do not.
+                let d_labels = match b {
+                    MatrixF32::M(m) if m.shape() == logits.shape() => {
+                        MatrixF32::new_m(log_p * -scale)
+                    }
+                    _ => b.clone() * 0.0,
+                };
+                (MatrixF32::new_m(d_logits), d_labels)
+            }
+            NaOperAry2::Loss(loss, reduction) => {
+                let adjoin = adjoin.v().unwrap_or_else(|| {
+                    panic!("Expected single value adjoin of loss, got {:?}", adjoin)
+                });
+                let scale = adjoin / loss_divisor(loss, reduction, a, b);
+                (
+                    unbroadcast(zip_map(a, b, |y, t| loss.derivative(y, t).0) * scale, a),
+                    unbroadcast(zip_map(a, b, |y, t| loss.derivative(y, t).1) * scale, b),
+                )
+            }
+        }
+    }
+}
+
+fn matrix_operands<'m>(
+    oper: &NaOperAry2,
+    a: &'m MatrixF32,
+    b: &'m MatrixF32,
+) -> (&'m nd::ArrayD<f32>, &'m nd::ArrayD<f32>) {
+    match (a.m(), b.m()) {
+        (Some(a), Some(b)) => (a, b),
+        _ => panic!(
+            "Expected matrices as operands of {:?} but got {:?} and {:?}",
+            oper, a, b
+        ),
+    }
+}
+
+fn layout_operand<'m>(oper: &NaOperAry1, a: &'m MatrixF32) -> &'m nd::ArrayD<f32> {
+    a.m()
+        .unwrap_or_else(|| panic!("Expected matrix as input to {} but got {:?}", oper, a))
+}
+
+/// View a matrix of the convolution as 2d.
+fn view2(m: &nd::ArrayD<f32>) -> nd::ArrayView2<'_, f32> {
+    m.view()
+        .into_dimensionality::<nd::Ix2>()
+        .unwrap_or_else(|_| panic!("Expected 2d matrix in Conv2d but got shape {:?}", m.shape()))
+}
+
+/// The number the summed loss is divided by: the number of samples for [Reduction::Mean], 1 for [Reduction::Sum].
+/// For [Loss::CrossEntropy] the classes along the last axis belong to the same sample.
+fn loss_divisor(loss: &Loss, reduction: &Reduction, a: &MatrixF32, b: &MatrixF32) -> f32 {
+    let shape = match (reduction, a.m(), b.m()) {
+        (Reduction::Sum, ..) | (Reduction::Mean, None, None) => return 1.0,
+        (Reduction::Mean, Some(m), None) | (Reduction::Mean, None, Some(m)) => m.shape().to_vec(),
+        (Reduction::Mean, Some(ma), Some(mb)) => broadcast_shape(ma.shape(), mb.shape()).unwrap(),
+    };
+    let n: usize = match loss {
+        Loss::CrossEntropy => shape[..shape.len().saturating_sub(1)].iter().product(),
+        _ => shape.iter().product(),
+    };
+    n.max(1) as f32
+}
+
+fn logits_operand(a: &MatrixF32) -> &nd::ArrayD<f32> {
+    a.m().unwrap_or_else(|| {
+        panic!(
+            "Expected matrix as logits of softmax cross-entropy but got {:?}",
+            a
+        )
+    })
+}
+
+/// The number the summed softmax cross-entropy is divided by: the number of samples for [Reduction::Mean].
+fn ce_divisor(reduction: &Reduction, logits: &nd::ArrayD<f32>) -> f32 {
+    match reduction {
+        Reduction::Mean => n_samples(logits).max(1) as f32,
+        Reduction::Sum => 1.0,
+    }
+}
+
+/// The [FloatOperAry1] computing the element-wise `oper` on each element.
+fn scalar_oper(oper: &NaOperAry1) -> FloatOperAry1 {
+    match oper {
+        NaOperAry1::Exp => FloatOperAry1::Exp,
+        NaOperAry1::Ln => FloatOperAry1::Ln,
+        NaOperAry1::Sin => FloatOperAry1::Sin,
+        NaOperAry1::Cos => FloatOperAry1::Cos,
+        NaOperAry1::Tanh => FloatOperAry1::Tanh,
+        NaOperAry1::Sigmoid => FloatOperAry1::Sigmoid,
+        NaOperAry1::Sqrt => FloatOperAry1::Sqrt,
+        NaOperAry1::Abs => FloatOperAry1::Abs,
+        NaOperAry1::LeakyRelu(alpha) => FloatOperAry1::LeakyRelu(*alpha),
+        NaOperAry1::Softplus => FloatOperAry1::Softplus,
+        _ => unreachable!("{} is not an element-wise operator", oper),
+    }
+}
+
+/// Apply `f` to each element.
+fn map(a: &MatrixF32, f: impl Fn(f32) -> f32) -> MatrixF32 {
+    match a {
+        MatrixF32::M(m) => MatrixF32::new_m(m.mapv(f)),
+        MatrixF32::V(v) => MatrixF32::V(f(*v)),
+    }
+}
+
+/// Apply `f` element-wise, broadcasting the operands to a common shape. A single value is used for all the
+/// elements of the matrix.
+fn zip_map(a: &MatrixF32, b: &MatrixF32, f: impl Fn(f32, f32) -> f32) -> MatrixF32 {
+    match (a, b) {
+        (MatrixF32::M(m1), MatrixF32::M(m2)) => {
+            let shape = broadcast_shape(m1.shape(), m2.shape()).unwrap_or_else(|e| panic!("{}", e));
+            MatrixF32::new_m(
+                nd::Zip::from(m1.broadcast(shape.clone()).unwrap())
+                    .and(m2.broadcast(shape).unwrap())
+                    .map_collect(|a, b| f(*a, *b)),
+            )
         }
+        (MatrixF32::M(m), MatrixF32::V(v)) => MatrixF32::new_m(m.mapv(|a| f(a, *v))),
+        (MatrixF32::V(v), MatrixF32::M(m)) => MatrixF32::new_m(m.mapv(|b| f(*v, b))),
+        (MatrixF32::V(v1), MatrixF32::V(v2)) => MatrixF32::V(f(*v1, *v2)),
+    }
+}
+
+/// Sum the `adjoin` over the axes `operand` was broadcast along, so the adjoin has the shape of the operand.
+/// A single value adjoin stands for all the elements, and is spread over a matrix operand.
+fn unbroadcast(adjoin: MatrixF32, operand: &MatrixF32) -> MatrixF32 {
+    match (adjoin, operand) {
+        (MatrixF32::M(m), MatrixF32::V(_)) => MatrixF32::V(m.sum()),
+        (MatrixF32::M(m), MatrixF32::M(o)) if m.shape() != o.shape() => {
+            // The adjoin may miss some of the axes too, if it was partly a single value.
+            let shape = broadcast_shape(m.shape(), o.shape()).unwrap();
+            MatrixF32::new_m(reduce_to_shape(m.broadcast(shape).unwrap(), o.shape()))
+        }
+        (MatrixF32::V(v), MatrixF32::M(o)) => MatrixF32::new_m(nd::ArrayD::from_elem(o.shape(), v)),
+        (adjoin, _) => adjoin,
+    }
+}
+
+fn mask(cond: bool) -> f32 {
+    if cond {
+        1.0
+    } else {
+        0.0
     }
 }
 
@@ -266,7 +574,7 @@ impl ops::Mul for &MatrixF32 {
     fn mul(self, b: Self) -> Self::Output {
         let a = self;
         match (a, b) {
-            (MatrixF32::M(m1), MatrixF32::M(m2)) => MatrixF32::new_m(m1.as_ref() * m2.as_ref()),
+            (MatrixF32::M(_), MatrixF32::M(_)) => zip_map(a, b, |a, b| a * b),
             (MatrixF32::M(m), MatrixF32::V(v)) => MatrixF32::new_m(m.as_ref() * (*v)),
             (MatrixF32::V(v), MatrixF32::M(m)) => MatrixF32::new_m(m.as_ref() * (*v)),
             (MatrixF32::V(v1), MatrixF32::V(v2)) => MatrixF32::V(v1 * v2),
@@ -279,7 +587,8 @@ mod tests {
     use super::MatrixCalculator;
     use crate::{
         compute::ComputGraph,
-        core_syntax::ExprBuilder,
+        core_syntax::{AsConst, Expr, ExprBuilder},
+        losses::{Loss, Reduction},
         nar::syntax::{MatrixF32, NaOperAry1, NaOperAry2},
     };
     use ndarray as nd;
@@ -323,6 +632,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn clamp() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let low = eb.new_variable("low");
+        let high = eb.new_variable("high");
+        let y = x.clamp(low, high);
+
+        let [x, low, high, y] = [x, low, high, y].map(|p| p.ident);
+        let mut cb = new_cb(eb);
+        cb.set_variable(
+            &x,
+            nd::ArrayD::from_shape_vec(sh2x2(), vec![-1.0, 0.5, 2.0, 3.0])
+                .unwrap()
+                .into(),
+        );
+        cb.set_variable(&low, MatrixF32::V(0.0));
+        cb.set_variable(&high, MatrixF32::V(1.0));
+        let primal = cb.forward(&y);
+        assert_eq!(
+            primal.m(),
+            Some(&nd::ArrayD::from_shape_vec(sh2x2(), vec![0.0, 0.5, 1.0, 1.0]).unwrap())
+        );
+        cb.backward(&y);
+        assert_eq!(
+            cb.adjoin(&x).unwrap().m(),
+            Some(&nd::ArrayD::from_shape_vec(sh2x2(), vec![0.0, 1.0, 0.0, 0.0]).unwrap())
+        );
+        // `high` is a single value broadcast to both of the clamped elements.
+        assert_eq!(cb.adjoin(&high).unwrap().v(), Some(2.0));
+    }
+
     #[test]
     fn forward_add_sub_mul_mixed() {
         let eb = new_eb();
@@ -343,6 +684,63 @@ mod tests {
         assert_eq!(y.m(), Some(&nd::ArrayD::from_elem(sh2x2(), expected)));
     }
 
+    #[test]
+    fn div_neg() {
+        let eb = new_eb();
+        let a = eb.new_variable("a");
+        let b = eb.new_variable("b");
+        let y = -a / b + 1.0;
+        let [a, b, y] = [a, b, y].map(|p| p.ident);
+        let mut cb = new_cb(eb);
+        cb.set_variable(
+            &a,
+            nd::ArrayD::from_shape_vec(sh2x2(), vec![1.0, 2.0, 3.0, 4.0])
+                .unwrap()
+                .into(),
+        );
+        cb.set_variable(&b, nd::ArrayD::from_elem(sh2x2(), 2.0_f32).into());
+        let expected = nd::ArrayD::from_shape_vec(sh2x2(), vec![0.5, 0.0, -0.5, -1.0]).unwrap();
+        assert_eq!(cb.forward(&y).m(), Some(&expected));
+
+        cb.backward(&y);
+        assert_eq!(
+            cb.adjoin(&a).unwrap().m(),
+            Some(&nd::ArrayD::from_elem(sh2x2(), -0.5))
+        );
+        let expected = nd::ArrayD::from_shape_vec(sh2x2(), vec![0.25, 0.5, 0.75, 1.0]).unwrap();
+        assert_eq!(cb.adjoin(&b).unwrap().m(), Some(&expected));
+    }
+
+    #[test]
+    fn loss_reduction() {
+        let eb = new_eb();
+        let y = eb.new_variable("y");
+        let t = eb.new_variable("t");
+        let mean = y.loss(t, Loss::Mse, Reduction::Mean);
+        let sum = y.loss(t, Loss::CrossEntropy, Reduction::Sum);
+        let ce = y.loss(t, Loss::CrossEntropy, Reduction::Mean);
+        assert_eq!("(y mse t)", format!("{}", mean));
+        assert_eq!("(y sum_cross_entropy t)", format!("{}", sum));
+        let [y, t, mean, sum, ce] = [y, t, mean, sum, ce].map(|p| p.ident);
+        let mut cb = new_cb(eb);
+        let m = |v: Vec<f32>| MatrixF32::from(nd::ArrayD::from_shape_vec(sh2x2(), v).unwrap());
+        cb.set_variable(&y, m(vec![0.5, 0.5, 0.25, 0.75]));
+        cb.set_variable(&t, m(vec![1.0, 0.0, 0.0, 1.0]));
+
+        assert_eq!(
+            Some((0.25 + 0.25 + 0.0625 + 0.0625) / 4.0),
+            cb.forward(&mean).v()
+        );
+        cb.backward(&mean);
+        let expected = m(vec![-0.25, 0.25, 0.125, -0.125]);
+        assert_eq!(Some(expected), cb.adjoin(&y));
+
+        let expected = -(0.5_f32.ln() + 0.75_f32.ln());
+        assert_eq!(Some(expected), cb.forward(&sum).v());
+        // Two samples with two classes each.
+        assert_eq!(Some(expected / 2.0), cb.forward(&ce).v());
+    }
+
     #[test]
     fn forward_conv() {
         let eb = new_eb();
@@ -372,6 +770,223 @@ mod tests {
         );
     }
 
+    #[test]
+    fn backward_conv() {
+        let eb = new_eb();
+        let a = eb.new_variable("a");
+        let k = eb.new_variable("k");
+        let y = a.conv2d(k).powi(2).sum();
+        let [a, k, y] = [a, k, y].map(|p| p.ident);
+        let mut cb = new_cb(eb);
+        let a_m = nd::ArrayD::from_shape_fn(sh(5, 4), |ix| {
+            (ix[0] as f32 - 2.0) * 0.3 + ix[1] as f32 * 0.1
+        });
+        let k_m =
+            nd::ArrayD::from_shape_vec(sh(2, 3), vec![0.5, -1.0, 0.25, 1.5, 0.0, -0.75]).unwrap();
+        cb.set_variable(&a, a_m.clone().into());
+        cb.set_variable(&k, k_m.clone().into());
+        cb.forward(&y);
+        cb.backward(&y);
+        let da = cb.adjoin(&a).unwrap().m().unwrap().clone();
+        let dk = cb.adjoin(&k).unwrap().m().unwrap().clone();
+
+        // Check against the central finite difference.
+        let h = 1e-2;
+        let mut value = |a_m: &nd::ArrayD<f32>, k_m: &nd::ArrayD<f32>| {
+            cb.reset_primal_of_variable(&a, a_m.clone().into());
+            cb.reset_primal_of_variable(&k, k_m.clone().into());
+            cb.reset_computed_primals();
+            cb.forward(&y).v().unwrap()
+        };
+        for (ix, expected) in da.indexed_iter() {
+            let (mut a_p, mut a_n) = (a_m.clone(), a_m.clone());
+            a_p[&ix] += h;
+            a_n[&ix] -= h;
+            let fd = (value(&a_p, &k_m) - value(&a_n, &k_m)) / (2.0 * h);
+            assert!(
+                (fd - expected).abs() < 1e-2,
+                "da at {:?}: {} vs {}",
+                ix,
+                expected,
+                fd
+            );
+        }
+        for (ix, expected) in dk.indexed_iter() {
+            let (mut k_p, mut k_n) = (k_m.clone(), k_m.clone());
+            k_p[&ix] += h;
+            k_n[&ix] -= h;
+            let fd = (value(&a_m, &k_p) - value(&a_m, &k_n)) / (2.0 * h);
+            assert!(
+                (fd - expected).abs() < 1e-2,
+                "dk at {:?}: {} vs {}",
+                ix,
+                expected,
+                fd
+            );
+        }
+    }
+
+    #[test]
+    fn matmul_dense() {
+        let eb = new_eb();
+        let w = eb.new_variable("w");
+        let x = eb.new_variable("x");
+        let y = w.matmul(x);
+        let s = y.sum();
+        let d = x.matmul(x);
+        let [w, x, y, s, d] = [w, x, y, s, d].map(|p| p.ident);
+        let mut cb = new_cb(eb);
+        cb.set_variable(&w, nd::arr2(&[[1.0, 2.0, 3.0], [-1.0, 0.0, 0.5]]).into());
+        cb.set_variable(&x, nd::arr1(&[2.0, -1.0, 4.0]).into());
+        assert_eq!(cb.forward(&y).m(), Some(&nd::arr1(&[12.0, 0.0]).into_dyn()));
+        assert_eq!(cb.forward(&d).v(), Some(21.0));
+
+        // The adjoin of the sum is a single value, spread over the whole product.
+        cb.forward(&s);
+        cb.backward(&s);
+        assert_eq!(
+            cb.adjoin(&w).unwrap().m(),
+            Some(&nd::arr2(&[[2.0, -1.0, 4.0], [2.0, -1.0, 4.0]]).into_dyn())
+        );
+        assert_eq!(
+            cb.adjoin(&x).unwrap().m(),
+            Some(&nd::arr1(&[0.0, 2.0, 3.5]).into_dyn())
+        );
+    }
+
+    #[test]
+    fn broadcast_bias() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let col = eb.new_variable("col");
+        let row = eb.new_variable("row");
+        let y = ((x + col) * row).sum();
+        let [x, col, row, y] = [x, col, row, y].map(|p| p.ident);
+        let mut cb = new_cb(eb);
+        cb.set_variable(&x, nd::arr2(&[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]).into());
+        cb.set_variable(&col, nd::arr2(&[[10.0], [20.0]]).into());
+        cb.set_variable(&row, nd::arr1(&[1.0, 0.0, -1.0]).into());
+        assert_eq!(cb.forward(&y).v(), Some(-4.0));
+        cb.backward(&y);
+
+        // Each gradient is summed over the axes its variable was broadcast along.
+        assert_eq!(
+            cb.adjoin(&x).unwrap().m(),
+            Some(&nd::arr2(&[[1.0, 0.0, -1.0], [1.0, 0.0, -1.0]]).into_dyn())
+        );
+        assert_eq!(
+            cb.adjoin(&col).unwrap().m(),
+            Some(&nd::arr2(&[[0.0], [0.0]]).into_dyn())
+        );
+        assert_eq!(
+            cb.adjoin(&row).unwrap().m(),
+            Some(&nd::arr1(&[35.0, 37.0, 39.0]).into_dyn())
+        );
+
+        // A single valued adjoin is spread over the whole operand.
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let col = eb.new_variable("col");
+        let y = (x + col).sum();
+        let [x, col, y] = [x, col, y].map(|p| p.ident);
+        let mut cb = new_cb(eb);
+        cb.set_variable(&x, nd::arr2(&[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]).into());
+        cb.set_variable(&col, nd::arr2(&[[10.0], [20.0]]).into());
+        assert_eq!(cb.forward(&y).v(), Some(111.0));
+        cb.backward(&y);
+        assert_eq!(cb.adjoin(&x).unwrap().m().unwrap().shape(), [2, 3]);
+        assert_eq!(
+            cb.adjoin(&col).unwrap().m(),
+            Some(&nd::arr2(&[[3.0], [3.0]]).into_dyn())
+        );
+    }
+
+    #[test]
+    fn reduce_axes() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        // Normalize each row, then weight the columns.
+        let normalized = (x - x.mean(&[1], true)) / x.std(&[1], true);
+        let w = eb.new_variable("w");
+        let y = (normalized * w).sum_axis(&[1], false).max_axis(&[], false);
+        assert_eq!(
+            "max[](sum[1]((((x - mean_keepdims[1](x)) ./ std_keepdims[1](x)) .* w)))",
+            format!("{}", y)
+        );
+        let [x, w, normalized, y] = [x, w, normalized, y].map(|p| p.ident);
+        let mut cb = new_cb(eb);
+        cb.set_variable(&x, nd::arr2(&[[1.0, 2.0, 3.0], [0.0, 0.0, 6.0]]).into());
+        cb.set_variable(&w, nd::arr1(&[-1.0, 0.0, 1.0]).into());
+        let n = cb.forward(&normalized);
+        let s = 1.5_f32.sqrt();
+        let expected = nd::arr2(&[
+            [-s, 0.0, s],
+            [-1.0 / 2.0_f32.sqrt(), -1.0 / 2.0_f32.sqrt(), 2.0_f32.sqrt()],
+        ]);
+        let err = (n.m().unwrap() - &expected.into_dyn()).mapv(f32::abs).sum();
+        assert!(err < 1e-5, "{}", n);
+        // The first row is the maximum.
+        assert!((cb.forward(&y).v().unwrap() - 2.0 * s).abs() < 1e-5);
+        cb.backward(&y);
+
+        // The gradient goes only to the row of the maximum, and it does not change the normalized row, so it's
+        // orthogonal to a shift and to a scaling of the row.
+        let dx = cb.adjoin(&x).unwrap();
+        let dx = dx.m().unwrap();
+        let dx0 = dx.index_axis(nd::Axis(0), 0);
+        assert!(dx0.sum().abs() < 1e-5, "{}", dx);
+        assert!(
+            (&dx0 * &nd::arr1(&[1.0, 2.0, 3.0])).sum().abs() < 1e-5,
+            "{}",
+            dx
+        );
+        assert_eq!(dx.index_axis(nd::Axis(0), 1).sum(), 0.0);
+        let dw = cb.adjoin(&w).unwrap();
+        let err = (dw.m().unwrap() - &nd::arr1(&[-s, 0.0, s]).into_dyn())
+            .mapv(f32::abs)
+            .sum();
+        assert!(err < 1e-5, "{}", dw);
+    }
+
+    #[test]
+    fn softmax_cross_entropy() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let indices = eb.new_variable("indices");
+        let one_hot = eb.new_variable("one_hot");
+        let fused = x.softmax_cross_entropy(indices, Reduction::Mean);
+        let fused_one_hot = x.softmax_cross_entropy(one_hot, Reduction::Mean);
+        let composed = (x.log_softmax(1) * one_hot).sum() * (-0.5);
+        let p = x.softmax(1);
+        let [x, indices, one_hot, fused, fused_one_hot, composed, p] =
+            [x, indices, one_hot, fused, fused_one_hot, composed, p].map(|e| e.ident);
+        let mut cb = new_cb(eb);
+        cb.set_variable(&x, nd::arr2(&[[1.0, 2.0, 3.0], [0.5, -1.0, 30.0]]).into());
+        cb.set_variable(&indices, nd::arr1(&[0.0, 2.0]).into());
+        cb.set_variable(
+            &one_hot,
+            nd::arr2(&[[1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]).into(),
+        );
+
+        let expected = cb.forward(&composed).v().unwrap();
+        assert!((cb.forward(&fused).v().unwrap() - expected).abs() < 1e-6);
+        assert!((cb.forward(&fused_one_hot).v().unwrap() - expected).abs() < 1e-6);
+
+        cb.backward(&fused);
+        let y = nd::arr2(&[[1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]).into_dyn();
+        let expected = (cb.forward(&p).m().unwrap() - &y) * 0.5;
+        let dx = cb.adjoin(&x).unwrap();
+        assert!(
+            (dx.m().unwrap() - &expected).mapv(f32::abs).sum() < 1e-6,
+            "{}",
+            dx
+        );
+        assert_eq!(
+            cb.adjoin(&indices).unwrap().m(),
+            Some(&nd::arr1(&[0.0, 0.0]).into_dyn())
+        );
+    }
+
     #[test]
     fn backward_add_mul() {
         let eb = new_eb();
@@ -464,6 +1079,78 @@ mod tests {
         assert_eq!(cb.adjoin(&a).unwrap().v(), Some(3.0 * 3.0_f32.powi(2)));
     }
 
+    #[test]
+    fn backward_elementwise() {
+        type ExprMatrix<'a> = Expr<'a, MatrixF32, NaOperAry1, NaOperAry2>;
+        type Unary = for<'a> fn(&ExprMatrix<'a>) -> ExprMatrix<'a>;
+        let opers: [(&str, Unary); 10] = [
+            ("exp", |a| a.exp()),
+            ("ln", |a| a.ln()),
+            ("sin", |a| a.sin()),
+            ("cos", |a| a.cos()),
+            ("tanh", |a| a.tanh()),
+            ("sigmoid", |a| a.sigmoid()),
+            ("sqrt", |a| a.sqrt()),
+            ("abs", |a| a.abs()),
+            ("leaky_relu", |a| a.leaky_relu(0.1)),
+            ("softplus", |a| a.softplus()),
+        ];
+        // Positive, so `ln` and `sqrt` are defined, except for `abs` and `leaky_relu`.
+        let positive = nd::ArrayD::from_shape_vec(sh2x2(), vec![0.5, 1.0, 1.5, 3.0]).unwrap();
+        let mixed = nd::ArrayD::from_shape_vec(sh2x2(), vec![-1.5, -0.5, 0.5, 2.0]).unwrap();
+        for (name, oper) in opers {
+            let input = match name {
+                "abs" | "leaky_relu" => &mixed,
+                _ => &positive,
+            };
+            let eb = new_eb();
+            let a = eb.new_variable("a");
+            let weights = nd::ArrayD::from_shape_vec(sh2x2(), vec![1.0, -2.0, 0.5, 3.0])
+                .unwrap()
+                .as_const(&eb);
+            let y = (oper(&a) * weights).sum();
+            let [a, y] = [a, y].map(|p| p.ident);
+            let mut cb = new_cb(eb);
+            let mut value = |x: &nd::ArrayD<f32>| {
+                cb.reset_computed_primals();
+                cb.reset_primal_of_variable(&a, x.clone().into());
+                cb.forward(&y).v().unwrap()
+            };
+            let h = 1e-2;
+            let mut expected = nd::ArrayD::zeros(sh2x2());
+            for (ix, x) in input.indexed_iter() {
+                let (mut x_p, mut x_n) = (input.clone(), input.clone());
+                x_p[&ix] = x + h;
+                x_n[&ix] = x - h;
+                expected[&ix] = (value(&x_p) - value(&x_n)) / (2.0 * h);
+            }
+            value(input);
+            cb.backward(&y);
+            let da = cb.adjoin(&a).unwrap();
+            let max_err = (da.m().unwrap() - &expected)
+                .iter()
+                .fold(0.0_f32, |acc, e| acc.max(e.abs()));
+            assert!(max_err < 1e-2, "{}: {} vs {}", name, da, expected);
+
+            // A single value gets a single value adjoin.
+            let eb = new_eb();
+            let a = eb.new_variable("a");
+            let y = oper(&a);
+            let [a, y] = [a, y].map(|p| p.ident);
+            let mut cb = new_cb(eb);
+            cb.set_variable(&a, MatrixF32::V(input[[1, 1]]));
+            cb.forward(&y);
+            cb.backward(&y);
+            let dv = cb.adjoin(&a).unwrap().v().unwrap();
+            assert!(
+                (dv - expected[[1, 1]] / 3.0).abs() < 1e-2,
+                "{}: {}",
+                name,
+                dv
+            );
+        }
+    }
+
     #[test]
     fn backward_sum_m() {
         let eb = new_eb();
@@ -33,6 +33,30 @@ where
 fn iter_conv2d_slices(
     input_shape: &[usize],
     kernel_shape: &[usize],
+) -> Result<SliceIteratorIx2, BadShapeError> {
+    iter_strided_slices(input_shape, [kernel_shape[0], kernel_shape[1]], [1, 1])
+}
+
+/// Produce iterator that yields the slice indexes of the `window` slid by `stride`, that can be used for pooling.
+/// The windows that would reach past the input are skipped.
+pub(crate) fn iter_pool2d_slices(
+    input_shape: &[usize],
+    window: [usize; 2],
+    stride: [usize; 2],
+) -> Result<SliceIteratorIx2, BadShapeError> {
+    if window.contains(&0) || stride.contains(&0) {
+        return Err(BadShapeError::from_string(format!(
+            "Window {:?} and stride {:?} must not be empty",
+            window, stride
+        )));
+    }
+    iter_strided_slices(input_shape, window, stride)
+}
+
+fn iter_strided_slices(
+    input_shape: &[usize],
+    kernel_shape: [usize; 2],
+    stride: [usize; 2],
 ) -> Result<SliceIteratorIx2, BadShapeError> {
     for i in 0..2 {
         if input_shape[i] < kernel_shape[i] {
@@ -43,10 +67,11 @@ fn iter_conv2d_slices(
         }
     }
 
-    let d0_range = [0_usize, (input_shape[0] - kernel_shape[0] + 1)];
-    let d1_range = [0_usize, (input_shape[1] - kernel_shape[1] + 1)];
+    let d0_range = [0_usize, (input_shape[0] - kernel_shape[0]) / stride[0] + 1];
+    let d1_range = [0_usize, (input_shape[1] - kernel_shape[1]) / stride[1] + 1];
     Ok(SliceIteratorIx2 {
-        kernel_shape: [kernel_shape[0], kernel_shape[1]],
+        kernel_shape,
+        stride,
         d0_range,
         d1_range,
         d0_curr: d0_range[0],
@@ -55,14 +80,17 @@ fn iter_conv2d_slices(
 }
 
 /// Given `v = convolute(a, k)`, where `k` is a kernel, calculate `dv/da` and `dv/dk`, returned in that order.
+///
+/// Every cell of the output is a sum of `a[i + m, j + n] * k[m, n]` over the kernel cells, so the adjoin of that
+/// cell flows to `a` multiplied by `k` and to `k` multiplied by `a`. The results have the shapes of `a` and `k`.
 /// # Arguments
 /// * `a` - the input matrix.
 /// * `k` - the kernel matrix.
-/// * `adv` - the adjoin from the upstream (reverse mode).
+/// * `adv` - the adjoin from the upstream (reverse mode), shaped as the output of [conv2d].
 pub fn conv2d_adjoin<A>(
-    a: &nd::Array2<A>,
-    k: &nd::Array2<A>,
-    adv: &nd::Array2<A>,
+    a: &nd::ArrayView2<A>,
+    k: &nd::ArrayView2<A>,
+    adv: &nd::ArrayView2<A>,
 ) -> (nd::Array2<A>, nd::Array2<A>)
 where
     A: ops::Mul<Output = A>,
@@ -71,21 +99,21 @@ where
     A: PartialEq,
 {
     let a_size = a.shape().into_v2d();
-    let mut dv_dk: nd::Array2<A> = nd::Array2::zeros(adv.raw_dim());
+    let mut dv_dk: nd::Array2<A> = nd::Array2::zeros(k.raw_dim());
     let mut dv_da: nd::Array2<A> = nd::Array2::zeros(a.raw_dim());
     for adv_ix in adv.shape().into_v2d().iter() {
         // Iterate over every cell of the adjoin, and calculate what's the contribution of `k` and `a`.
-        if adv[adv_ix.as_ix()] == A::zero() {
+        let adjoin = adv[adv_ix.as_ix()];
+        if adjoin == A::zero() {
             continue;
         }
         for k_ix in k.shape().into_v2d().iter() {
             // Iterate over each kernel cell and at the same time calculate `dv/dk` and `dv/da`.
             if let Some(a_ix) = a_size.contains(adv_ix + k_ix) {
-                let adv_ix = adv_ix.as_ix();
                 let a_ix = a_ix.as_ix();
                 let k_ix = k_ix.as_ix();
-                dv_dk[adv_ix] = dv_dk[adv_ix] + a[a_ix] * adv[adv_ix];
-                dv_da[a_ix] = dv_da[a_ix] + k[k_ix] + adv[adv_ix];
+                dv_dk[k_ix] = dv_dk[k_ix] + a[a_ix] * adjoin;
+                dv_da[a_ix] = dv_da[a_ix] + k[k_ix] * adjoin;
             }
         }
     }
@@ -93,7 +121,7 @@ where
 }
 
 #[derive(Debug)]
-struct BadShapeError(String);
+pub(crate) struct BadShapeError(String);
 
 impl BadShapeError {
     fn from_string(message: String) -> BadShapeError {
@@ -101,6 +129,12 @@ impl BadShapeError {
     }
 }
 
+impl fmt::Display for BadShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 trait V2Helper {
     fn into_v2d(&self) -> V2;
 }
@@ -124,8 +158,10 @@ impl ShapeHelper for V2 {
 
 pub struct SliceIteratorIx2 {
     kernel_shape: [usize; 2],
-    /// Range of the indices (left inclusive, right exclusive) for dimension 0 of the input matrix. The kernel
-    /// is slid along those values.
+    /// Step between the slices along each dimension.
+    stride: [usize; 2],
+    /// Range of the slice positions (left inclusive, right exclusive) along dimension 0 of the input matrix. The
+    /// kernel is slid to the position times the stride.
     d0_range: [usize; 2],
     d1_range: [usize; 2],
     d0_curr: usize,
@@ -143,8 +179,8 @@ impl Iterator for SliceIteratorIx2 {
         if self.d0_curr >= self.d0_range[1] {
             return None;
         }
-        let d0_a = self.d0_curr;
-        let d1_a = self.d1_curr;
+        let d0_a = self.d0_curr * self.stride[0];
+        let d1_a = self.d1_curr * self.stride[1];
         let d0_b = d0_a + self.kernel_shape[0];
         let d1_b = d1_a + self.kernel_shape[1];
         self.d1_curr += 1;
@@ -167,8 +203,8 @@ impl SliceIteratorIx2 {
 mod tests {
     use std::ops;
 
-    use super::conv2d;
-    use super::iter_conv2d_slices;
+    use super::{conv2d, conv2d_adjoin};
+    use super::{iter_conv2d_slices, iter_pool2d_slices};
     use ndarray::{self as nd, arr2};
 
     #[test]
@@ -186,6 +222,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_iter_pool_strided() {
+        let a = new_arr_inc_i32(5, 7);
+        let mut actual_slice_corners: Vec<(i32, i32)> = Vec::new();
+        let slices = iter_pool2d_slices(a.shape(), [2, 3], [2, 2]).unwrap();
+        assert_eq!(slices.output_shape(), [2, 3]);
+        for sl in slices {
+            let a_slice = a.slice(sl);
+            actual_slice_corners.push((a_slice[(0, 0)], a_slice[(1, 2)]));
+        }
+        // The last row and column are not reached by any window.
+        assert_eq!(
+            actual_slice_corners,
+            vec![(0, 9), (2, 11), (4, 13), (14, 23), (16, 25), (18, 27)]
+        );
+        assert!(iter_pool2d_slices(a.shape(), [2, 2], [0, 1]).is_err());
+    }
+
     #[test]
     fn test_fail_iter_on_bad_shapes() {
         assert!(
@@ -235,6 +289,34 @@ mod tests {
         assert_eq!(actual.into_dimensionality::<nd::Ix2>().unwrap(), expected);
     }
 
+    #[test]
+    fn test_conv2d_adjoin() {
+        let a = new_arr_inc_f32(4, 5);
+        let k = nd::arr2(&[[1.0, -2.0], [0.5, 3.0], [-1.0, 0.25]]);
+        let adv =
+            nd::Array2::from_shape_fn(shape2(2, 4), |(ir, ic)| ir as f32 - 0.5 * ic as f32 + 1.0);
+        let (dv_da, dv_dk) = conv2d_adjoin(&a.view(), &k.view(), &adv.view());
+        assert_eq!(dv_da.shape(), a.shape());
+        assert_eq!(dv_dk.shape(), k.shape());
+
+        // The convolution is linear, so the directional derivative `<adv, conv2d(a, k + dk)>` is exact.
+        let loss = |a: &nd::Array2<f32>, k: &nd::Array2<f32>| {
+            let v = conv2d(&a.view().into_dyn().into(), &k.view().into_dyn().into());
+            (v.into_dimensionality::<nd::Ix2>().unwrap() * &adv).sum()
+        };
+        let base = loss(&a, &k);
+        for (ix, expected) in dv_dk.indexed_iter() {
+            let mut k_h = k.clone();
+            k_h[ix] += 1.0;
+            assert_eq!(loss(&a, &k_h) - base, *expected, "dv/dk at {:?}", ix);
+        }
+        for (ix, expected) in dv_da.indexed_iter() {
+            let mut a_h = a.clone();
+            a_h[ix] += 1.0;
+            assert_eq!(loss(&a_h, &k) - base, *expected, "dv/da at {:?}", ix);
+        }
+    }
+
     fn dot<F, const N: usize>(a: [F; N], b: [F; N]) -> F
     where
         F: ops::Add<F, Output = F>,
@@ -13,9 +13,9 @@ impl V2 {
         }
     }
 
-    /// Check if `other` is within the box between point (0, 0) and `self.
+    /// Check if `other` is within the box between point (0, 0) (inclusive) and `self` (exclusive).
     pub fn contains(&self, other: V2) -> Option<V2> {
-        if self.0 >= other.0 && self.1 >= other.1 {
+        if self.0 > other.0 && self.1 > other.1 {
             Some(other)
         } else {
             None
@@ -0,0 +1,229 @@
+//! Operators that change the layout of the elements, but not their values: reshape, transpose, slice, concat...
+//! The backward routes every element of the adjoin back to the position of the element in the input.
+use ndarray::{self as nd, Axis};
+
+use super::syntax::NaOperAry1;
+
+/// Shape of the output of a layout operator applied to an input of shape `a`, or an error for an incompatible
+/// input.
+pub(crate) fn layout_shape(oper: &NaOperAry1, a: &[usize]) -> Result<Vec<usize>, String> {
+    match oper {
+        NaOperAry1::Reshape(dims) => {
+            let dims = dims.as_slice();
+            if dims.iter().product::<usize>() == a.iter().product::<usize>() {
+                Ok(dims.to_vec())
+            } else {
+                Err(format!("cannot reshape {:?} to {:?}", a, dims))
+            }
+        }
+        NaOperAry1::Transpose => Ok(a.iter().rev().copied().collect()),
+        NaOperAry1::Permute(axes) => {
+            let axes = axes.as_slice();
+            let mut sorted = axes.to_vec();
+            sorted.sort();
+            if sorted.into_iter().eq(0..a.len()) {
+                Ok(axes.iter().map(|&axis| a[axis]).collect())
+            } else {
+                Err(format!(
+                    "{:?} is not a permutation of the axes of {:?}",
+                    axes, a
+                ))
+            }
+        }
+        NaOperAry1::Flatten => Ok(vec![a.iter().product()]),
+        NaOperAry1::InsertAxis(axis) => {
+            if *axis > a.len() {
+                return Err(format!("cannot insert axis {} into {:?}", axis, a));
+            }
+            let mut shape = a.to_vec();
+            shape.insert(*axis, 1);
+            Ok(shape)
+        }
+        NaOperAry1::Slice(starts, ends) => {
+            let (starts, ends) = (starts.as_slice(), ends.as_slice());
+            if starts.len() > a.len() {
+                return Err(format!("cannot slice {} axes of {:?}", starts.len(), a));
+            }
+            let mut shape = a.to_vec();
+            for (i, (&start, &end)) in starts.iter().zip(ends).enumerate() {
+                if start > end || end > a[i] {
+                    return Err(format!(
+                        "range {}..{} out of bounds of axis {} of {:?}",
+                        start, end, i, a
+                    ));
+                }
+                shape[i] = end - start;
+            }
+            Ok(shape)
+        }
+        _ => unreachable!("{} is not a layout operator", oper),
+    }
+}
+
+/// Apply a layout operator. Panic on an incompatible input. The result is in the standard layout.
+pub(crate) fn layout_forward(oper: &NaOperAry1, a: &nd::ArrayD<f32>) -> nd::ArrayD<f32> {
+    let shape = layout_shape(oper, a.shape()).unwrap_or_else(|e| panic!("{}", e));
+    match oper {
+        NaOperAry1::Reshape(_) | NaOperAry1::Flatten | NaOperAry1::InsertAxis(_) => {
+            to_shape(a, &shape)
+        }
+        NaOperAry1::Transpose => a.t().as_standard_layout().into_owned(),
+        NaOperAry1::Permute(axes) => a
+            .view()
+            .permuted_axes(axes.as_slice())
+            .as_standard_layout()
+            .into_owned(),
+        NaOperAry1::Slice(starts, ends) => a
+            .slice_each_axis(|ax| match ax.axis.index() {
+                i if i < starts.as_slice().len() => {
+                    nd::Slice::from(starts.as_slice()[i]..ends.as_slice()[i])
+                }
+                _ => nd::Slice::from(..),
+            })
+            .to_owned(),
+        _ => unreachable!("{} is not a layout operator", oper),
+    }
+}
+
+/// Given the `adjoin` of `v = oper(a)`, calculate the adjoin of `a`, shaped as `a`.
+pub(crate) fn layout_backward(
+    oper: &NaOperAry1,
+    a: &nd::ArrayD<f32>,
+    adjoin: &nd::ArrayD<f32>,
+) -> nd::ArrayD<f32> {
+    match oper {
+        NaOperAry1::Reshape(_) | NaOperAry1::Flatten | NaOperAry1::InsertAxis(_) => {
+            to_shape(adjoin, a.shape())
+        }
+        NaOperAry1::Transpose => adjoin.t().as_standard_layout().into_owned(),
+        NaOperAry1::Permute(axes) => {
+            let mut inverse = vec![0; axes.as_slice().len()];
+            for (i, &axis) in axes.as_slice().iter().enumerate() {
+                inverse[axis] = i;
+            }
+            adjoin
+                .view()
+                .permuted_axes(inverse)
+                .as_standard_layout()
+                .into_owned()
+        }
+        NaOperAry1::Slice(starts, ends) => {
+            let mut da = nd::ArrayD::zeros(a.raw_dim());
+            da.slice_each_axis_mut(|ax| match ax.axis.index() {
+                i if i < starts.as_slice().len() => {
+                    nd::Slice::from(starts.as_slice()[i]..ends.as_slice()[i])
+                }
+                _ => nd::Slice::from(..),
+            })
+            .assign(adjoin);
+            da
+        }
+        _ => unreachable!("{} is not a layout operator", oper),
+    }
+}
+
+/// Shape of `a` and `b` joined along `axis`, or an error if the other axes do not match.
+pub(crate) fn concat_shape(axis: usize, a: &[usize], b: &[usize]) -> Result<Vec<usize>, String> {
+    let other_axes_match = (0..a.len()).all(|i| i == axis || a[i] == b[i]);
+    if axis >= a.len() || a.len() != b.len() || !other_axes_match {
+        return Err(format!(
+            "cannot concat {:?} and {:?} along axis {}",
+            a, b, axis
+        ));
+    }
+    let mut shape = a.to_vec();
+    shape[axis] += b[axis];
+    Ok(shape)
+}
+
+/// Join `a` and `b` along `axis`. Panic if the other axes do not match.
+pub(crate) fn concat(axis: usize, a: &nd::ArrayD<f32>, b: &nd::ArrayD<f32>) -> nd::ArrayD<f32> {
+    concat_shape(axis, a.shape(), b.shape()).unwrap_or_else(|e| panic!("{}", e));
+    nd::concatenate(Axis(axis), &[a.view(), b.view()]).unwrap()
+}
+
+/// Given the `adjoin` of `v = concat(a, b)`, split it into the adjoins of `a` and `b`.
+pub(crate) fn concat_adjoin(
+    axis: usize,
+    a: &nd::ArrayD<f32>,
+    adjoin: &nd::ArrayD<f32>,
+) -> (nd::ArrayD<f32>, nd::ArrayD<f32>) {
+    let (da, db) = adjoin.view().split_at(Axis(axis), a.len_of(Axis(axis)));
+    (da.to_owned(), db.to_owned())
+}
+
+fn to_shape(a: &nd::ArrayD<f32>, shape: &[usize]) -> nd::ArrayD<f32> {
+    a.to_shape(shape).unwrap().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{concat, concat_adjoin, layout_backward, layout_forward, layout_shape};
+    use crate::nar::syntax::{Dims, NaOperAry1};
+    use ndarray::{self as nd, arr2};
+
+    #[test]
+    fn shapes() {
+        let a = [2, 3, 4];
+        let shape = |oper| layout_shape(&oper, &a);
+        assert_eq!(
+            shape(NaOperAry1::Reshape(Dims::new(&[6, 4]))),
+            Ok(vec![6, 4])
+        );
+        assert!(shape(NaOperAry1::Reshape(Dims::new(&[5, 4]))).is_err());
+        assert_eq!(shape(NaOperAry1::Transpose), Ok(vec![4, 3, 2]));
+        assert_eq!(
+            shape(NaOperAry1::Permute(Dims::new(&[1, 2, 0]))),
+            Ok(vec![3, 4, 2])
+        );
+        assert!(shape(NaOperAry1::Permute(Dims::new(&[1, 1, 0]))).is_err());
+        assert!(shape(NaOperAry1::Permute(Dims::new(&[1, 0]))).is_err());
+        assert_eq!(shape(NaOperAry1::Flatten), Ok(vec![24]));
+        assert_eq!(shape(NaOperAry1::InsertAxis(3)), Ok(vec![2, 3, 4, 1]));
+        let slice = |starts: &[usize], ends: &[usize]| {
+            shape(NaOperAry1::Slice(Dims::new(starts), Dims::new(ends)))
+        };
+        assert_eq!(slice(&[0, 1], &[1, 3]), Ok(vec![1, 2, 4]));
+        assert_eq!(
+            slice(&[0, 1], &[1, 4]),
+            Err("range 1..4 out of bounds of axis 1 of [2, 3, 4]".to_owned())
+        );
+    }
+
+    #[test]
+    fn forward_backward() {
+        let a =
+            nd::ArrayD::from_shape_fn(vec![2, 3, 4], |ix| (ix[0] * 12 + ix[1] * 4 + ix[2]) as f32);
+        let opers = [
+            NaOperAry1::Reshape(Dims::new(&[4, 6])),
+            NaOperAry1::Transpose,
+            NaOperAry1::Permute(Dims::new(&[2, 0, 1])),
+            NaOperAry1::Flatten,
+            NaOperAry1::InsertAxis(1),
+            NaOperAry1::Slice(Dims::new(&[1, 0]), Dims::new(&[2, 2])),
+        ];
+        for oper in opers {
+            let v = layout_forward(&oper, &a);
+            assert_eq!(v.shape(), layout_shape(&oper, a.shape()).unwrap());
+            // Every element of the output comes from the element of the input with the same value, so the
+            // backward of the output itself puts the values back in place, and zeros elsewhere.
+            let da = layout_backward(&oper, &a, &v);
+            assert_eq!(da.shape(), a.shape());
+            for (x, dx) in a.iter().zip(da.iter()) {
+                assert!(*dx == *x || *dx == 0.0, "{}: {} vs {}", oper, x, dx);
+            }
+            assert_eq!(da.sum(), v.sum(), "{}", oper);
+        }
+        let v = layout_forward(&NaOperAry1::Permute(Dims::new(&[2, 0, 1])), &a);
+        assert_eq!(v[[3, 1, 2]], a[[1, 2, 3]]);
+    }
+
+    #[test]
+    fn concat_split() {
+        let a = arr2(&[[1.0, 2.0], [3.0, 4.0]]).into_dyn();
+        let b = arr2(&[[5.0], [6.0]]).into_dyn();
+        let v = concat(1, &a, &b);
+        assert_eq!(v, arr2(&[[1.0, 2.0, 5.0], [3.0, 4.0, 6.0]]).into_dyn());
+        assert_eq!(concat_adjoin(1, &a, &v), (a, b));
+    }
+}
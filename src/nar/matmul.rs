@@ -0,0 +1,188 @@
+use ndarray::{self as nd, Axis};
+
+/// Shape of `a · b`, or an error for incompatible operands.
+///
+/// Both operands have 1 to 3 dimensions. A vector `(k)` on the left is a row, on the right a column, and its
+/// dimension is dropped from the output, so `(m, k) · (k)` is `(m)` and `(k) · (k)` has no dimensions at all.
+/// 3d operands `(batch, m, k)` are multiplied matrix by matrix; an operand with less dimensions is shared by all
+/// the matrices of the batch.
+pub(crate) fn matmul_shape(a: &[usize], b: &[usize]) -> Result<Vec<usize>, String> {
+    for shape in [a, b] {
+        if shape.is_empty() || shape.len() > 3 {
+            return Err(format!(
+                "matmul expects operands with 1 to 3 dimensions, got {:?} and {:?}",
+                a, b
+            ));
+        }
+    }
+    let (a3, b3) = (as_batch_a(a), as_batch_b(b));
+    if a3[2] != b3[1] {
+        return Err(format!(
+            "matmul inner dimensions of {:?} and {:?} do not match",
+            a, b
+        ));
+    }
+    if a.len() == 3 && b.len() == 3 && a[0] != b[0] {
+        return Err(format!(
+            "matmul batch sizes of {:?} and {:?} do not match",
+            a, b
+        ));
+    }
+    let mut shape = Vec::with_capacity(3);
+    if a.len() == 3 || b.len() == 3 {
+        shape.push(a3[0].max(b3[0]));
+    }
+    if a.len() > 1 {
+        shape.push(a3[1]);
+    }
+    if b.len() > 1 {
+        shape.push(b3[2]);
+    }
+    Ok(shape)
+}
+
+/// Matrix product `a · b`, see [matmul_shape] for the supported shapes. Panic on incompatible operands.
+pub(crate) fn matmul(a: &nd::ArrayD<f32>, b: &nd::ArrayD<f32>) -> nd::ArrayD<f32> {
+    let shape = matmul_shape(a.shape(), b.shape()).unwrap_or_else(|e| panic!("{}", e));
+    let (a3, b3) = (view_a(a), view_b(b));
+    let batch = a3.len_of(Axis(0)).max(b3.len_of(Axis(0)));
+    let mut v = nd::Array3::zeros((batch, a3.shape()[1], b3.shape()[2]));
+    for (i, mut v_i) in v.outer_iter_mut().enumerate() {
+        v_i.assign(&batch_item(a3, i).dot(&batch_item(b3, i)));
+    }
+    v.into_shape_with_order(shape).unwrap()
+}
+
+/// Given `v = a · b`, calculate `dv/da = adjoin · bᵀ` and `dv/db = aᵀ · adjoin`, returned in that order and shaped
+/// as `a` and `b`. The gradient of an operand shared by the batch is summed over the batch.
+pub(crate) fn matmul_adjoin(
+    a: &nd::ArrayD<f32>,
+    b: &nd::ArrayD<f32>,
+    adjoin: &nd::ArrayD<f32>,
+) -> (nd::ArrayD<f32>, nd::ArrayD<f32>) {
+    let (a3, b3) = (view_a(a), view_b(b));
+    let batch = a3.len_of(Axis(0)).max(b3.len_of(Axis(0)));
+    let adjoin = adjoin
+        .view()
+        .into_shape_with_order((batch, a3.shape()[1], b3.shape()[2]))
+        .unwrap();
+    let mut da = nd::Array3::zeros(a3.raw_dim());
+    let mut db = nd::Array3::zeros(b3.raw_dim());
+    for (i, adjoin_i) in adjoin.outer_iter().enumerate() {
+        let mut da_i = da.index_axis_mut(Axis(0), i.min(a3.len_of(Axis(0)) - 1));
+        da_i += &adjoin_i.dot(&batch_item(b3, i).t());
+        let mut db_i = db.index_axis_mut(Axis(0), i.min(b3.len_of(Axis(0)) - 1));
+        db_i += &batch_item(a3, i).t().dot(&adjoin_i);
+    }
+    (
+        da.into_shape_with_order(a.shape()).unwrap(),
+        db.into_shape_with_order(b.shape()).unwrap(),
+    )
+}
+
+/// The shape of the left operand as `(batch, m, k)`.
+fn as_batch_a(a: &[usize]) -> [usize; 3] {
+    match a {
+        [k] => [1, 1, *k],
+        [m, k] => [1, *m, *k],
+        _ => [a[0], a[1], a[2]],
+    }
+}
+
+/// The shape of the right operand as `(batch, k, n)`.
+fn as_batch_b(b: &[usize]) -> [usize; 3] {
+    match b {
+        [k] => [1, *k, 1],
+        [k, n] => [1, *k, *n],
+        _ => [b[0], b[1], b[2]],
+    }
+}
+
+fn view_a(a: &nd::ArrayD<f32>) -> nd::ArrayView3<'_, f32> {
+    a.view()
+        .into_shape_with_order(as_batch_a(a.shape()))
+        .unwrap()
+}
+
+fn view_b(b: &nd::ArrayD<f32>) -> nd::ArrayView3<'_, f32> {
+    b.view()
+        .into_shape_with_order(as_batch_b(b.shape()))
+        .unwrap()
+}
+
+/// The `i`-th matrix of the batch, or the only one if the operand is shared.
+fn batch_item<'a>(m: nd::ArrayView3<'a, f32>, i: usize) -> nd::ArrayView2<'a, f32> {
+    let i = i.min(m.len_of(Axis(0)) - 1);
+    m.index_axis_move(Axis(0), i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matmul, matmul_adjoin, matmul_shape};
+    use ndarray::{self as nd, arr2};
+
+    #[test]
+    fn shapes() {
+        assert_eq!(matmul_shape(&[2, 3], &[3, 4]), Ok(vec![2, 4]));
+        assert_eq!(matmul_shape(&[2, 3], &[3]), Ok(vec![2]));
+        assert_eq!(matmul_shape(&[3], &[3, 4]), Ok(vec![4]));
+        assert_eq!(matmul_shape(&[3], &[3]), Ok(vec![]));
+        assert_eq!(matmul_shape(&[5, 2, 3], &[5, 3, 4]), Ok(vec![5, 2, 4]));
+        assert_eq!(matmul_shape(&[5, 2, 3], &[3, 4]), Ok(vec![5, 2, 4]));
+        assert_eq!(matmul_shape(&[2, 3], &[5, 3, 4]), Ok(vec![5, 2, 4]));
+        assert_eq!(
+            matmul_shape(&[2, 3], &[4, 3]),
+            Err("matmul inner dimensions of [2, 3] and [4, 3] do not match".to_owned())
+        );
+        assert!(matmul_shape(&[5, 2, 3], &[4, 3, 4]).is_err());
+        assert!(matmul_shape(&[1, 5, 2, 3], &[3, 4]).is_err());
+        assert!(matmul_shape(&[], &[3]).is_err());
+    }
+
+    #[test]
+    fn forward() {
+        let a = arr2(&[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]).into_dyn();
+        let b = arr2(&[[1.0, 0.0], [0.0, 1.0], [1.0, -1.0]]).into_dyn();
+        assert_eq!(
+            matmul(&a, &b),
+            arr2(&[[4.0, -1.0], [10.0, -1.0]]).into_dyn()
+        );
+        let x = nd::arr1(&[1.0, 1.0, 2.0]).into_dyn();
+        assert_eq!(matmul(&a, &x), nd::arr1(&[9.0, 21.0]).into_dyn());
+        assert_eq!(matmul(&x, &x), nd::arr0(6.0).into_dyn());
+
+        // Each matrix of the batch is multiplied separately.
+        let a2 = &a * 2.0;
+        let batch = nd::stack(nd::Axis(0), &[a.view(), a2.view()]).unwrap();
+        let v = matmul(&batch, &b);
+        assert_eq!(v.shape(), [2, 2, 2]);
+        assert_eq!(v.index_axis(nd::Axis(0), 1), matmul(&a, &b) * 2.0);
+    }
+
+    #[test]
+    fn adjoin_batch() {
+        let a =
+            nd::Array::from_shape_fn((3, 2, 4), |(i, j, k)| (i + 2 * j) as f32 - 0.5 * k as f32);
+        let b = nd::Array::from_shape_fn((4, 3), |(i, j)| i as f32 * 0.25 - j as f32).into_dyn();
+        let a = a.into_dyn();
+        let adjoin =
+            nd::Array::from_shape_fn((3, 2, 3), |(i, j, k)| (i * j) as f32 - k as f32).into_dyn();
+        let (da, db) = matmul_adjoin(&a, &b, &adjoin);
+        assert_eq!(da.shape(), a.shape());
+        assert_eq!(db.shape(), b.shape());
+
+        // The product is linear in each operand, so the directional derivative `<adjoin, a · (b + db)>` is exact.
+        let value = |a: &nd::ArrayD<f32>, b: &nd::ArrayD<f32>| (matmul(a, b) * &adjoin).sum();
+        let base = value(&a, &b);
+        for (ix, expected) in da.indexed_iter() {
+            let mut a_h = a.clone();
+            a_h[&ix] += 1.0;
+            assert_eq!(value(&a_h, &b) - base, *expected, "da at {:?}", ix);
+        }
+        for (ix, expected) in db.indexed_iter() {
+            let mut b_h = b.clone();
+            b_h[&ix] += 1.0;
+            assert_eq!(value(&a, &b_h) - base, *expected, "db at {:?}", ix);
+        }
+    }
+}
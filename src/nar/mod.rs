@@ -1,4 +1,11 @@
+mod broadcast;
 pub mod calculator;
 mod conv;
 mod conv_iter;
+mod layout;
+mod matmul;
+mod pool;
+mod reduce;
+pub mod shape;
+mod softmax;
 pub mod syntax;
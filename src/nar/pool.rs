@@ -0,0 +1,170 @@
+//! 2d pooling over the last two axes, see [PoolOp]. The leading axes, like the batch and the channels, are pooled
+//! independently. The windows are slid by the same iterator as the convolution, see [iter_pool2d_slices].
+use ndarray as nd;
+
+use super::conv::iter_pool2d_slices;
+use super::syntax::PoolOp;
+
+/// Shape of the output of pooling an input of shape `a` with the `window` slid by `stride`.
+pub(crate) fn pool2d_shape(
+    window: [usize; 2],
+    stride: [usize; 2],
+    a: &[usize],
+) -> Result<Vec<usize>, String> {
+    if a.len() < 2 {
+        return Err(format!("pool2d expects at least 2 dimensions, got {:?}", a));
+    }
+    let (batch, hw) = a.split_at(a.len() - 2);
+    let slices = iter_pool2d_slices(hw, window, stride).map_err(|e| e.to_string())?;
+    Ok([batch, &slices.output_shape()].concat())
+}
+
+/// Pool `a`. Panic on an input smaller than the window.
+pub(crate) fn pool2d(
+    op: &PoolOp,
+    window: [usize; 2],
+    stride: [usize; 2],
+    a: &nd::ArrayD<f32>,
+) -> nd::ArrayD<f32> {
+    let shape = pool2d_shape(window, stride, a.shape()).unwrap_or_else(|e| panic!("{}", e));
+    let mut values = Vec::with_capacity(shape.iter().product());
+    for m in as_matrices(a).outer_iter() {
+        for sl in iter_pool2d_slices(m.shape(), window, stride).unwrap() {
+            let w = m.slice(sl);
+            values.push(match op {
+                PoolOp::Max => w.fold(f32::NEG_INFINITY, |acc, x| acc.max(*x)),
+                PoolOp::Avg => w.mean().unwrap(),
+            });
+        }
+    }
+    nd::ArrayD::from_shape_vec(shape, values).unwrap()
+}
+
+/// Given the `adjoin` of `v = pool2d(a)`, calculate the adjoin of `a`. Max pooling routes the adjoin of a window
+/// to the first maximal element, average pooling spreads it evenly over the window. Overlapping windows add up.
+pub(crate) fn pool2d_backward(
+    op: &PoolOp,
+    window: [usize; 2],
+    stride: [usize; 2],
+    a: &nd::ArrayD<f32>,
+    adjoin: &nd::ArrayD<f32>,
+) -> nd::ArrayD<f32> {
+    let matrices = as_matrices(a);
+    let adjoins = as_matrices(adjoin);
+    let mut da = nd::Array3::zeros(matrices.raw_dim());
+    for ((m, adjoin), mut da) in matrices
+        .outer_iter()
+        .zip(adjoins.outer_iter())
+        .zip(da.outer_iter_mut())
+    {
+        let slices = iter_pool2d_slices(m.shape(), window, stride).unwrap();
+        for (sl, adjoin) in slices.zip(adjoin.iter()) {
+            let mut da_w = da.slice_mut(sl);
+            match op {
+                PoolOp::Max => {
+                    let w = m.slice(sl);
+                    let (argmax, _) =
+                        w.indexed_iter()
+                            .fold(((0, 0), f32::NEG_INFINITY), |acc, (ix, x)| {
+                                if *x > acc.1 {
+                                    (ix, *x)
+                                } else {
+                                    acc
+                                }
+                            });
+                    da_w[argmax] += adjoin;
+                }
+                PoolOp::Avg => da_w += *adjoin / da_w.len() as f32,
+            }
+        }
+    }
+    da.into_shape_with_order(a.shape()).unwrap()
+}
+
+/// View `a` as a stack of the matrices of its last two axes.
+fn as_matrices(a: &nd::ArrayD<f32>) -> nd::CowArray<'_, f32, nd::Ix3> {
+    let (batch, hw) = a.shape().split_at(a.ndim() - 2);
+    let batch: usize = batch.iter().product();
+    a.to_shape((batch, hw[0], hw[1])).unwrap()
+}
+
+/// The axes averaged by global average pooling: the last two.
+pub(crate) fn global_pool_axes(ndim: usize) -> Result<[usize; 2], String> {
+    if ndim < 2 {
+        return Err(format!(
+            "global_avg_pool2d expects at least 2 dimensions, got {}",
+            ndim
+        ));
+    }
+    Ok([ndim - 2, ndim - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pool2d, pool2d_backward, pool2d_shape};
+    use crate::nar::syntax::PoolOp;
+    use ndarray::{self as nd, arr2};
+
+    #[test]
+    fn shapes() {
+        assert_eq!(pool2d_shape([2, 2], [2, 2], &[4, 6]), Ok(vec![2, 3]));
+        assert_eq!(pool2d_shape([2, 2], [2, 2], &[5, 5]), Ok(vec![2, 2]));
+        assert_eq!(
+            pool2d_shape([3, 3], [1, 1], &[8, 3, 5, 5]),
+            Ok(vec![8, 3, 3, 3])
+        );
+        assert_eq!(pool2d_shape([2, 3], [1, 2], &[4, 7]), Ok(vec![3, 3]));
+        assert!(pool2d_shape([3, 3], [1, 1], &[2, 5]).is_err());
+        assert!(pool2d_shape([2, 2], [0, 1], &[4, 4]).is_err());
+        assert!(pool2d_shape([1, 1], [1, 1], &[4]).is_err());
+    }
+
+    #[test]
+    fn forward() {
+        let a = nd::ArrayD::from_shape_fn(vec![4, 4], |ix| (ix[0] * 4 + ix[1]) as f32);
+        assert_eq!(
+            pool2d(&PoolOp::Max, [2, 2], [2, 2], &a),
+            arr2(&[[5.0, 7.0], [13.0, 15.0]]).into_dyn()
+        );
+        assert_eq!(
+            pool2d(&PoolOp::Avg, [2, 2], [2, 2], &a),
+            arr2(&[[2.5, 4.5], [10.5, 12.5]]).into_dyn()
+        );
+
+        // The leading axes are pooled independently.
+        let b = nd::stack(nd::Axis(0), &[a.view(), (-&a).view()]).unwrap();
+        let v = pool2d(&PoolOp::Max, [3, 3], [1, 1], &b);
+        assert_eq!(v.shape(), [2, 2, 2]);
+        assert_eq!(
+            v.index_axis(nd::Axis(0), 1),
+            arr2(&[[0.0, -1.0], [-4.0, -5.0]]).into_dyn()
+        );
+    }
+
+    #[test]
+    fn backward() {
+        let a = arr2(&[[1.0, 3.0, 2.0], [0.0, 3.0, 5.0], [4.0, 1.0, 1.0]]).into_dyn();
+        let adjoin = arr2(&[[1.0, 2.0], [3.0, 4.0]]).into_dyn();
+        // The first window has two maxima 3, and the first of them gets the adjoin. The two right windows
+        // overlap on their maximum 5, so it gets both their adjoins.
+        assert_eq!(
+            pool2d_backward(&PoolOp::Max, [2, 2], [1, 1], &a, &adjoin),
+            arr2(&[[0.0, 1.0, 0.0], [0.0, 0.0, 6.0], [3.0, 0.0, 0.0]]).into_dyn()
+        );
+        assert_eq!(
+            pool2d_backward(&PoolOp::Avg, [2, 2], [1, 1], &a, &adjoin),
+            arr2(&[[0.25, 0.75, 0.5], [1.0, 2.5, 1.5], [0.75, 1.75, 1.0]]).into_dyn()
+        );
+        // The elements out of all the windows get no gradient.
+        assert_eq!(
+            pool2d_backward(
+                &PoolOp::Avg,
+                [2, 2],
+                [2, 2],
+                &a,
+                &nd::arr2(&[[4.0]]).into_dyn()
+            ),
+            arr2(&[[1.0, 1.0, 0.0], [1.0, 1.0, 0.0], [0.0, 0.0, 0.0]]).into_dyn()
+        );
+    }
+}
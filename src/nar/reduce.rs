@@ -0,0 +1,262 @@
+//! Reductions along chosen axes, see [ReduceOp].
+//!
+//! The input is rearranged into lanes: the kept axes are moved to the front and the reduced axes to the back, so
+//! each row of the resulting `(groups, n)` matrix holds the `n` elements reduced to a single output element.
+use ndarray as nd;
+
+use super::syntax::ReduceOp;
+
+/// Shape of the output of a reduction of `axes` of an input of shape `a`. No `axes` means all the axes.
+pub(crate) fn reduce_shape(
+    axes: &[usize],
+    keepdims: bool,
+    a: &[usize],
+) -> Result<Vec<usize>, String> {
+    for (i, axis) in axes.iter().enumerate() {
+        if *axis >= a.len() || axes[..i].contains(axis) {
+            return Err(format!("cannot reduce axes {:?} of {:?}", axes, a));
+        }
+    }
+    let reduced = |i: usize| axes.is_empty() || axes.contains(&i);
+    Ok((0..a.len())
+        .filter_map(|i| match (reduced(i), keepdims) {
+            (false, _) => Some(a[i]),
+            (true, true) => Some(1),
+            (true, false) => None,
+        })
+        .collect())
+}
+
+/// Reduce `axes` of `a`. Panic on bad axes.
+pub(crate) fn reduce_forward(
+    op: &ReduceOp,
+    axes: &[usize],
+    keepdims: bool,
+    a: &nd::ArrayD<f32>,
+) -> nd::ArrayD<f32> {
+    let shape = reduce_shape(axes, keepdims, a.shape()).unwrap_or_else(|e| panic!("{}", e));
+    let (lanes, _) = to_lanes(axes, a);
+    let values: Vec<f32> = lanes.rows().into_iter().map(|row| value(op, row)).collect();
+    nd::ArrayD::from_shape_vec(shape, values).unwrap()
+}
+
+/// Given the `adjoin` of `v = reduce(a)`, with one element per output element, calculate the adjoin of `a`.
+pub(crate) fn reduce_backward(
+    op: &ReduceOp,
+    axes: &[usize],
+    a: &nd::ArrayD<f32>,
+    adjoin: &nd::ArrayD<f32>,
+) -> nd::ArrayD<f32> {
+    let (lanes, permutation) = to_lanes(axes, a);
+    let mut da = nd::Array2::zeros(lanes.raw_dim());
+    for ((row, mut da_row), adjoin) in lanes
+        .rows()
+        .into_iter()
+        .zip(da.rows_mut())
+        .zip(adjoin.iter())
+    {
+        gradient(op, row, *adjoin, da_row.view_mut());
+    }
+    // Undo the permutation of `to_lanes`.
+    let permuted_shape: Vec<usize> = permutation.iter().map(|&axis| a.shape()[axis]).collect();
+    let mut inverse = vec![0; permutation.len()];
+    for (i, &axis) in permutation.iter().enumerate() {
+        inverse[axis] = i;
+    }
+    da.into_shape_with_order(permuted_shape)
+        .unwrap()
+        .permuted_axes(inverse)
+        .as_standard_layout()
+        .into_owned()
+}
+
+/// Rearrange `a` into rows of the elements reduced together. Return the rows and the permutation of the axes.
+fn to_lanes(axes: &[usize], a: &nd::ArrayD<f32>) -> (nd::Array2<f32>, Vec<usize>) {
+    let reduced = |i: &usize| axes.is_empty() || axes.contains(i);
+    let (mut permutation, back): (Vec<usize>, Vec<usize>) =
+        (0..a.ndim()).partition(|i| !reduced(i));
+    let groups: usize = permutation.iter().map(|&i| a.shape()[i]).product();
+    permutation.extend(back);
+    let lanes = a
+        .view()
+        .permuted_axes(permutation.clone())
+        .as_standard_layout()
+        .into_owned();
+    let n = lanes.len() / groups.max(1);
+    (
+        lanes.into_shape_with_order((groups, n)).unwrap(),
+        permutation,
+    )
+}
+
+fn value(op: &ReduceOp, row: nd::ArrayView1<f32>) -> f32 {
+    let n = row.len() as f32;
+    match op {
+        ReduceOp::Sum => row.sum(),
+        ReduceOp::Mean => row.sum() / n,
+        ReduceOp::Max => row.fold(f32::NEG_INFINITY, |acc, x| acc.max(*x)),
+        ReduceOp::Min => row.fold(f32::INFINITY, |acc, x| acc.min(*x)),
+        ReduceOp::Prod => row.product(),
+        ReduceOp::Var => {
+            let mean = row.sum() / n;
+            row.fold(0.0, |acc, x| acc + (x - mean) * (x - mean)) / n
+        }
+        ReduceOp::Std => value(&ReduceOp::Var, row).sqrt(),
+        ReduceOp::LogSumExp => {
+            let max = value(&ReduceOp::Max, row);
+            if max.is_infinite() {
+                return max;
+            }
+            max + row.fold(0.0, |acc, x| acc + (x - max).exp()).ln()
+        }
+    }
+}
+
+/// Write `adjoin * d value(row) / d row` into `out`.
+fn gradient(op: &ReduceOp, row: nd::ArrayView1<f32>, adjoin: f32, mut out: nd::ArrayViewMut1<f32>) {
+    let n = row.len() as f32;
+    match op {
+        ReduceOp::Sum => out.fill(adjoin),
+        ReduceOp::Mean => out.fill(adjoin / n),
+        ReduceOp::Max | ReduceOp::Min => {
+            // The gradient is split evenly between the elements equal to the extreme.
+            let extreme = value(op, row);
+            let count = row.iter().filter(|x| **x == extreme).count() as f32;
+            out.zip_mut_with(&row, |o, x| {
+                *o = if *x == extreme { adjoin / count } else { 0.0 }
+            });
+        }
+        ReduceOp::Prod => {
+            // The product of the other elements, without dividing by a possibly zero element.
+            for i in 0..row.len() {
+                let others = row
+                    .iter()
+                    .enumerate()
+                    .fold(1.0, |acc, (j, x)| if i == j { acc } else { acc * x });
+                out[i] = adjoin * others;
+            }
+        }
+        ReduceOp::Var | ReduceOp::Std => {
+            let mean = row.sum() / n;
+            let scale = match op {
+                ReduceOp::Std => {
+                    let std = value(op, row);
+                    if std == 0.0 {
+                        0.0
+                    } else {
+                        adjoin / (2.0 * std)
+                    }
+                }
+                _ => adjoin,
+            };
+            out.zip_mut_with(&row, |o, x| *o = scale * 2.0 * (x - mean) / n);
+        }
+        ReduceOp::LogSumExp => {
+            let lse = value(op, row);
+            out.zip_mut_with(&row, |o, x| *o = adjoin * (x - lse).exp());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reduce_backward, reduce_forward, reduce_shape};
+    use crate::nar::syntax::ReduceOp;
+    use ndarray::{self as nd, arr1, arr2};
+
+    #[test]
+    fn shapes() {
+        assert_eq!(reduce_shape(&[1], false, &[2, 3, 4]), Ok(vec![2, 4]));
+        assert_eq!(reduce_shape(&[0, 2], true, &[2, 3, 4]), Ok(vec![1, 3, 1]));
+        assert_eq!(reduce_shape(&[], false, &[2, 3]), Ok(vec![]));
+        assert_eq!(reduce_shape(&[], true, &[2, 3]), Ok(vec![1, 1]));
+        assert!(reduce_shape(&[2], false, &[2, 3]).is_err());
+        assert!(reduce_shape(&[1, 1], false, &[2, 3]).is_err());
+    }
+
+    #[test]
+    fn forward() {
+        let a = arr2(&[[1.0, 2.0, 3.0], [4.0, 0.0, -1.0]]).into_dyn();
+        let reduce = |op, axes: &[usize]| reduce_forward(&op, axes, false, &a);
+        assert_eq!(reduce(ReduceOp::Sum, &[1]), arr1(&[6.0, 3.0]).into_dyn());
+        assert_eq!(
+            reduce(ReduceOp::Mean, &[0]),
+            arr1(&[2.5, 1.0, 1.0]).into_dyn()
+        );
+        assert_eq!(reduce(ReduceOp::Max, &[1]), arr1(&[3.0, 4.0]).into_dyn());
+        assert_eq!(reduce(ReduceOp::Min, &[]), nd::arr0(-1.0).into_dyn());
+        assert_eq!(reduce(ReduceOp::Prod, &[1]), arr1(&[6.0, -0.0]).into_dyn());
+        assert_eq!(
+            reduce(ReduceOp::Var, &[0]),
+            arr1(&[2.25, 1.0, 4.0]).into_dyn()
+        );
+        assert_eq!(
+            reduce(ReduceOp::Std, &[0]),
+            arr1(&[1.5, 1.0, 2.0]).into_dyn()
+        );
+        let lse = reduce(ReduceOp::LogSumExp, &[1]);
+        let expected = (1.0_f32.exp() + 2.0_f32.exp() + 3.0_f32.exp()).ln();
+        assert!((lse[[0]] - expected).abs() < 1e-6);
+        assert_eq!(
+            reduce_forward(&ReduceOp::Sum, &[0], true, &a),
+            arr2(&[[5.0, 2.0, 2.0]]).into_dyn()
+        );
+    }
+
+    #[test]
+    fn backward() {
+        let a = nd::ArrayD::from_shape_fn(vec![2, 3, 2], |ix| {
+            ((ix[0] * 6 + ix[1] * 2 + ix[2]) as f32 * 0.7).sin() + 0.1
+        });
+        let adjoin = arr2(&[[1.0, -2.0], [0.5, 3.0]]).into_dyn();
+        let h = 1e-2;
+        let ops = [
+            ReduceOp::Sum,
+            ReduceOp::Mean,
+            ReduceOp::Max,
+            ReduceOp::Min,
+            ReduceOp::Prod,
+            ReduceOp::Var,
+            ReduceOp::Std,
+            ReduceOp::LogSumExp,
+        ];
+        for op in ops {
+            let da = reduce_backward(&op, &[1], &a, &adjoin);
+            assert_eq!(da.shape(), a.shape());
+            let value = |a: &nd::ArrayD<f32>| (reduce_forward(&op, &[1], false, a) * &adjoin).sum();
+            for (ix, expected) in da.indexed_iter() {
+                let (mut a_p, mut a_n) = (a.clone(), a.clone());
+                a_p[&ix] += h;
+                a_n[&ix] -= h;
+                let fd = (value(&a_p) - value(&a_n)) / (2.0 * h);
+                assert!(
+                    (fd - expected).abs() < 1e-2,
+                    "{:?} at {:?}: {} vs {}",
+                    op,
+                    ix,
+                    expected,
+                    fd
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn backward_ties_and_zeros() {
+        let a = arr1(&[2.0, 0.0, 2.0, 3.0]).into_dyn();
+        let adjoin = nd::arr0(1.0).into_dyn();
+        assert_eq!(
+            reduce_backward(&ReduceOp::Min, &[], &a, &adjoin),
+            arr1(&[0.0, 1.0, 0.0, 0.0]).into_dyn()
+        );
+        let b = arr1(&[3.0, 1.0, 3.0]).into_dyn();
+        assert_eq!(
+            reduce_backward(&ReduceOp::Max, &[], &b, &adjoin),
+            arr1(&[0.5, 0.0, 0.5]).into_dyn()
+        );
+        assert_eq!(
+            reduce_backward(&ReduceOp::Prod, &[], &a, &adjoin),
+            arr1(&[0.0, 12.0, 0.0, 0.0]).into_dyn()
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! Static shape inference for [MatrixF32] graphs.
+//!
+//! Shape errors otherwise surface deep inside `ndarray` as panics during `forward`. [infer_shapes] computes the
+//! output shape of every node before the graph is executed, out of the shapes of the parameters, constants and
+//! variables, and reports all the inconsistencies at once.
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    compute::{ComputGraph, Node},
+    core_syntax::Ident,
+};
+
+use super::{
+    broadcast::broadcast_shape,
+    layout::{concat_shape, layout_shape},
+    matmul::matmul_shape,
+    pool::{global_pool_axes, pool2d_shape},
+    reduce::reduce_shape,
+    softmax::{check_axis, check_labels},
+    syntax::{MatrixF32, NaOperAry1, NaOperAry2},
+};
+
+/// Shape of a [MatrixF32] value. A scalar is compatible with any shape in element-wise operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shape {
+    Scalar,
+    Array(Vec<usize>),
+}
+
+impl Shape {
+    pub fn of(value: &MatrixF32) -> Shape {
+        match value {
+            MatrixF32::M(m) => Shape::Array(m.shape().to_vec()),
+            MatrixF32::V(_) => Shape::Scalar,
+        }
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shape::Scalar => write!(f, "scalar"),
+            Shape::Array(dims) => write!(f, "{:?}", dims),
+        }
+    }
+}
+
+/// An inconsistency found by [infer_shapes].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapeError {
+    pub ident: Ident,
+    /// The expression of the node, like `(a + b)`, or the name for the variables and parameters.
+    pub expr: String,
+    pub message: String,
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (node {}): {}", self.expr, self.ident, self.message)
+    }
+}
+
+/// Infer the shapes of all the nodes needed to compute `output`. The shapes of the variables are taken from
+/// `variables`, or from the primals of the variables that are already set. On failure, return all the errors found.
+/// A node whose argument has an error is not checked, so each inconsistency is reported once.
+pub fn infer_shapes(
+    cg: &ComputGraph<MatrixF32, NaOperAry1, NaOperAry2>,
+    output: &dyn AsRef<Ident>,
+    variables: &[(Ident, Shape)],
+) -> Result<BTreeMap<Ident, Shape>, Vec<ShapeError>> {
+    let variables: BTreeMap<Ident, Shape> = variables.iter().cloned().collect();
+    // Collect the nodes needed for the output. The ident order is topological.
+    let mut needed: BTreeMap<Ident, Node<MatrixF32, NaOperAry1, NaOperAry2>> = BTreeMap::new();
+    let mut stack = vec![*output.as_ref()];
+    while let Some(ident) = stack.pop() {
+        if needed.contains_key(&ident) {
+            continue;
+        }
+        let node = cg.get_node(&ident);
+        stack.extend(node.args());
+        needed.insert(ident, node);
+    }
+
+    let mut shapes: BTreeMap<Ident, Shape> = BTreeMap::new();
+    let mut errors: Vec<ShapeError> = Vec::new();
+    for (ident, node) in needed.iter() {
+        let shape = match node {
+            Node::Const(value) => Ok(Shape::of(value)),
+            Node::Variable { name, .. } => variables
+                .get(ident)
+                .cloned()
+                .or_else(|| node.primal_or_const().map(Shape::of))
+                .ok_or_else(|| format!("shape of variable {} is not known", name)),
+            Node::Parameter { .. } => node
+                .primal_or_const()
+                .map(Shape::of)
+                .ok_or_else(|| "parameter is not set".to_owned()),
+            Node::Ary1 { oper, arg1, .. } => match shapes.get(arg1) {
+                Some(a) => infer_ary1(oper, a),
+                None => continue,
+            },
+            Node::Ary2 {
+                oper, arg1, arg2, ..
+            } => match (shapes.get(arg1), shapes.get(arg2)) {
+                (Some(a), Some(b)) => infer_ary2(oper, a, b),
+                _ => continue,
+            },
+        };
+        match shape {
+            Ok(shape) => {
+                shapes.insert(*ident, shape);
+            }
+            Err(message) => errors.push(ShapeError {
+                ident: *ident,
+                expr: format!("{}", cg.expr_builder().expr(ident)),
+                message,
+            }),
+        }
+    }
+    if errors.is_empty() {
+        Ok(shapes)
+    } else {
+        Err(errors)
+    }
+}
+
+fn infer_ary1(oper: &NaOperAry1, a: &Shape) -> Result<Shape, String> {
+    match oper {
+        NaOperAry1::Relu
+        | NaOperAry1::PowI(_)
+        | NaOperAry1::Neg
+        | NaOperAry1::Exp
+        | NaOperAry1::Ln
+        | NaOperAry1::Sin
+        | NaOperAry1::Cos
+        | NaOperAry1::Tanh
+        | NaOperAry1::Sigmoid
+        | NaOperAry1::Sqrt
+        | NaOperAry1::Abs
+        | NaOperAry1::LeakyRelu(_)
+        | NaOperAry1::Softplus => Ok(a.clone()),
+        NaOperAry1::Sum => Ok(Shape::Scalar),
+        NaOperAry1::Softmax(axis) | NaOperAry1::LogSoftmax(axis) => match a {
+            Shape::Array(da) => check_axis(*axis, da).map(|_| a.clone()),
+            Shape::Scalar => Err(format!("{} expects a matrix, got scalar", oper)),
+        },
+        NaOperAry1::Reduce(_, axes, keepdims) => match a {
+            Shape::Array(da) => match reduce_shape(axes.as_slice(), *keepdims, da)? {
+                dims if dims.is_empty() => Ok(Shape::Scalar),
+                dims => Ok(Shape::Array(dims)),
+            },
+            Shape::Scalar => Err(format!("{} expects a matrix, got scalar", oper)),
+        },
+        NaOperAry1::Pool2d(_, window, stride) => match a {
+            Shape::Array(da) => Ok(Shape::Array(pool2d_shape(*window, *stride, da)?)),
+            Shape::Scalar => Err(format!("{} expects a matrix, got scalar", oper)),
+        },
+        NaOperAry1::GlobalAvgPool2d => match a {
+            Shape::Array(da) => match &da[..global_pool_axes(da.len())?[0]] {
+                [] => Ok(Shape::Scalar),
+                dims => Ok(Shape::Array(dims.to_vec())),
+            },
+            Shape::Scalar => Err(format!("{} expects a matrix, got scalar", oper)),
+        },
+        NaOperAry1::Reshape(_)
+        | NaOperAry1::Transpose
+        | NaOperAry1::Permute(_)
+        | NaOperAry1::Flatten
+        | NaOperAry1::InsertAxis(_)
+        | NaOperAry1::Slice(..) => match a {
+            Shape::Array(da) => Ok(Shape::Array(layout_shape(oper, da)?)),
+            Shape::Scalar => Err(format!("{} expects a matrix, got scalar", oper)),
+        },
+    }
+}
+
+fn infer_ary2(oper: &NaOperAry2, a: &Shape, b: &Shape) -> Result<Shape, String> {
+    match oper {
+        NaOperAry2::Add
+        | NaOperAry2::Sub
+        | NaOperAry2::MulComp
+        | NaOperAry2::Div
+        | NaOperAry2::Gt
+        | NaOperAry2::Lt
+        | NaOperAry2::Where
+        | NaOperAry2::WhereNot => match (a, b) {
+            (Shape::Scalar, b) => Ok(b.clone()),
+            (a, Shape::Scalar) => Ok(a.clone()),
+            (Shape::Array(da), Shape::Array(db)) => Ok(Shape::Array(broadcast_shape(da, db)?)),
+        },
+        NaOperAry2::Loss(..) => match (a, b) {
+            (Shape::Array(da), Shape::Array(db)) => broadcast_shape(da, db).map(|_| Shape::Scalar),
+            _ => Ok(Shape::Scalar),
+        },
+        NaOperAry2::SoftmaxCrossEntropy(_) => match (a, b) {
+            (Shape::Array(da), Shape::Array(db)) => {
+                check_labels(da, Some(db)).map(|_| Shape::Scalar)
+            }
+            (Shape::Array(da), Shape::Scalar) => check_labels(da, None).map(|_| Shape::Scalar),
+            _ => Err(format!(
+                "softmax cross-entropy expects matrix logits, got {}",
+                a
+            )),
+        },
+        NaOperAry2::Concat(axis) => match (a, b) {
+            (Shape::Array(da), Shape::Array(db)) => Ok(Shape::Array(concat_shape(*axis, da, db)?)),
+            _ => Err(format!("concat expects matrices, got {} and {}", a, b)),
+        },
+        NaOperAry2::MatMul => match (a, b) {
+            (Shape::Array(da), Shape::Array(db)) => match matmul_shape(da, db)? {
+                dims if dims.is_empty() => Ok(Shape::Scalar),
+                dims => Ok(Shape::Array(dims)),
+            },
+            _ => Err(format!("matmul expects matrices, got {} and {}", a, b)),
+        },
+        NaOperAry2::Conv2d => match (a, b) {
+            (Shape::Array(da), Shape::Array(dk)) if da.len() == 2 && dk.len() == 2 => {
+                if da[0] < dk[0] || da[1] < dk[1] {
+                    Err(format!("input {} smaller than kernel {}", a, b))
+                } else {
+                    Ok(Shape::Array(vec![da[0] - dk[0] + 1, da[1] - dk[1] + 1]))
+                }
+            }
+            _ => Err(format!(
+                "conv2d expects 2d input and kernel, got {} and {}",
+                a, b
+            )),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{infer_shapes, Shape};
+    use crate::{
+        compute::ComputGraph,
+        core_syntax::ExprBuilder,
+        nar::{
+            calculator::MatrixCalculator,
+            syntax::{MatrixF32, NaOperAry1, NaOperAry2},
+        },
+    };
+    use ndarray as nd;
+
+    #[test]
+    fn infer() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let k = eb.new_named_parameter("k", MatrixF32::new_m(nd::ArrayD::zeros(vec![2, 2])));
+        let b = eb.new_named_parameter("b", MatrixF32::V(1.0));
+        let c = x.conv2d(k) + b;
+        let y = c.relu().sum();
+        let [x, c, y] = [x, c, y].map(|e| e.ident);
+        let cg = ComputGraph::<MatrixF32, _, _>::new(eb, &MatrixCalculator);
+
+        let shapes = infer_shapes(&cg, &y, &[(x, Shape::Array(vec![4, 3]))]).unwrap();
+        assert_eq!(shapes[&c], Shape::Array(vec![3, 2]));
+        assert_eq!(shapes[&y], Shape::Scalar);
+    }
+
+    #[test]
+    fn broadcast() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let b = eb.new_named_parameter("b", MatrixF32::new_m(nd::ArrayD::zeros(vec![4, 1])));
+        let w = eb.new_named_parameter("w", MatrixF32::new_m(nd::ArrayD::zeros(vec![3])));
+        let y = (x + b) * w;
+        let [x, y] = [x, y].map(|e| e.ident);
+        let cg = ComputGraph::<MatrixF32, _, _>::new(eb, &MatrixCalculator);
+
+        let shapes = infer_shapes(&cg, &y, &[(x, Shape::Array(vec![2, 1, 1]))]).unwrap();
+        assert_eq!(shapes[&y], Shape::Array(vec![2, 4, 3]));
+    }
+
+    #[test]
+    fn report_all_errors() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let u = eb.new_variable("u");
+        let k = eb.new_named_parameter("k", MatrixF32::new_m(nd::ArrayD::zeros(vec![5, 1])));
+        let w = eb.new_named_parameter("w", MatrixF32::new_m(nd::ArrayD::zeros(vec![3, 1])));
+        let y = (x.conv2d(k) + x * w).sum() + u;
+        let [x, y] = [x, y].map(|e| e.ident);
+        let cg = ComputGraph::<MatrixF32, _, _>::new(eb, &MatrixCalculator);
+
+        let errors = infer_shapes(&cg, &y, &[(x, Shape::Array(vec![4, 2]))]).unwrap_err();
+        let errors: Vec<String> = errors.iter().map(|e| format!("{}", e)).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "u (node _1): shape of variable u is not known",
+                "(xconv2dk) (node _4): input [4, 2] smaller than kernel [5, 1]",
+                "(x .* w) (node _5): shapes [4, 2] and [3, 1] do not match",
+            ]
+        );
+    }
+
+    #[test]
+    fn matmul() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let w = eb.new_named_parameter("w", MatrixF32::new_m(nd::ArrayD::zeros(vec![3, 2])));
+        let b = eb.new_named_parameter("b", MatrixF32::new_m(nd::ArrayD::zeros(vec![5, 2])));
+        let y = x.matmul(w) + b;
+        let z = w.matmul(x);
+        let [x, y, z] = [x, y, z].map(|e| e.ident);
+        let cg = ComputGraph::<MatrixF32, _, _>::new(eb, &MatrixCalculator);
+
+        let shapes = infer_shapes(&cg, &y, &[(x, Shape::Array(vec![5, 3]))]).unwrap();
+        assert_eq!(shapes[&y], Shape::Array(vec![5, 2]));
+        let errors = infer_shapes(&cg, &z, &[(x, Shape::Array(vec![5, 3]))]).unwrap_err();
+        assert_eq!(
+            format!("{}", errors[0]),
+            "(w @ x) (node _5): matmul inner dimensions of [3, 2] and [5, 3] do not match"
+        );
+    }
+
+    fn new_eb() -> ExprBuilder<MatrixF32, NaOperAry1, NaOperAry2> {
+        ExprBuilder::new()
+    }
+}
@@ -0,0 +1,190 @@
+//! Softmax along an axis, and the cross-entropy of the softmax fused into a single operator.
+//!
+//! The softmax is computed as `exp(x - max(x))` normalized, so large logits do not overflow. The fused
+//! cross-entropy takes the logits directly, which is both more stable than `ln(softmax(x))` and has the simple
+//! gradient `p - y`.
+use ndarray::{self as nd, Axis};
+
+use super::syntax::MatrixF32;
+
+pub(crate) fn check_axis(axis: usize, shape: &[usize]) -> Result<(), String> {
+    if axis < shape.len() {
+        Ok(())
+    } else {
+        Err(format!(
+            "softmax axis {} out of bounds of {:?}",
+            axis, shape
+        ))
+    }
+}
+
+/// Softmax of `a` along `axis`, or its logarithm if `log`.
+pub(crate) fn softmax(axis: usize, log: bool, a: &nd::ArrayD<f32>) -> nd::ArrayD<f32> {
+    check_axis(axis, a.shape()).unwrap_or_else(|e| panic!("{}", e));
+    let mut v = a.to_owned();
+    for mut lane in v.lanes_mut(Axis(axis)) {
+        let max = lane.fold(f32::NEG_INFINITY, |acc, x| acc.max(*x));
+        lane.mapv_inplace(|x| x - max);
+        let log_sum = lane.fold(0.0, |acc, x| acc + x.exp()).ln();
+        if log {
+            lane.mapv_inplace(|x| x - log_sum);
+        } else {
+            lane.mapv_inplace(|x| (x - log_sum).exp());
+        }
+    }
+    v
+}
+
+/// Given the `primal` of `v = softmax(a)` (or of the log-softmax if `log`) and its `adjoin`, calculate the adjoin
+/// of `a`.
+pub(crate) fn softmax_backward(
+    axis: usize,
+    log: bool,
+    primal: &nd::ArrayD<f32>,
+    adjoin: &nd::ArrayD<f32>,
+) -> nd::ArrayD<f32> {
+    let mut da = adjoin.to_owned();
+    nd::Zip::from(da.lanes_mut(Axis(axis)))
+        .and(primal.lanes(Axis(axis)))
+        .for_each(|mut da, v| {
+            if log {
+                // d log p_i / d x_j = δ_ij - p_j
+                let sum = da.sum();
+                da.zip_mut_with(&v, |g, v| *g -= v.exp() * sum);
+            } else {
+                // d p_i / d x_j = p_i (δ_ij - p_j)
+                let dot = (&da * &v).sum();
+                da.zip_mut_with(&v, |g, p| *g = p * (*g - dot));
+            }
+        });
+    da
+}
+
+/// Check the `labels` of the softmax cross-entropy of `logits`, with the classes along the last axis. The labels
+/// are either probabilities (one-hot) of the same shape as the logits, or class indices shaped as the logits
+/// without the last axis. A single value is the class index of a single sample.
+pub(crate) fn check_labels(logits: &[usize], labels: Option<&[usize]>) -> Result<(), String> {
+    let samples = &logits[..logits.len().saturating_sub(1)];
+    match labels {
+        _ if logits.is_empty() => {
+            Err("softmax cross-entropy expects logits with classes".to_owned())
+        }
+        Some(labels) if labels == logits || labels == samples => Ok(()),
+        None if samples.is_empty() => Ok(()),
+        _ => Err(format!(
+            "labels {} do not match logits {:?}, expected one-hot {:?} or class indices {:?}",
+            labels.map_or("scalar".to_owned(), |l| format!("{:?}", l)),
+            logits,
+            logits,
+            samples
+        )),
+    }
+}
+
+/// The labels as probabilities of the same shape as the `logits`. Class indices are converted to one-hot.
+pub(crate) fn one_hot_labels(logits: &nd::ArrayD<f32>, labels: &MatrixF32) -> nd::ArrayD<f32> {
+    check_labels(logits.shape(), labels.m().map(|m| m.shape())).unwrap_or_else(|e| panic!("{}", e));
+    let indices = match labels {
+        MatrixF32::M(m) if m.shape() == logits.shape() => return m.as_ref().clone(),
+        MatrixF32::M(m) => m.iter().copied().collect(),
+        MatrixF32::V(v) => vec![*v],
+    };
+    let classes = logits.shape()[logits.ndim() - 1];
+    let mut one_hot = nd::ArrayD::zeros(logits.raw_dim());
+    for (mut lane, index) in one_hot
+        .lanes_mut(Axis(logits.ndim() - 1))
+        .into_iter()
+        .zip(indices)
+    {
+        if index < 0.0 || index.fract() != 0.0 || index as usize >= classes {
+            panic!(
+                "class index {} is not one of the {} classes",
+                index, classes
+            );
+        }
+        lane[index as usize] = 1.0;
+    }
+    one_hot
+}
+
+/// Number of samples of the `logits`: all the elements but the classes on the last axis.
+pub(crate) fn n_samples(logits: &nd::ArrayD<f32>) -> usize {
+    logits.shape()[..logits.ndim().saturating_sub(1)]
+        .iter()
+        .product()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{one_hot_labels, softmax, softmax_backward};
+    use crate::nar::syntax::MatrixF32;
+    use ndarray::{self as nd, arr1, arr2};
+
+    #[test]
+    fn stable() {
+        let a = arr2(&[[1000.0, 1000.0], [-1000.0, 0.0]]).into_dyn();
+        let close =
+            |x: nd::ArrayD<f32>, y: nd::Array2<f32>| (x - y.into_dyn()).mapv(f32::abs).sum() < 1e-6;
+        assert!(close(
+            softmax(1, false, &a),
+            arr2(&[[0.5, 0.5], [0.0, 1.0]])
+        ));
+        let ln_half = 0.5_f32.ln();
+        assert!(close(
+            softmax(1, true, &a),
+            arr2(&[[ln_half, ln_half], [-1000.0, 0.0]])
+        ));
+    }
+
+    #[test]
+    fn backward() {
+        let a = nd::ArrayD::from_shape_fn(vec![3, 4], |ix| {
+            ((ix[0] * 4 + ix[1]) as f32 * 1.3).sin() * 2.0
+        });
+        let adjoin = nd::ArrayD::from_shape_fn(vec![3, 4], |ix| ix[0] as f32 - ix[1] as f32 * 0.5);
+        let h = 1e-2;
+        for (axis, log) in [(0, false), (1, false), (0, true), (1, true)] {
+            let da = softmax_backward(axis, log, &softmax(axis, log, &a), &adjoin);
+            let value = |a: &nd::ArrayD<f32>| (softmax(axis, log, a) * &adjoin).sum();
+            for (ix, expected) in da.indexed_iter() {
+                let (mut a_p, mut a_n) = (a.clone(), a.clone());
+                a_p[&ix] += h;
+                a_n[&ix] -= h;
+                let fd = (value(&a_p) - value(&a_n)) / (2.0 * h);
+                assert!(
+                    (fd - expected).abs() < 1e-2,
+                    "{} {} at {:?}: {} vs {}",
+                    axis,
+                    log,
+                    ix,
+                    expected,
+                    fd
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn labels() {
+        let logits = nd::ArrayD::zeros(vec![2, 3]);
+        let indices = MatrixF32::new_m(arr1(&[2.0, 0.0]).into_dyn());
+        let one_hot = arr2(&[[0.0, 0.0, 1.0], [1.0, 0.0, 0.0]]).into_dyn();
+        assert_eq!(one_hot_labels(&logits, &indices), one_hot);
+        assert_eq!(
+            one_hot_labels(&logits, &MatrixF32::new_m(one_hot.clone())),
+            one_hot
+        );
+        let single = nd::ArrayD::zeros(vec![3]);
+        assert_eq!(
+            one_hot_labels(&single, &MatrixF32::V(1.0)),
+            arr1(&[0.0, 1.0, 0.0]).into_dyn()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "class index 3 is not one of the 3 classes")]
+    fn bad_index() {
+        let logits = nd::ArrayD::zeros(vec![2, 3]);
+        one_hot_labels(&logits, &MatrixF32::new_m(arr1(&[3.0, 0.0]).into_dyn()));
+    }
+}
@@ -1,4 +1,8 @@
-use crate::core_syntax::{ComputValue, DefaultAdjoin, Expr, ExprNode, Operator};
+use crate::core_syntax::{impl_expr_ops, ComputValue, DefaultAdjoin, Expr, ExprNode, Operator};
+use crate::losses::{Loss, Reduction};
+use crate::render::{
+    Notation, RenderOperator, PRECEDENCE_ADD, PRECEDENCE_CMP, PRECEDENCE_MUL, PRECEDENCE_SELECT,
+};
 use ndarray as nd;
 
 use std::fmt;
@@ -13,17 +17,164 @@ pub enum NaOperAry1 {
     PowI(i32),
     /// Add all the elements of the matrix and return a single value.
     Sum,
+    Neg,
+    Exp,
+    Ln,
+    Sin,
+    Cos,
+    Tanh,
+    /// 1 / (1 + e^-a)
+    Sigmoid,
+    Sqrt,
+    Abs,
+    /// `a` for positive `a`, `alpha * a` otherwise.
+    LeakyRelu(f32),
+    /// ln(1 + e^a), a smooth version of [NaOperAry1::Relu].
+    Softplus,
+    /// Change the shape keeping the elements in the row-major order.
+    Reshape(Dims),
+    /// Reverse the order of the axes.
+    Transpose,
+    /// Reorder the axes: axis `i` of the output is axis `axes[i]` of the input.
+    Permute(Dims),
+    /// Reshape to a vector of all the elements in the row-major order.
+    Flatten,
+    /// Insert an axis of length 1 at the position.
+    InsertAxis(usize),
+    /// Elements from `starts` (inclusive) to `ends` (exclusive) along each axis. The axes after `starts.len()`
+    /// are kept whole.
+    Slice(Dims, Dims),
+    /// Reduce the axes (all of them if none) to a single element each. With `keepdims` the reduced axes stay
+    /// with length 1, otherwise they are removed, and reducing all of them gives a single value.
+    Reduce(ReduceOp, Dims, bool),
+    /// Softmax along the axis: the exponents normalized to sum to 1.
+    Softmax(usize),
+    /// Logarithm of [NaOperAry1::Softmax], computed without underflow.
+    LogSoftmax(usize),
+    /// Pool the last two axes with the `window` slid by the `stride`, in that order. See [ExprMatrix::pool2d].
+    Pool2d(PoolOp, [usize; 2], [usize; 2]),
+    /// Average of the last two axes, which are removed.
+    GlobalAvgPool2d,
+}
+
+/// How [NaOperAry1::Pool2d] combines the elements of a window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PoolOp {
+    /// The gradient flows to the first maximal element of the window.
+    Max,
+    /// The gradient is spread evenly over the window.
+    Avg,
+}
+
+impl fmt::Display for PoolOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            PoolOp::Max => "max",
+            PoolOp::Avg => "avg",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// How [NaOperAry1::Reduce] combines the elements along the reduced axes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReduceOp {
+    Sum,
+    Mean,
+    /// The gradient is split evenly between the maximal elements.
+    Max,
+    /// The gradient is split evenly between the minimal elements.
+    Min,
+    Prod,
+    /// Population variance, divided by the number of elements.
+    Var,
+    /// Square root of [ReduceOp::Var].
+    Std,
+    /// `ln(sum(exp(x)))`, computed without overflow.
+    LogSumExp,
+}
+
+impl fmt::Display for ReduceOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ReduceOp::Sum => "sum",
+            ReduceOp::Mean => "mean",
+            ReduceOp::Max => "max",
+            ReduceOp::Min => "min",
+            ReduceOp::Prod => "prod",
+            ReduceOp::Var => "var",
+            ReduceOp::Std => "std",
+            ReduceOp::LogSumExp => "logsumexp",
+        };
+        write!(f, "{}", s)
+    }
 }
 
 impl Operator for NaOperAry1 {}
 
+/// The longest shape or the most axes a [NaOperAry1] can hold.
+pub const MAX_DIMS: usize = 6;
+
+/// A short list of dimensions or axes, stored inline so the operators stay `Copy`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Dims {
+    len: usize,
+    dims: [usize; MAX_DIMS],
+}
+
+impl Dims {
+    pub fn new(dims: &[usize]) -> Dims {
+        assert!(
+            dims.len() <= MAX_DIMS,
+            "At most {} dimensions are supported, got {:?}",
+            MAX_DIMS,
+            dims
+        );
+        let mut d = [0; MAX_DIMS];
+        d[..dims.len()].copy_from_slice(dims);
+        Dims {
+            len: dims.len(),
+            dims: d,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[usize] {
+        &self.dims[..self.len]
+    }
+}
+
+impl fmt::Debug for Dims {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_slice())
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum NaOperAry2 {
     Add,
     Sub,
     // Element-wise multiplication.
     MulComp,
+    /// Matrix product, batched for 3d operands. See [ExprMatrix::matmul].
+    MatMul,
     Conv2d,
+    /// Element-wise 1 if a > b, 0 otherwise. The gradient is zero.
+    Gt,
+    /// Element-wise 1 if a < b, 0 otherwise. The gradient is zero.
+    Lt,
+    /// Element-wise `a` where the condition `b` is non-zero, 0 otherwise. The gradient flows only to `a`.
+    Where,
+    /// Element-wise `a` where the condition `b` is zero, 0 otherwise. The gradient flows only to `a`.
+    WhereNot,
+    /// Element-wise division.
+    Div,
+    /// Loss of the prediction `a` w.r.t. the target `b`, reduced to a single value.
+    Loss(Loss, Reduction),
+    /// Join `a` and `b` along the axis. The other axes must match.
+    Concat(usize),
+    /// Cross-entropy of the softmax of the logits `a` w.r.t. the labels `b`, with the classes along the last axis,
+    /// reduced over the samples. See [ExprMatrix::softmax_cross_entropy].
+    SoftmaxCrossEntropy(Reduction),
 }
 
 impl Operator for NaOperAry2 {}
@@ -34,6 +185,39 @@ impl fmt::Display for NaOperAry1 {
             NaOperAry1::Relu => "relu".to_owned(),
             NaOperAry1::PowI(p) => format!("pow{}", p),
             NaOperAry1::Sum => "sum".to_owned(),
+            NaOperAry1::Neg => "neg".to_owned(),
+            NaOperAry1::Exp => "exp".to_owned(),
+            NaOperAry1::Ln => "ln".to_owned(),
+            NaOperAry1::Sin => "sin".to_owned(),
+            NaOperAry1::Cos => "cos".to_owned(),
+            NaOperAry1::Tanh => "tanh".to_owned(),
+            NaOperAry1::Sigmoid => "sigmoid".to_owned(),
+            NaOperAry1::Sqrt => "sqrt".to_owned(),
+            NaOperAry1::Abs => "abs".to_owned(),
+            NaOperAry1::LeakyRelu(alpha) => format!("leaky_relu_{}", alpha),
+            NaOperAry1::Softplus => "softplus".to_owned(),
+            NaOperAry1::Reshape(dims) => format!("reshape{:?}", dims),
+            NaOperAry1::Transpose => "transpose".to_owned(),
+            NaOperAry1::Permute(axes) => format!("permute{:?}", axes),
+            NaOperAry1::Flatten => "flatten".to_owned(),
+            NaOperAry1::InsertAxis(axis) => format!("insert_axis{}", axis),
+            NaOperAry1::Slice(starts, ends) => {
+                let ranges: Vec<String> = (starts.as_slice().iter().zip(ends.as_slice()))
+                    .map(|(start, end)| format!("{}..{}", start, end))
+                    .collect();
+                format!("slice[{}]", ranges.join(", "))
+            }
+            NaOperAry1::Reduce(op, axes, keepdims) => {
+                let keepdims = if *keepdims { "_keepdims" } else { "" };
+                format!("{}{}{:?}", op, keepdims, axes)
+            }
+            NaOperAry1::Softmax(axis) => format!("softmax{}", axis),
+            NaOperAry1::LogSoftmax(axis) => format!("log_softmax{}", axis),
+            NaOperAry1::Pool2d(op, window, stride) => format!(
+                "{}_pool2d[{}x{}, stride {}x{}]",
+                op, window[0], window[1], stride[0], stride[1]
+            ),
+            NaOperAry1::GlobalAvgPool2d => "global_avg_pool2d".to_owned(),
         };
         write!(f, "{}", s)
     }
@@ -45,12 +229,214 @@ impl fmt::Display for NaOperAry2 {
             NaOperAry2::Add => " + ",
             NaOperAry2::Sub => " - ",
             NaOperAry2::MulComp => " .* ",
+            NaOperAry2::MatMul => " @ ",
             NaOperAry2::Conv2d => "conv2d",
+            NaOperAry2::Gt => " > ",
+            NaOperAry2::Lt => " < ",
+            NaOperAry2::Where => " if ",
+            NaOperAry2::WhereNot => " unless ",
+            NaOperAry2::Div => " ./ ",
+            NaOperAry2::Loss(loss, Reduction::Mean) => return write!(f, " {} ", loss),
+            NaOperAry2::Loss(loss, Reduction::Sum) => return write!(f, " sum_{} ", loss),
+            NaOperAry2::Concat(axis) => return write!(f, " concat{} ", axis),
+            NaOperAry2::SoftmaxCrossEntropy(Reduction::Mean) => " softmax_ce ",
+            NaOperAry2::SoftmaxCrossEntropy(Reduction::Sum) => " sum_softmax_ce ",
         };
         write!(f, "{}", s)
     }
 }
 
+impl RenderOperator for NaOperAry1 {
+    fn notation(&self) -> Notation {
+        match self {
+            NaOperAry1::Relu => Notation::Function {
+                latex: "\\operatorname{relu}",
+                mathml: "relu",
+            },
+            NaOperAry1::PowI(p) => Notation::PowI(*p),
+            NaOperAry1::Sum => Notation::Function {
+                latex: "\\sum",
+                mathml: "&#x2211;",
+            },
+            NaOperAry1::Neg => Notation::Function {
+                latex: "-",
+                mathml: "&#x2212;",
+            },
+            NaOperAry1::Exp => Notation::Function {
+                latex: "\\exp",
+                mathml: "exp",
+            },
+            NaOperAry1::Ln => Notation::Function {
+                latex: "\\ln",
+                mathml: "ln",
+            },
+            NaOperAry1::Sin => Notation::Function {
+                latex: "\\sin",
+                mathml: "sin",
+            },
+            NaOperAry1::Cos => Notation::Function {
+                latex: "\\cos",
+                mathml: "cos",
+            },
+            NaOperAry1::Tanh => Notation::Function {
+                latex: "\\tanh",
+                mathml: "tanh",
+            },
+            NaOperAry1::Sigmoid => Notation::Function {
+                latex: "\\sigma",
+                mathml: "&#x3C3;",
+            },
+            NaOperAry1::Sqrt => Notation::Sqrt,
+            NaOperAry1::Abs => Notation::Function {
+                latex: "\\operatorname{abs}",
+                mathml: "abs",
+            },
+            NaOperAry1::LeakyRelu(_) => Notation::Function {
+                latex: "\\operatorname{leakyrelu}",
+                mathml: "leakyrelu",
+            },
+            NaOperAry1::Softplus => Notation::Function {
+                latex: "\\operatorname{softplus}",
+                mathml: "softplus",
+            },
+            NaOperAry1::Reshape(_) => Notation::Function {
+                latex: "\\operatorname{reshape}",
+                mathml: "reshape",
+            },
+            NaOperAry1::Transpose => Notation::Function {
+                latex: "\\operatorname{transpose}",
+                mathml: "transpose",
+            },
+            NaOperAry1::Permute(_) => Notation::Function {
+                latex: "\\operatorname{permute}",
+                mathml: "permute",
+            },
+            NaOperAry1::Flatten => Notation::Function {
+                latex: "\\operatorname{flatten}",
+                mathml: "flatten",
+            },
+            NaOperAry1::InsertAxis(_) => Notation::Function {
+                latex: "\\operatorname{insert\\_axis}",
+                mathml: "insert_axis",
+            },
+            NaOperAry1::Slice(..) => Notation::Function {
+                latex: "\\operatorname{slice}",
+                mathml: "slice",
+            },
+            NaOperAry1::Reduce(op, ..) => {
+                let (latex, mathml) = match op {
+                    ReduceOp::Sum => ("\\sum", "&#x2211;"),
+                    ReduceOp::Mean => ("\\operatorname{mean}", "mean"),
+                    ReduceOp::Max => ("\\max", "max"),
+                    ReduceOp::Min => ("\\min", "min"),
+                    ReduceOp::Prod => ("\\prod", "&#x220F;"),
+                    ReduceOp::Var => ("\\operatorname{var}", "var"),
+                    ReduceOp::Std => ("\\operatorname{std}", "std"),
+                    ReduceOp::LogSumExp => ("\\operatorname{logsumexp}", "logsumexp"),
+                };
+                Notation::Function { latex, mathml }
+            }
+            NaOperAry1::Softmax(_) => Notation::Function {
+                latex: "\\operatorname{softmax}",
+                mathml: "softmax",
+            },
+            NaOperAry1::LogSoftmax(_) => Notation::Function {
+                latex: "\\operatorname{logsoftmax}",
+                mathml: "logsoftmax",
+            },
+            NaOperAry1::Pool2d(PoolOp::Max, ..) => Notation::Function {
+                latex: "\\operatorname{maxpool}",
+                mathml: "maxpool",
+            },
+            NaOperAry1::Pool2d(PoolOp::Avg, ..) => Notation::Function {
+                latex: "\\operatorname{avgpool}",
+                mathml: "avgpool",
+            },
+            NaOperAry1::GlobalAvgPool2d => Notation::Function {
+                latex: "\\operatorname{globalavgpool}",
+                mathml: "globalavgpool",
+            },
+        }
+    }
+}
+
+impl RenderOperator for NaOperAry2 {
+    fn notation(&self) -> Notation {
+        match self {
+            NaOperAry2::Add => Notation::Infix {
+                latex: "+",
+                mathml: "+",
+                precedence: PRECEDENCE_ADD,
+                associative: true,
+            },
+            NaOperAry2::Sub => Notation::Infix {
+                latex: "-",
+                mathml: "&#x2212;",
+                precedence: PRECEDENCE_ADD,
+                associative: false,
+            },
+            NaOperAry2::MulComp => Notation::Infix {
+                latex: "\\odot",
+                mathml: "&#x2299;",
+                precedence: PRECEDENCE_MUL,
+                associative: true,
+            },
+            NaOperAry2::MatMul => Notation::Infix {
+                latex: "\\cdot",
+                mathml: "&#x22C5;",
+                precedence: PRECEDENCE_MUL,
+                associative: true,
+            },
+            NaOperAry2::Conv2d => Notation::Function {
+                latex: "\\operatorname{conv2d}",
+                mathml: "conv2d",
+            },
+            NaOperAry2::Gt => Notation::Infix {
+                latex: ">",
+                mathml: "&gt;",
+                precedence: PRECEDENCE_CMP,
+                associative: false,
+            },
+            NaOperAry2::Lt => Notation::Infix {
+                latex: "<",
+                mathml: "&lt;",
+                precedence: PRECEDENCE_CMP,
+                associative: false,
+            },
+            NaOperAry2::Where => Notation::Infix {
+                latex: "\\text{ if }",
+                mathml: "if",
+                precedence: PRECEDENCE_SELECT,
+                associative: false,
+            },
+            NaOperAry2::WhereNot => Notation::Infix {
+                latex: "\\text{ unless }",
+                mathml: "unless",
+                precedence: PRECEDENCE_SELECT,
+                associative: false,
+            },
+            NaOperAry2::Div => Notation::Infix {
+                latex: "\\oslash",
+                mathml: "&#x2298;",
+                precedence: PRECEDENCE_MUL,
+                associative: false,
+            },
+            NaOperAry2::Loss(loss, _) => {
+                let (latex, mathml) = loss.notation();
+                Notation::Function { latex, mathml }
+            }
+            NaOperAry2::Concat(_) => Notation::Function {
+                latex: "\\operatorname{concat}",
+                mathml: "concat",
+            },
+            NaOperAry2::SoftmaxCrossEntropy(_) => Notation::Function {
+                latex: "\\operatorname{softmax\\_ce}",
+                mathml: "softmax_ce",
+            },
+        }
+    }
+}
+
 /// For all the practical purposes, V of value `v` can be treat as M with all the fields set to `v`.
 #[derive(Debug, Clone, PartialEq)]
 pub enum MatrixF32 {
@@ -81,9 +467,9 @@ impl MatrixF32 {
     }
 }
 
-impl From<nd::ArrayBase<nd::OwnedRepr<f32>, nd::Dim<nd::IxDynImpl>>> for MatrixF32 {
-    fn from(value: nd::ArrayBase<nd::OwnedRepr<f32>, nd::Dim<nd::IxDynImpl>>) -> Self {
-        MatrixF32::new_m(value)
+impl<D: nd::Dimension> From<nd::Array<f32, D>> for MatrixF32 {
+    fn from(value: nd::Array<f32, D>) -> Self {
+        MatrixF32::new_m(value.into_dyn())
     }
 }
 
@@ -93,7 +479,21 @@ impl From<f32> for MatrixF32 {
     }
 }
 
-impl ComputValue for MatrixF32 {}
+impl ComputValue for MatrixF32 {
+    fn n_elements(&self) -> usize {
+        match self {
+            MatrixF32::M(m) => m.len(),
+            MatrixF32::V(_) => 1,
+        }
+    }
+
+    fn is_finite(&self) -> bool {
+        match self {
+            MatrixF32::M(m) => m.iter().all(|v| v.is_finite()),
+            MatrixF32::V(v) => v.is_finite(),
+        }
+    }
+}
 
 impl ops::Add for MatrixF32 {
     type Output = Self;
@@ -163,45 +563,276 @@ impl<'a> ExprMatrix<'a> {
         self.register_and_continue_expr(node)
     }
 
+    pub fn exp(&self) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Exp, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn ln(&self) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Ln, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn sin(&self) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Sin, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn cos(&self) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Cos, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn tanh(&self) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Tanh, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// Element-wise 1 / (1 + e^-a), like the gates of a recurrent layer.
+    pub fn sigmoid(&self) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Sigmoid, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn sqrt(&self) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Sqrt, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn abs(&self) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Abs, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// Element-wise `a` for positive `a`, `alpha * a` otherwise.
+    pub fn leaky_relu(&self, alpha: f32) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::LeakyRelu(alpha), self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn softplus(&self) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Softplus, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// Matrix product `self · other`, like `w.matmul(x) + b` for a dense layer. A vector on the left is a row, on
+    /// the right a column. 3d operands are batches of matrices, multiplied one by one; a 2d operand is shared by
+    /// the whole batch.
+    pub fn matmul(&self, other: ExprMatrix<'a>) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary2(NaOperAry2::MatMul, self.ident, other.ident);
+        self.register_and_continue_expr(node)
+    }
+
     pub fn conv2d(&self, kernel: ExprMatrix<'a>) -> ExprMatrix<'a> {
         let node = ExprNode::Ary2(NaOperAry2::Conv2d, self.ident, kernel.ident);
         self.register_and_continue_expr(node)
     }
-}
 
-impl<'a> ops::Add for ExprMatrix<'a> {
-    type Output = ExprMatrix<'a>;
+    /// Loss of the prediction `self` w.r.t. the `target`. Panic on a non-positive [Loss::Huber] delta.
+    pub fn loss(&self, target: ExprMatrix<'a>, loss: Loss, reduction: Reduction) -> ExprMatrix<'a> {
+        loss.validate();
+        let node = ExprNode::Ary2(NaOperAry2::Loss(loss, reduction), self.ident, target.ident);
+        self.register_and_continue_expr(node)
+    }
 
-    fn add(self, rhs: Self) -> Self::Output {
-        let node = ExprNode::Ary2(NaOperAry2::Add, self.ident, rhs.ident);
+    /// Mask with 1 where `self > other`, 0 elsewhere.
+    pub fn gt(&self, other: ExprMatrix<'a>) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary2(NaOperAry2::Gt, self.ident, other.ident);
         self.register_and_continue_expr(node)
     }
-}
 
-impl<'a> ops::Sub for ExprMatrix<'a> {
-    type Output = ExprMatrix<'a>;
+    /// Mask with 1 where `self < other`, 0 elsewhere.
+    pub fn lt(&self, other: ExprMatrix<'a>) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary2(NaOperAry2::Lt, self.ident, other.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// Use `self` as a mask: `a` where the mask is non-zero, `b` elsewhere. The gradient is routed through the
+    /// chosen branch, and is zero for the mask.
+    pub fn select(&self, a: ExprMatrix<'a>, b: ExprMatrix<'a>) -> ExprMatrix<'a> {
+        let a = ExprNode::Ary2(NaOperAry2::Where, a.ident, self.ident);
+        let b = ExprNode::Ary2(NaOperAry2::WhereNot, b.ident, self.ident);
+        self.register_and_continue_expr(a) + self.register_and_continue_expr(b)
+    }
+
+    /// Element-wise minimum.
+    pub fn min(&self, other: ExprMatrix<'a>) -> ExprMatrix<'a> {
+        self.lt(other).select(*self, other)
+    }
 
-    fn sub(self, rhs: Self) -> Self::Output {
-        let node = ExprNode::Ary2(NaOperAry2::Sub, self.ident, rhs.ident);
+    /// Element-wise maximum.
+    pub fn max(&self, other: ExprMatrix<'a>) -> ExprMatrix<'a> {
+        self.gt(other).select(*self, other)
+    }
+
+    /// Limit the elements to `[low, high]`.
+    pub fn clamp(&self, low: ExprMatrix<'a>, high: ExprMatrix<'a>) -> ExprMatrix<'a> {
+        self.max(low).min(high)
+    }
+
+    /// Reduce the `axes` (all of them if empty) with `op`, see [NaOperAry1::Reduce].
+    pub fn reduce(&self, op: ReduceOp, axes: &[usize], keepdims: bool) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(
+            NaOperAry1::Reduce(op, Dims::new(axes), keepdims),
+            self.ident,
+        );
+        self.register_and_continue_expr(node)
+    }
+
+    /// Sum along the `axes`, unlike [ExprMatrix::sum] that always sums all the elements.
+    pub fn sum_axis(&self, axes: &[usize], keepdims: bool) -> ExprMatrix<'a> {
+        self.reduce(ReduceOp::Sum, axes, keepdims)
+    }
+
+    pub fn mean(&self, axes: &[usize], keepdims: bool) -> ExprMatrix<'a> {
+        self.reduce(ReduceOp::Mean, axes, keepdims)
+    }
+
+    /// Maximum along the `axes`. See [ExprMatrix::max] for the element-wise maximum of two matrices.
+    pub fn max_axis(&self, axes: &[usize], keepdims: bool) -> ExprMatrix<'a> {
+        self.reduce(ReduceOp::Max, axes, keepdims)
+    }
+
+    /// Minimum along the `axes`. See [ExprMatrix::min] for the element-wise minimum of two matrices.
+    pub fn min_axis(&self, axes: &[usize], keepdims: bool) -> ExprMatrix<'a> {
+        self.reduce(ReduceOp::Min, axes, keepdims)
+    }
+
+    pub fn prod(&self, axes: &[usize], keepdims: bool) -> ExprMatrix<'a> {
+        self.reduce(ReduceOp::Prod, axes, keepdims)
+    }
+
+    pub fn var(&self, axes: &[usize], keepdims: bool) -> ExprMatrix<'a> {
+        self.reduce(ReduceOp::Var, axes, keepdims)
+    }
+
+    pub fn std(&self, axes: &[usize], keepdims: bool) -> ExprMatrix<'a> {
+        self.reduce(ReduceOp::Std, axes, keepdims)
+    }
+
+    pub fn logsumexp(&self, axes: &[usize], keepdims: bool) -> ExprMatrix<'a> {
+        self.reduce(ReduceOp::LogSumExp, axes, keepdims)
+    }
+
+    /// Softmax along the `axis`, the probabilities of the classes out of the logits.
+    pub fn softmax(&self, axis: usize) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Softmax(axis), self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// Logarithm of the softmax along the `axis`, stable also for the very unlikely classes.
+    pub fn log_softmax(&self, axis: usize) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::LogSoftmax(axis), self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// Pool the last two axes with `op` over the `window` slid by the `stride`, like `x.max_pool2d([2, 2], [2, 2])`
+    /// to halve the height and the width of an image. The windows reaching past the input are skipped, and the
+    /// leading axes are pooled independently.
+    pub fn pool2d(&self, op: PoolOp, window: [usize; 2], stride: [usize; 2]) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Pool2d(op, window, stride), self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    pub fn max_pool2d(&self, window: [usize; 2], stride: [usize; 2]) -> ExprMatrix<'a> {
+        self.pool2d(PoolOp::Max, window, stride)
+    }
+
+    pub fn avg_pool2d(&self, window: [usize; 2], stride: [usize; 2]) -> ExprMatrix<'a> {
+        self.pool2d(PoolOp::Avg, window, stride)
+    }
+
+    /// Average of each matrix of the last two axes, like of each channel of the images before a dense layer.
+    pub fn global_avg_pool2d(&self) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::GlobalAvgPool2d, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// Cross-entropy of the softmax of the logits `self` w.r.t. the `labels`, with the classes along the last axis.
+    /// The labels are either the probabilities of the classes (one-hot) shaped as the logits, or the class indices
+    /// shaped as the logits without the last axis. The gradient of the logits is `p - y`; the class indices get no
+    /// gradient.
+    pub fn softmax_cross_entropy(
+        &self,
+        labels: ExprMatrix<'a>,
+        reduction: Reduction,
+    ) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary2(
+            NaOperAry2::SoftmaxCrossEntropy(reduction),
+            self.ident,
+            labels.ident,
+        );
+        self.register_and_continue_expr(node)
+    }
+
+    /// Change the shape, keeping the elements in the row-major order. The number of elements must not change.
+    pub fn reshape(&self, shape: &[usize]) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Reshape(Dims::new(shape)), self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// Reverse the order of the axes, the usual transpose for a 2d matrix.
+    pub fn transpose(&self) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Transpose, self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// Reorder the axes: axis `i` of the result is axis `axes[i]` of `self`.
+    pub fn permute_axes(&self, axes: &[usize]) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Permute(Dims::new(axes)), self.ident);
+        self.register_and_continue_expr(node)
+    }
+
+    /// All the elements as a vector, like between a convolution and a dense layer.
+    pub fn flatten(&self) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::Flatten, self.ident);
         self.register_and_continue_expr(node)
     }
-}
 
-/// **Element-wise** multiplication. It's element-wise and not a product since it seems to be more common,
-/// and easier to use in an expression.
-impl<'a> ops::Mul for ExprMatrix<'a> {
-    type Output = ExprMatrix<'a>;
+    /// Insert an axis of length 1 at the position `axis`.
+    pub fn insert_axis(&self, axis: usize) -> ExprMatrix<'a> {
+        let node = ExprNode::Ary1(NaOperAry1::InsertAxis(axis), self.ident);
+        self.register_and_continue_expr(node)
+    }
 
-    fn mul(self, rhs: Self) -> Self::Output {
-        let node = ExprNode::Ary2(NaOperAry2::MulComp, self.ident, rhs.ident);
+    /// Take the `ranges` of the first axes, like `x.slice(&[1..3])` for rows 1 and 2.
+    pub fn slice(&self, ranges: &[ops::Range<usize>]) -> ExprMatrix<'a> {
+        let starts: Vec<usize> = ranges.iter().map(|r| r.start).collect();
+        let ends: Vec<usize> = ranges.iter().map(|r| r.end).collect();
+        let oper = NaOperAry1::Slice(Dims::new(&starts), Dims::new(&ends));
+        let node = ExprNode::Ary1(oper, self.ident);
         self.register_and_continue_expr(node)
     }
+
+    /// Join `self` and `others` along an existing axis.
+    pub fn concat(&self, others: &[ExprMatrix<'a>], axis: usize) -> ExprMatrix<'a> {
+        others.iter().fold(*self, |acc, other| {
+            let node = ExprNode::Ary2(NaOperAry2::Concat(axis), acc.ident, other.ident);
+            self.register_and_continue_expr(node)
+        })
+    }
+
+    /// Join `self` and `others`, all of the same shape, along a new axis.
+    pub fn stack(&self, others: &[ExprMatrix<'a>], axis: usize) -> ExprMatrix<'a> {
+        let others: Vec<ExprMatrix<'a>> = others.iter().map(|o| o.insert_axis(axis)).collect();
+        self.insert_axis(axis).concat(&others, axis)
+    }
 }
 
+// `*` is **element-wise** multiplication. It's element-wise and not a product since it seems to be more common,
+// and easier to use in an expression.
+impl_expr_ops!(MatrixF32, NaOperAry1, NaOperAry2, neg: NaOperAry1::Neg,
+    Add::add, AddAssign::add_assign => NaOperAry2::Add;
+    Sub::sub, SubAssign::sub_assign => NaOperAry2::Sub;
+    Mul::mul, MulAssign::mul_assign => NaOperAry2::MulComp;
+    Div::div, DivAssign::div_assign => NaOperAry2::Div;
+);
+
 #[cfg(test)]
 mod tests {
     use super::{MatrixF32, NaOperAry1, NaOperAry2};
-    use crate::core_syntax::ExprBuilder;
+    use crate::core_syntax::{AsConst, ExprBuilder};
+    use ndarray as nd;
 
     #[test]
     fn syntax_add() {
@@ -230,6 +861,36 @@ mod tests {
         assert_eq!("(a .* b)", format!("{}", c));
     }
 
+    #[test]
+    fn syntax_scalar_ops() {
+        let eb = new_eb();
+        let a = eb.new_variable("a");
+        let k = nd::Array2::<f32>::eye(2).as_const(&eb);
+        let mut b = 2.0 * -a / k;
+        b += 1.0;
+        assert_eq!(
+            "(((2 .* neg(a)) ./ [[1, 0],\n [0, 1]]) + 1)",
+            format!("{}", b)
+        );
+    }
+
+    #[test]
+    fn syntax_layout() {
+        let eb = new_eb();
+        let a = eb.new_variable("a");
+        let b = eb.new_variable("b");
+        let c = a.transpose().reshape(&[2, 3]).slice(&[0..1, 1..3]);
+        assert_eq!(
+            "slice[0..1, 1..3](reshape[2, 3](transpose(a)))",
+            format!("{}", c)
+        );
+        let d = a.stack(&[b], 1).permute_axes(&[1, 0, 2]).flatten();
+        assert_eq!(
+            "flatten(permute[1, 0, 2]((insert_axis1(a) concat1 insert_axis1(b))))",
+            format!("{}", d)
+        );
+    }
+
     #[test]
     fn syntax_relu() {
         let eb = new_eb();
@@ -238,6 +899,64 @@ mod tests {
         assert_eq!("relu(a)", format!("{}", b));
     }
 
+    #[test]
+    fn syntax_elementwise() {
+        let eb = new_eb();
+        let a = eb.new_variable("a");
+        let b = a.sigmoid() * a.tanh() + a.leaky_relu(0.1).exp().ln().sqrt();
+        assert_eq!(
+            "((sigmoid(a) .* tanh(a)) + sqrt(ln(exp(leaky_relu_0.1(a)))))",
+            format!("{}", b)
+        );
+    }
+
+    #[test]
+    fn syntax_pool() {
+        let eb = new_eb();
+        let a = eb.new_variable("a");
+        let b = a
+            .max_pool2d([2, 2], [2, 2])
+            .avg_pool2d([3, 1], [1, 1])
+            .global_avg_pool2d();
+        assert_eq!(
+            "global_avg_pool2d(avg_pool2d[3x1, stride 1x1](max_pool2d[2x2, stride 2x2](a)))",
+            format!("{}", b)
+        );
+    }
+
+    #[test]
+    fn latex_sqrt() {
+        let eb = new_eb();
+        let a = eb.new_variable("a");
+        assert_eq!("\\sqrt{\\exp\\left(a\\right)}", a.exp().sqrt().to_latex());
+    }
+
+    #[test]
+    fn latex_mixed_products() {
+        let eb = new_eb();
+        let a = eb.new_variable("a");
+        let b = eb.new_variable("b");
+        let c = eb.new_variable("c");
+        assert_eq!(
+            "a \\odot \\left(b \\cdot c\\right)",
+            (a * b.matmul(c)).to_latex()
+        );
+        assert_eq!("a \\odot b \\cdot c", (a * b).matmul(c).to_latex());
+        assert_eq!("a \\cdot b \\cdot c", a.matmul(b.matmul(c)).to_latex());
+    }
+
+    #[test]
+    fn latex() {
+        let eb = new_eb();
+        let a = eb.new_variable("a");
+        let k = eb.new_named_parameter("k1", MatrixF32::V(1.0));
+        let y = (a.conv2d(k) * a).relu().sum();
+        assert_eq!(
+            "\\sum\\left(\\operatorname{relu}\\left(\\operatorname{conv2d}\\left(a, k_{1}\\right) \\odot a\\right)\\right)",
+            y.to_latex()
+        );
+    }
+
     fn new_eb() -> ExprBuilder<MatrixF32, NaOperAry1, NaOperAry2> {
         ExprBuilder::new()
     }
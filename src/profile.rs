@@ -0,0 +1,212 @@
+//! Opt-in instrumentation of [ComputGraph](crate::compute::ComputGraph) forward and backward passes.
+//!
+//! The profiler measures wall time, call count and allocated elements per node and per operator type. The time
+//! is exclusive, i.e. the time spent in the arguments of a node (and in cloning the nodes with `get_node`) is not
+//! accounted to the node itself.
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::core_syntax::Ident;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pass {
+    Forward,
+    Backward,
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pass::Forward => f.pad("forward"),
+            Pass::Backward => f.pad("backward"),
+        }
+    }
+}
+
+/// Aggregated measurements.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stat {
+    pub calls: u64,
+    /// Exclusive wall time.
+    pub time: Duration,
+    /// Number of scalar elements of the produced values (primals in forward, adjoins in backward).
+    pub elements: u64,
+}
+
+impl Stat {
+    fn add(&mut self, time: Duration, elements: usize) {
+        self.calls += 1;
+        self.time += time;
+        self.elements += elements as u64;
+    }
+
+    fn merge(&mut self, other: &Stat) {
+        self.calls += other.calls;
+        self.time += other.time;
+        self.elements += other.elements;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeProfile {
+    pub pass: Pass,
+    pub ident: Ident,
+    /// Operator type, like `Mul` or `PowI`. Leaf nodes are `Const`, `Variable` and `Parameter`.
+    pub oper: String,
+    pub stat: Stat,
+}
+
+#[derive(Debug, Clone)]
+pub struct OperProfile {
+    pub pass: Pass,
+    pub oper: String,
+    pub stat: Stat,
+}
+
+/// Profiling results, sorted by time, the most expensive first.
+#[derive(Debug, Clone)]
+pub struct ProfileReport {
+    pub by_operator: Vec<OperProfile>,
+    pub by_node: Vec<NodeProfile>,
+    /// Cloning of the nodes with `ComputGraph::get_node`.
+    pub get_node: Stat,
+}
+
+impl ProfileReport {
+    pub fn total_time(&self) -> Duration {
+        self.by_operator
+            .iter()
+            .map(|p| p.stat.time)
+            .fold(self.get_node.time, |acc, t| acc + t)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Profiler {
+    /// Time spent in nested spans, one entry per open span.
+    nested: Vec<Duration>,
+    nodes: BTreeMap<(Pass, Ident), (String, Stat)>,
+    get_node: Stat,
+}
+
+impl Profiler {
+    /// Open a span. Every `enter` must be followed by exactly one `exit_*`.
+    pub(crate) fn enter(&mut self) -> Instant {
+        self.nested.push(Duration::ZERO);
+        Instant::now()
+    }
+
+    pub(crate) fn exit_node(
+        &mut self,
+        start: Instant,
+        pass: Pass,
+        ident: Ident,
+        oper: String,
+        elements: usize,
+    ) {
+        let time = self.exit(start);
+        self.nodes
+            .entry((pass, ident))
+            .or_insert_with(|| (oper, Stat::default()))
+            .1
+            .add(time, elements);
+    }
+
+    pub(crate) fn exit_get_node(&mut self, start: Instant) {
+        let time = self.exit(start);
+        self.get_node.add(time, 0);
+    }
+
+    /// Close the span and return the exclusive time.
+    fn exit(&mut self, start: Instant) -> Duration {
+        let total = start.elapsed();
+        let nested = self
+            .nested
+            .pop()
+            .expect("Bug: profiler exit without enter!");
+        if let Some(parent) = self.nested.last_mut() {
+            *parent += total;
+        }
+        total.saturating_sub(nested)
+    }
+
+    pub(crate) fn report(&self) -> ProfileReport {
+        let mut by_operator: BTreeMap<(Pass, String), Stat> = BTreeMap::new();
+        let mut by_node: Vec<NodeProfile> = Vec::new();
+        for ((pass, ident), (oper, stat)) in self.nodes.iter() {
+            by_operator
+                .entry((*pass, oper.clone()))
+                .or_default()
+                .merge(stat);
+            by_node.push(NodeProfile {
+                pass: *pass,
+                ident: *ident,
+                oper: oper.clone(),
+                stat: *stat,
+            });
+        }
+        let mut by_operator: Vec<OperProfile> = by_operator
+            .into_iter()
+            .map(|((pass, oper), stat)| OperProfile { pass, oper, stat })
+            .collect();
+        by_operator.sort_by_key(|p| Reverse(p.stat.time));
+        by_node.sort_by_key(|p| Reverse(p.stat.time));
+        ProfileReport {
+            by_operator,
+            by_node,
+            get_node: self.get_node,
+        }
+    }
+}
+
+/// Operator type out of the operator, i.e. the name of the enum variant without the parameters.
+pub(crate) fn oper_type<OP: fmt::Debug>(oper: &OP) -> String {
+    let s = format!("{:?}", oper);
+    match s.find(['(', ' ', '{']) {
+        Some(i) => s[..i].to_owned(),
+        None => s,
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total_time().as_secs_f64().max(f64::MIN_POSITIVE);
+        let row = |f: &mut fmt::Formatter<'_>,
+                   pass: &dyn fmt::Display,
+                   oper: &str,
+                   node: &str,
+                   stat: &Stat| {
+            writeln!(
+                f,
+                "{:<9} {:<12} {:>8} {:>10} {:>12.3} {:>6.1}% {:>12}",
+                pass,
+                oper,
+                node,
+                stat.calls,
+                stat.time.as_secs_f64() * 1000.0,
+                100.0 * stat.time.as_secs_f64() / total,
+                stat.elements
+            )
+        };
+        let header = format!(
+            "{:<9} {:<12} {:>8} {:>10} {:>12} {:>7} {:>12}",
+            "pass", "oper", "node", "calls", "time [ms]", "time", "elements"
+        );
+        writeln!(f, "By operator:")?;
+        writeln!(f, "{}", header)?;
+        for p in self.by_operator.iter() {
+            row(f, &p.pass, &p.oper, "", &p.stat)?;
+        }
+        row(f, &"", "get_node", "", &self.get_node)?;
+        writeln!(f, "By node:")?;
+        writeln!(f, "{}", header)?;
+        for p in self.by_node.iter() {
+            row(f, &p.pass, &p.oper, &format!("{}", p.ident), &p.stat)?;
+        }
+        Ok(())
+    }
+}
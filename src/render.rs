@@ -0,0 +1,382 @@
+//! Render expressions in math notation, as LaTeX or MathML. Unlike the ASCII [fmt::Display] of [Expr], the
+//! renderers respect operator precedence, so `(a + (b * c))` is rendered as `a + b \cdot c`.
+//!
+//! The operators describe how they should be rendered by implementing [RenderOperator].
+//!
+//! [fmt::Display]: std::fmt::Display
+use crate::core_syntax::{ComputValue, Expr, ExprNode, Ident, Operator};
+
+/// Precedence of the conditional selection (`a if c`, `b unless c`).
+pub const PRECEDENCE_SELECT: u8 = 2;
+/// Precedence of comparisons (`>`, `<`).
+pub const PRECEDENCE_CMP: u8 = 5;
+/// Precedence of additive operators (`+`, `-`).
+pub const PRECEDENCE_ADD: u8 = 10;
+/// Precedence of multiplicative operators (`*`, `.*`).
+pub const PRECEDENCE_MUL: u8 = 20;
+/// Precedence of a power. Anything that is not atomic is put into parentheses when used as a base.
+const PRECEDENCE_POW: u8 = 30;
+/// Precedence of identifiers, numbers and function applications.
+const PRECEDENCE_ATOM: u8 = u8::MAX;
+
+/// How an operator is rendered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Notation {
+    /// A named function applied to the arguments, like `\sin\left(x\right)`. `latex` is the LaTeX command
+    /// (like `\sin` or `\operatorname{relu}`), `mathml` is the function name (like `sin`).
+    Function {
+        latex: &'static str,
+        mathml: &'static str,
+    },
+    /// A binary infix operator, like `+`. Operators with higher `precedence` bind stronger. An `associative`
+    /// operator does not need parentheses around the right operand with the same operator, i.e. `a + (b + c)`
+    /// is rendered as `a + b + c`, while `a - (b - c)` and `a + (b - c)` keep the parentheses.
+    Infix {
+        latex: &'static str,
+        mathml: &'static str,
+        precedence: u8,
+        associative: bool,
+    },
+    /// The argument raised to a constant integer power, like `x^{2}`.
+    PowI(i32),
+    /// The first argument raised to the power of the second argument.
+    Pow,
+    /// The square root of the argument, drawn as a radical.
+    Sqrt,
+}
+
+/// An operator that knows how to render itself in math notation.
+pub trait RenderOperator: Operator {
+    fn notation(&self) -> Notation;
+}
+
+impl<'a, F, OP1, OP2> Expr<'a, F, OP1, OP2>
+where
+    F: ComputValue,
+    OP1: RenderOperator,
+    OP2: RenderOperator,
+{
+    /// Render the expression as LaTeX (without the surrounding `$`).
+    pub fn to_latex(&self) -> String {
+        self.render(&Latex, &self.ident).0
+    }
+
+    /// Render the expression as a MathML `<math>` element.
+    pub fn to_mathml(&self) -> String {
+        let (body, _) = self.render(&MathMl, &self.ident);
+        format!(
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"><mrow>{}</mrow></math>",
+            body
+        )
+    }
+
+    /// Render a node and return the rendered string together with the precedence of the outermost operator,
+    /// so the caller can decide if parentheses are needed.
+    fn render<T: Target>(&self, target: &T, node_ident: &Ident) -> (String, u8) {
+        let node = self
+            .eb
+            .id_to_node
+            .borrow()
+            .get(node_ident)
+            .unwrap_or_else(|| panic!("No node for ident {}", node_ident))
+            .clone();
+        match node {
+            ExprNode::Const(value) => {
+                let s = format!("{}", value);
+                if s.starts_with('-') {
+                    // Negative numbers are not atomic, and bind weaker than any binary operator.
+                    (target.number(&s), PRECEDENCE_ADD - 1)
+                } else {
+                    (target.number(&s), PRECEDENCE_ATOM)
+                }
+            }
+            ExprNode::Variable(name_id) => {
+                let name = self.eb.get_name(&name_id).unwrap();
+                (render_name(target, &name), PRECEDENCE_ATOM)
+            }
+            ExprNode::Parameter(name_id, _) => {
+                let name = name_id
+                    .and_then(|id| self.eb.get_name(&id))
+                    .unwrap_or(format!("{}", node_ident));
+                (render_name(target, &name), PRECEDENCE_ATOM)
+            }
+            ExprNode::Ary1(op, arg1) => {
+                let arg1 = self.render(target, &arg1);
+                render_notation(target, op.notation(), &[arg1], None)
+            }
+            ExprNode::Ary2(op, arg1, arg2) => {
+                let rhs_notation = match self.eb.id_to_node.borrow().get(&arg2) {
+                    Some(ExprNode::Ary2(rhs_op, ..)) => Some(rhs_op.notation()),
+                    _ => None,
+                };
+                let arg1 = self.render(target, &arg1);
+                let arg2 = self.render(target, &arg2);
+                render_notation(target, op.notation(), &[arg1, arg2], rhs_notation)
+            }
+        }
+    }
+}
+
+/// Render an operator applied to the rendered `args`. `rhs_notation` is the notation of the operator of the right
+/// operand, if it's a binary operator.
+fn render_notation<T: Target>(
+    target: &T,
+    notation: Notation,
+    args: &[(String, u8)],
+    rhs_notation: Option<Notation>,
+) -> (String, u8) {
+    match notation {
+        Notation::Function { latex, mathml } => {
+            let args: Vec<&str> = args.iter().map(|(s, _)| s.as_str()).collect();
+            (target.function(latex, mathml, &args), PRECEDENCE_ATOM)
+        }
+        Notation::Infix {
+            latex,
+            mathml,
+            precedence,
+            associative,
+        } => {
+            let (lhs, lhs_precedence) = &args[0];
+            let (rhs, rhs_precedence) = &args[1];
+            let lhs = if *lhs_precedence < precedence {
+                target.parens(lhs)
+            } else {
+                lhs.to_owned()
+            };
+            let rhs = if *rhs_precedence < precedence
+                || (*rhs_precedence == precedence
+                    && !(associative && rhs_notation == Some(notation)))
+            {
+                target.parens(rhs)
+            } else {
+                rhs.to_owned()
+            };
+            (target.infix(&lhs, latex, mathml, &rhs), precedence)
+        }
+        Notation::PowI(p) => {
+            let base = parens_for_base(target, &args[0]);
+            let exp = target.number(&format!("{}", p));
+            (target.power(&base, &exp), PRECEDENCE_POW)
+        }
+        Notation::Pow => {
+            let base = parens_for_base(target, &args[0]);
+            let (exp, _) = &args[1];
+            (target.power(&base, exp), PRECEDENCE_POW)
+        }
+        Notation::Sqrt => (target.sqrt(&args[0].0), PRECEDENCE_ATOM),
+    }
+}
+
+fn parens_for_base<T: Target>(target: &T, (base, precedence): &(String, u8)) -> String {
+    if *precedence == PRECEDENCE_ATOM {
+        base.to_owned()
+    } else {
+        target.parens(base)
+    }
+}
+
+/// Split a name into a base and a subscript, so `x_1` and `p1` become `x` with subscript `1` and `p` with
+/// subscript `1`. Unnamed parameters (displayed as `_1`) are rendered as `\theta_{1}`.
+fn render_name<T: Target>(target: &T, name: &str) -> String {
+    if let Some((base, sub)) = name.split_once('_') {
+        let base = if base.is_empty() { None } else { Some(base) };
+        return target.ident(base, Some(sub));
+    }
+    let base = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    if base.is_empty() || base.len() == name.len() {
+        target.ident(Some(name), None)
+    } else {
+        target.ident(Some(base), Some(&name[base.len()..]))
+    }
+}
+
+/// The output format of the renderer.
+trait Target {
+    /// Identifier with an optional subscript. When `base` is None, the identifier is an unnamed parameter.
+    fn ident(&self, base: Option<&str>, sub: Option<&str>) -> String;
+    fn number(&self, s: &str) -> String;
+    fn parens(&self, s: &str) -> String;
+    fn function(&self, latex: &str, mathml: &str, args: &[&str]) -> String;
+    fn infix(&self, lhs: &str, latex: &str, mathml: &str, rhs: &str) -> String;
+    fn power(&self, base: &str, exp: &str) -> String;
+    fn sqrt(&self, s: &str) -> String;
+}
+
+struct Latex;
+
+impl Target for Latex {
+    fn ident(&self, base: Option<&str>, sub: Option<&str>) -> String {
+        let base = match base {
+            None => "\\theta".to_owned(),
+            Some(base) if base.chars().count() > 1 => format!("\\mathrm{{{}}}", base),
+            Some(base) => base.to_owned(),
+        };
+        match sub {
+            Some(sub) => format!("{}_{{{}}}", base, sub),
+            None => base,
+        }
+    }
+
+    fn number(&self, s: &str) -> String {
+        s.to_owned()
+    }
+
+    fn parens(&self, s: &str) -> String {
+        format!("\\left({}\\right)", s)
+    }
+
+    fn function(&self, latex: &str, _mathml: &str, args: &[&str]) -> String {
+        format!("{}{}", latex, self.parens(&args.join(", ")))
+    }
+
+    fn infix(&self, lhs: &str, latex: &str, _mathml: &str, rhs: &str) -> String {
+        format!("{} {} {}", lhs, latex, rhs)
+    }
+
+    fn power(&self, base: &str, exp: &str) -> String {
+        format!("{}^{{{}}}", base, exp)
+    }
+
+    fn sqrt(&self, s: &str) -> String {
+        format!("\\sqrt{{{}}}", s)
+    }
+}
+
+struct MathMl;
+
+impl Target for MathMl {
+    fn ident(&self, base: Option<&str>, sub: Option<&str>) -> String {
+        let base = match base {
+            None => "<mi>&#x3B8;</mi>".to_owned(),
+            Some(base) => format!("<mi>{}</mi>", base),
+        };
+        match sub {
+            Some(sub) => format!("<msub>{}<mn>{}</mn></msub>", base, sub),
+            None => base,
+        }
+    }
+
+    fn number(&self, s: &str) -> String {
+        match s.strip_prefix('-') {
+            Some(s) => format!("<mrow><mo>&#x2212;</mo><mn>{}</mn></mrow>", s),
+            None => format!("<mn>{}</mn>", s),
+        }
+    }
+
+    fn parens(&self, s: &str) -> String {
+        format!("<mrow><mo>(</mo>{}<mo>)</mo></mrow>", s)
+    }
+
+    fn function(&self, _latex: &str, mathml: &str, args: &[&str]) -> String {
+        format!(
+            "<mrow><mi>{}</mi><mo>&#x2061;</mo>{}</mrow>",
+            mathml,
+            self.parens(&args.join("<mo>,</mo>"))
+        )
+    }
+
+    fn infix(&self, lhs: &str, _latex: &str, mathml: &str, rhs: &str) -> String {
+        format!("<mrow>{}<mo>{}</mo>{}</mrow>", lhs, mathml, rhs)
+    }
+
+    fn power(&self, base: &str, exp: &str) -> String {
+        format!("<msup><mrow>{}</mrow><mrow>{}</mrow></msup>", base, exp)
+    }
+
+    fn sqrt(&self, s: &str) -> String {
+        format!("<msqrt>{}</msqrt>", s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        core_syntax::ExprBuilder,
+        float::syntax::{AsConst, FloatOperAry1, FloatOperAry2},
+    };
+
+    #[test]
+    fn latex_precedence() {
+        let eb = new_eb();
+        let a = eb.new_variable("a");
+        let b = eb.new_variable("b");
+        let c = eb.new_variable("c");
+        assert_eq!("a + b \\cdot c", (a + b * c).to_latex());
+        assert_eq!("\\left(a + b\\right) \\cdot c", ((a + b) * c).to_latex());
+        assert_eq!("a - b + c", ((a - b) + c).to_latex());
+        assert_eq!("a - \\left(b + c\\right)", (a - (b + c)).to_latex());
+        assert_eq!("a + \\left(b - c\\right)", (a + (b - c)).to_latex());
+    }
+
+    #[test]
+    fn latex_functions_and_powers() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let y = eb.new_variable("y");
+        assert_eq!(
+            "\\sin\\left(x\\right) \\cdot \\ln\\left(x\\right)",
+            (x.sin() * x.ln()).to_latex()
+        );
+        assert_eq!("\\left(x + y\\right)^{2}", (x + y).powi(2).to_latex());
+        assert_eq!("x^{y}", x.pow(y).to_latex());
+        assert_eq!("\\cos\\left(x\\right)^{3}", x.cos().powi(3).to_latex());
+        assert_eq!(
+            "\\operatorname{relu}\\left(x - 2\\right)",
+            (x - 2.0.as_const(&eb)).relu().to_latex()
+        );
+        assert_eq!(
+            "x + \\left(-2\\right)",
+            (x + (-2.0).as_const(&eb)).to_latex()
+        );
+    }
+
+    #[test]
+    fn latex_subscripts() {
+        let eb = new_eb();
+        let x = eb.new_variable("x_1");
+        let lin = x.linreg();
+        let p = eb.new_named_parameter("p2", 1.0);
+        let loss = eb.new_variable("loss");
+        let y = lin + p + loss;
+        assert_eq!(
+            "\\theta_{1} \\cdot x_{1} + \\theta_{2} + p_{2} + \\mathrm{loss}",
+            y.to_latex()
+        );
+    }
+
+    #[test]
+    fn mathml() {
+        let eb = new_eb();
+        let x = eb.new_variable("x1");
+        let y = (x - 2.0.as_const(&eb)).powi(2);
+        assert_eq!(
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"><mrow>\
+            <msup><mrow><mrow><mo>(</mo><mrow><msub><mi>x</mi><mn>1</mn></msub><mo>&#x2212;</mo><mn>2</mn></mrow><mo>)</mo></mrow></mrow>\
+            <mrow><mn>2</mn></mrow></msup>\
+            </mrow></math>",
+            y.to_mathml()
+        );
+    }
+
+    #[test]
+    fn sqrt() {
+        let eb = new_eb();
+        let x = eb.new_variable("x");
+        let y = eb.new_variable("y");
+        assert_eq!(
+            "\\sqrt{x + y} \\cdot 2",
+            ((x + y).sqrt() * 2.0.as_const(&eb)).to_latex()
+        );
+        assert_eq!("\\sqrt{x}^{2}", x.sqrt().powi(2).to_latex());
+        assert_eq!(
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"><mrow>\
+            <msqrt><mi>x</mi></msqrt>\
+            </mrow></math>",
+            x.sqrt().to_mathml()
+        );
+    }
+
+    fn new_eb() -> ExprBuilder<f32, FloatOperAry1, FloatOperAry2> {
+        ExprBuilder::new()
+    }
+}
@@ -0,0 +1,327 @@
+//! Compile a [ComputGraph] into a linear tape for fast repeated evaluation.
+//!
+//! [ComputGraph] walks a map of nodes, clones the nodes and dispatches through `&dyn Calculator` on every
+//! `forward` and `backward`. [Tape] is built once out of the graph: the nodes reachable from the output are
+//! ordered topologically and each node gets a preassigned value slot. Forward and backward are then tight loops
+//! over the instructions, without maps, clones of the nodes or recursion.
+use std::collections::BTreeMap;
+
+use crate::{
+    compute::{ComputGraph, Node, OperCalculator},
+    core_syntax::{ComputValue, Ident, Operator},
+};
+
+/// A single operation on the tape. The arguments and the output are indices of the value slots.
+#[derive(Debug, Clone, Copy)]
+pub enum Instruction<OP1, OP2> {
+    Ary1 {
+        oper: OP1,
+        arg1: usize,
+        out: usize,
+    },
+    Ary2 {
+        oper: OP2,
+        arg1: usize,
+        arg2: usize,
+        out: usize,
+    },
+}
+
+/// What a value slot holds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SlotKind {
+    Const,
+    Variable,
+    Parameter,
+    Computed,
+}
+
+pub struct Tape<'c, F, OP1, OP2, C>
+where
+    F: ComputValue,
+    OP1: Operator,
+    OP2: Operator,
+    C: OperCalculator<OP1, OP2, F>,
+{
+    calculator: &'c C,
+    /// Instructions in topological order, i.e. the arguments are computed before they are used.
+    instructions: Vec<Instruction<OP1, OP2>>,
+    /// Ident of the node for each slot.
+    idents: Vec<Ident>,
+    kinds: Vec<SlotKind>,
+    slot_of: BTreeMap<Ident, usize>,
+    output: usize,
+    primals: Vec<Option<F>>,
+    /// Adjoins of the current backward pass.
+    grads: Vec<Option<F>>,
+    /// Adjoins accumulated over the backward passes, with the count of the passes.
+    adjoins: Vec<Option<(F, u32)>>,
+}
+
+impl<'c, F, OP1, OP2, C> Tape<'c, F, OP1, OP2, C>
+where
+    F: ComputValue,
+    OP1: Operator,
+    OP2: Operator,
+    C: OperCalculator<OP1, OP2, F>,
+{
+    /// Compile the part of the graph needed to compute `output`. The constants, the parameters and the variables
+    /// that are already set are copied from the graph.
+    pub fn new(
+        cg: &ComputGraph<F, OP1, OP2>,
+        output: &dyn AsRef<Ident>,
+        calculator: &'c C,
+    ) -> Tape<'c, F, OP1, OP2, C> {
+        let output = *output.as_ref();
+        // Collect the nodes reachable from the output. The idents are assigned in the order the nodes are created,
+        // and the arguments are always created before the node that uses them, so the ident order is topological.
+        let mut nodes: BTreeMap<Ident, Node<F, OP1, OP2>> = BTreeMap::new();
+        let mut stack = vec![output];
+        while let Some(ident) = stack.pop() {
+            if nodes.contains_key(&ident) {
+                continue;
+            }
+            let node = cg.get_node(&ident);
+            stack.extend(node.args());
+            nodes.insert(ident, node);
+        }
+
+        let slot_of: BTreeMap<Ident, usize> = nodes
+            .keys()
+            .enumerate()
+            .map(|(slot, ident)| (*ident, slot))
+            .collect();
+        let mut instructions = Vec::new();
+        let mut idents = Vec::new();
+        let mut kinds = Vec::new();
+        let mut primals = Vec::new();
+        for (slot, (ident, node)) in nodes.iter().enumerate() {
+            idents.push(*ident);
+            primals.push(match node {
+                Node::Ary1 { .. } | Node::Ary2 { .. } => None,
+                _ => node.primal_or_const().cloned(),
+            });
+            kinds.push(match node {
+                Node::Const(_) => SlotKind::Const,
+                Node::Variable { .. } => SlotKind::Variable,
+                Node::Parameter { .. } => SlotKind::Parameter,
+                Node::Ary1 { .. } | Node::Ary2 { .. } => SlotKind::Computed,
+            });
+            match node {
+                Node::Ary1 { oper, arg1, .. } => instructions.push(Instruction::Ary1 {
+                    oper: *oper,
+                    arg1: slot_of[arg1],
+                    out: slot,
+                }),
+                Node::Ary2 {
+                    oper, arg1, arg2, ..
+                } => instructions.push(Instruction::Ary2 {
+                    oper: *oper,
+                    arg1: slot_of[arg1],
+                    arg2: slot_of[arg2],
+                    out: slot,
+                }),
+                _ => (),
+            }
+        }
+        let n_slots = idents.len();
+        Tape {
+            calculator,
+            instructions,
+            idents,
+            kinds,
+            output: slot_of[&output],
+            slot_of,
+            primals,
+            grads: vec![None; n_slots],
+            adjoins: vec![None; n_slots],
+        }
+    }
+
+    pub fn instructions(&self) -> &[Instruction<OP1, OP2>] {
+        &self.instructions
+    }
+
+    pub fn n_slots(&self) -> usize {
+        self.idents.len()
+    }
+
+    /// Ident of the node held in the slot.
+    pub fn ident(&self, slot: usize) -> Ident {
+        self.idents[slot]
+    }
+
+    pub fn slot_kind(&self, slot: usize) -> SlotKind {
+        self.kinds[slot]
+    }
+
+    /// Slot of the output node.
+    pub fn output_slot(&self) -> usize {
+        self.output
+    }
+
+    /// Return the slot of the node, or None if the node is not needed to compute the output.
+    pub fn slot(&self, ident: &dyn AsRef<Ident>) -> Option<usize> {
+        self.slot_of.get(ident.as_ref()).copied()
+    }
+
+    /// Set variable to a value, replacing the old value. Variables not needed for the output are ignored.
+    pub fn set_variable(&mut self, ident: &dyn AsRef<Ident>, value: F) {
+        if let Some(slot) = self.slot(ident) {
+            self.set_variable_slot(slot, value);
+        }
+    }
+
+    /// Like [Tape::set_variable] but without the lookup of the slot, for the hot loops.
+    pub fn set_variable_slot(&mut self, slot: usize, value: F) {
+        assert_eq!(
+            self.kinds[slot],
+            SlotKind::Variable,
+            "Node {} is not a Variable!",
+            self.idents[slot]
+        );
+        self.primals[slot] = Some(value);
+    }
+
+    /// Calculate primals of all the slots and return the output.
+    pub fn forward(&mut self) -> F {
+        for instruction in self.instructions.iter() {
+            match instruction {
+                Instruction::Ary1 { oper, arg1, out } => {
+                    let a = self.primals[*arg1]
+                        .as_ref()
+                        .unwrap_or_else(|| panic!("Primal missing for {}", self.idents[*arg1]));
+                    let primal = self.calculator.forward_ary1(oper, a);
+                    self.primals[*out] = Some(primal);
+                }
+                Instruction::Ary2 {
+                    oper,
+                    arg1,
+                    arg2,
+                    out,
+                } => {
+                    let a = self.primals[*arg1]
+                        .as_ref()
+                        .unwrap_or_else(|| panic!("Primal missing for {}", self.idents[*arg1]));
+                    let b = self.primals[*arg2]
+                        .as_ref()
+                        .unwrap_or_else(|| panic!("Primal missing for {}", self.idents[*arg2]));
+                    let primal = self.calculator.forward_ary2(oper, a, b);
+                    self.primals[*out] = Some(primal);
+                }
+            }
+        }
+        self.primals[self.output]
+            .clone()
+            .expect("Bug: output primal missing after forward!")
+    }
+
+    /// Calculate adjoins of the output w.r.t. all the slots, and accumulate them. Must be run after [Tape::forward].
+    pub fn backward(&mut self) {
+        for grad in self.grads.iter_mut() {
+            grad.take();
+        }
+        let output = self.primals[self.output]
+            .clone()
+            .expect("Run forward before backward!");
+        self.grads[self.output] = Some(F::default_adjoin(output));
+        for instruction in self.instructions.iter().rev() {
+            match instruction {
+                Instruction::Ary1 { oper, arg1, out } => {
+                    let adjoin = if let Some(adjoin) = self.grads[*out].take() {
+                        adjoin
+                    } else {
+                        continue;
+                    };
+                    let a = self.primals[*arg1].as_ref().unwrap();
+                    let primal = self.primals[*out].as_ref().unwrap();
+                    let adjoin_a = self.calculator.backward_ary1(oper, a, primal, &adjoin);
+                    add_grad(&mut self.grads[*arg1], adjoin_a);
+                    add_adjoin(&mut self.adjoins[*out], adjoin);
+                }
+                Instruction::Ary2 {
+                    oper,
+                    arg1,
+                    arg2,
+                    out,
+                } => {
+                    let adjoin = if let Some(adjoin) = self.grads[*out].take() {
+                        adjoin
+                    } else {
+                        continue;
+                    };
+                    let a = self.primals[*arg1].as_ref().unwrap();
+                    let b = self.primals[*arg2].as_ref().unwrap();
+                    let primal = self.primals[*out].as_ref().unwrap();
+                    let (adjoin_a, adjoin_b) =
+                        self.calculator.backward_ary2(oper, a, b, primal, &adjoin);
+                    add_grad(&mut self.grads[*arg1], adjoin_a);
+                    add_grad(&mut self.grads[*arg2], adjoin_b);
+                    add_adjoin(&mut self.adjoins[*out], adjoin);
+                }
+            }
+        }
+        // What is left are the adjoins of the leaves.
+        for (slot, grad) in self.grads.iter_mut().enumerate() {
+            if let Some(grad) = grad.take() {
+                if self.kinds[slot] != SlotKind::Const {
+                    add_adjoin(&mut self.adjoins[slot], grad);
+                }
+            }
+        }
+    }
+
+    pub fn primal(&self, ident: &dyn AsRef<Ident>) -> Option<F> {
+        self.primals[self.slot(ident)?].clone()
+    }
+
+    /// Adjoin accumulated over the backward passes, or None if there was no backward pass since the last reset.
+    pub fn adjoin(&self, ident: &dyn AsRef<Ident>) -> Option<F> {
+        let (adjoin, _) = self.adjoins[self.slot(ident)?].clone()?;
+        Some(adjoin)
+    }
+
+    pub fn reset_adjoins(&mut self) {
+        for adjoin in self.adjoins.iter_mut() {
+            adjoin.take();
+        }
+    }
+
+    /// Move parameters against the adjoins averaged over the backward passes, and reset the adjoins.
+    pub fn update_params_lr(&mut self, learning_rate: f32) {
+        for slot in 0..self.idents.len() {
+            if self.kinds[slot] != SlotKind::Parameter {
+                continue;
+            }
+            if let Some((adjoin, cnt)) = self.adjoins[slot].take() {
+                let primal = self.primals[slot].take().unwrap();
+                self.primals[slot] = Some(primal + adjoin * -1.0 * (learning_rate / cnt as f32));
+            }
+        }
+        self.reset_adjoins();
+    }
+
+    /// Copy the parameters (e.g. after training on the tape) back to the graph.
+    pub fn store_params(&self, cg: &mut ComputGraph<F, OP1, OP2>) {
+        for slot in 0..self.idents.len() {
+            if self.kinds[slot] == SlotKind::Parameter {
+                let value = self.primals[slot].clone().unwrap();
+                cg.reset_primal_of_parameter(&self.idents[slot], value);
+            }
+        }
+    }
+}
+
+fn add_grad<F: ComputValue>(grad: &mut Option<F>, value: F) {
+    *grad = Some(match grad.take() {
+        Some(old) => old + value,
+        None => value,
+    });
+}
+
+fn add_adjoin<F: ComputValue>(adjoin: &mut Option<(F, u32)>, value: F) {
+    *adjoin = Some(match adjoin.take() {
+        Some((old, cnt)) => (old + value, cnt + 1),
+        None => (value, 1),
+    });
+}
@@ -0,0 +1,73 @@
+// Generated by rs-autograd. Do not edit.
+
+pub fn relu_sum(x: f32) -> f32 {
+    let v1: f32 = -0.25; // bias
+    let v2: f32 = -1.5;
+    let v3: f32 = 0.9;
+    let v8: f32 = 0.0;
+    let v9: f32 = -1.8;
+    let v14: f32 = 1.5;
+    let v15: f32 = 0.9;
+    let v4 = x - v2;
+    let v5 = if v4 <= 0.0 { 0.0 } else { v4 };
+    let v6 = v5 * v3;
+    let v7 = v1 + v6;
+    let v10 = x - v8;
+    let v11 = if v10 <= 0.0 { 0.0 } else { v10 };
+    let v12 = v11 * v9;
+    let v13 = v7 + v12;
+    let v16 = x - v14;
+    let v17 = if v16 <= 0.0 { 0.0 } else { v16 };
+    let v18 = v17 * v15;
+    v13 + v18
+}
+
+pub fn relu_sum_grad(x: f32) -> (f32, [f32; 1]) {
+    let v1: f32 = -0.25; // bias
+    let v2: f32 = -1.5;
+    let v3: f32 = 0.9;
+    let v8: f32 = 0.0;
+    let v9: f32 = -1.8;
+    let v14: f32 = 1.5;
+    let v15: f32 = 0.9;
+    let v4 = x - v2;
+    let v5 = if v4 <= 0.0 { 0.0 } else { v4 };
+    let v6 = v5 * v3;
+    let v7 = v1 + v6;
+    let v10 = x - v8;
+    let v11 = if v10 <= 0.0 { 0.0 } else { v10 };
+    let v12 = v11 * v9;
+    let v13 = v7 + v12;
+    let v16 = x - v14;
+    let v17 = if v16 <= 0.0 { 0.0 } else { v16 };
+    let v18 = v17 * v15;
+    let v19 = v13 + v18;
+    let mut g_x = 0.0_f32;
+    let mut g_v4 = 0.0_f32;
+    let mut g_v5 = 0.0_f32;
+    let mut g_v6 = 0.0_f32;
+    let mut g_v7 = 0.0_f32;
+    let mut g_v10 = 0.0_f32;
+    let mut g_v11 = 0.0_f32;
+    let mut g_v12 = 0.0_f32;
+    let mut g_v13 = 0.0_f32;
+    let mut g_v16 = 0.0_f32;
+    let mut g_v17 = 0.0_f32;
+    let mut g_v18 = 0.0_f32;
+    let g_v19 = 1.0_f32;
+    g_v13 += g_v19 * (1.0);
+    g_v18 += g_v19 * (1.0);
+    g_v17 += g_v18 * (v15);
+    g_v16 += g_v17 * (if v16 <= 0.0 { 0.0 } else { 1.0 });
+    g_x += g_v16 * (1.0);
+    g_v7 += g_v13 * (1.0);
+    g_v12 += g_v13 * (1.0);
+    g_v11 += g_v12 * (v9);
+    g_v10 += g_v11 * (if v10 <= 0.0 { 0.0 } else { 1.0 });
+    g_x += g_v10 * (1.0);
+    g_v6 += g_v7 * (1.0);
+    g_v5 += g_v6 * (v3);
+    g_v4 += g_v5 * (if v4 <= 0.0 { 0.0 } else { 1.0 });
+    g_x += g_v4 * (1.0);
+    (v19, [g_x])
+}
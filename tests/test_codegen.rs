@@ -0,0 +1,49 @@
+use rs_autograd::{
+    compute::ComputGraph,
+    core_syntax::ExprBuilder,
+    float::{
+        calculator::FloatCalculator,
+        codegen::{to_rust, Opts},
+        syntax::{FloatOperAry1, FloatOperAry2},
+    },
+};
+
+/// The generated code checked in, to verify that it compiles and computes the same as the graph.
+mod generated {
+    include!("generated/relu_sum.rs");
+}
+
+/// A sum of ReLUs like the one approximating sine, with fixed "trained" parameters.
+#[test]
+fn test_codegen_relu_sum() {
+    let eb = ExprBuilder::<f32, FloatOperAry1, FloatOperAry2>::new();
+    let x = eb.new_variable("x");
+    let knots = [-1.5_f32, 0.0, 1.5];
+    let slopes = [0.9_f32, -1.8, 0.9];
+    let mut y = eb.new_named_parameter("bias", -0.25);
+    for (knot, slope) in knots.iter().zip(slopes) {
+        let knot = eb.new_parameter(*knot);
+        let slope = eb.new_parameter(slope);
+        y = y + (x - knot).relu() * slope;
+    }
+    let [x, y] = [x, y].map(|e| e.ident);
+    let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+
+    let code = to_rust(&cg, &y, &[Opts::FnName("relu_sum"), Opts::Gradient]);
+    assert_eq!(
+        include_str!("generated/relu_sum.rs"),
+        code,
+        "The generated code changed, update tests/generated/relu_sum.rs"
+    );
+
+    for x_inp in [-2.0_f32, -1.0, 0.3, 1.0, 2.5] {
+        cg.reset_state_for_next_epoch();
+        cg.reset_primal_of_variable(&x, x_inp);
+        let expected = cg.forward(&y);
+        cg.backward(&y);
+        assert_eq!(expected, generated::relu_sum(x_inp));
+        let (actual, grad) = generated::relu_sum_grad(x_inp);
+        assert_eq!(expected, actual);
+        assert_eq!(cg.adjoin(&x).unwrap(), grad[0]);
+    }
+}
@@ -1,40 +1,24 @@
-mod utils;
 use ndarray as nd;
 use rand::{self, rngs::StdRng};
 use rand::{Rng, SeedableRng};
 use rs_autograd::{
     compute::ComputGraph,
     core_syntax::ExprBuilder,
+    losses::{Loss, Reduction},
     nar::{
         calculator::MatrixCalculator,
         syntax::{MatrixF32, NaOperAry1, NaOperAry2},
     },
 };
-use utils::{assert_functions_similar, FloatRange, Opts};
 
-#[ignore]
 #[test]
-fn test_conv2d_5x5_forward() {
-    // let input_range = FloatRange::new(-3.1, 3.2, 0.1);
-
-    // let n_params: usize = 30;
-    // let mut rng = StdRng::seed_from_u64(42);
-    // let mut get_init_param = || {
-    //     let arr = nd::ArrayD::from_shape_fn(sh((n_params, 1)), |_| rng.gen_range(-3.0..3.0));
-    //     MatrixF32::new_m(arr)
-    // };
-
+fn test_conv2d_5x4_forward() {
     let eb = new_eb();
     let x = eb.new_variable("x");
     let k = eb.new_variable("k");
     let y = x.conv2d(k);
-    // let p0 = eb.new_named_parameter("p0", get_init_param());
-    // let p1 = eb.new_named_parameter("p1", get_init_param());
-    // let y = ((x - p0).relu() * p1).sum();
-    // let t = eb.new_variable("t");
-    // let loss = (y - t).powi(2);
 
-    let [x, y] = [x, y].map(|p| p.ident);
+    let [x, k, y] = [x, k, y].map(|p| p.ident);
     let mut cg = ComputGraph::<MatrixF32, _, _>::new(eb, &MatrixCalculator);
 
     cg.reset_primal_of_variable(&x, MatrixF32::new_m(get_m(5, 4)));
@@ -43,16 +27,80 @@ fn test_conv2d_5x5_forward() {
     //  [8, 9, 10, 11],
     //  [12, 13, 14, 15],
     //  [16, 17, 18, 19]]
+    cg.reset_primal_of_variable(&k, MatrixF32::new_m(get_m(2, 2)));
+    // [[0, 1],
+    //  [2, 3]]
 
     let m_forward = cg.forward(&y);
-    panic!("{}", m_forward)
+    let expected = nd::ArrayD::from_shape_fn(sh((4, 3)), |ix| {
+        let v = (ix[0] * 4 + ix[1]) as f32;
+        v + 2.0 * (v + 4.0) + 3.0 * (v + 5.0) + 1.0
+    });
+    assert_eq!(m_forward.m(), Some(&expected));
+}
+
+/// Fit the kernel of a single convolution to the images convolved by a known kernel.
+#[test]
+fn test_conv2d_fit_kernel() {
+    let target_kernel = nd::ArrayD::from_shape_vec(
+        sh((3, 3)),
+        vec![0.5, -1.0, 0.25, 1.0, 0.0, -0.5, -0.25, 0.75, 1.5],
+    )
+    .unwrap();
+
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut random_m = |shape| nd::ArrayD::from_shape_fn(sh(shape), |_| rng.gen_range(-1.0..1.0));
+    let init_kernel = random_m((3, 3));
+    let samples: Vec<(nd::ArrayD<f32>, nd::ArrayD<f32>)> = (0..8)
+        .map(|_| {
+            let input = random_m((7, 6));
+            let target = convolve(&input, &target_kernel);
+            (input, target)
+        })
+        .collect();
+
+    let eb = new_eb();
+    let x = eb.new_variable("x");
+    let k = eb.new_named_parameter("k", MatrixF32::new_m(init_kernel));
+    let t = eb.new_variable("t");
+    let loss = x.conv2d(k).loss(t, Loss::Mse, Reduction::Mean);
+
+    let [x, k, t, loss] = [x, k, t, loss].map(|p| p.ident);
+    let mut cg = ComputGraph::<MatrixF32, _, _>::new(eb, &MatrixCalculator);
 
-    //         cg.reset_primal_of_variable(&t, MatrixF32::V(target_poly(x_inp)));
-    //         total_loss += cg.forward(&loss).v().unwrap();
-    //         cg.backward(&loss);
-    //     }
+    let mut total_loss = 0.0;
+    for _ in 0..200 {
+        cg.zero_grad();
+        total_loss = 0.0;
+        for (input, target) in samples.iter() {
+            cg.reset_primal_of_variable(&x, MatrixF32::new_m(input.clone()));
+            cg.reset_primal_of_variable(&t, MatrixF32::new_m(target.clone()));
+            total_loss += cg.accumulate(&loss).v().unwrap();
+        }
+        total_loss /= samples.len() as f32;
+        cg.step(0.5).unwrap();
+    }
 
-    // }
+    assert!(total_loss < 1e-6, "loss {}", total_loss);
+    let fitted = cg.primal(&k);
+    let max_err = (fitted.m().unwrap() - &target_kernel)
+        .iter()
+        .fold(0.0_f32, |acc, e| acc.max(e.abs()));
+    assert!(
+        max_err < 1e-3,
+        "kernel\n{}\ntarget\n{}",
+        fitted,
+        target_kernel
+    );
+}
+
+fn convolve(input: &nd::ArrayD<f32>, kernel: &nd::ArrayD<f32>) -> nd::ArrayD<f32> {
+    let (kr, kc) = (kernel.shape()[0], kernel.shape()[1]);
+    let shape = (input.shape()[0] - kr + 1, input.shape()[1] - kc + 1);
+    nd::ArrayD::from_shape_fn(sh(shape), |ix| {
+        let window = input.slice(nd::s![ix[0]..ix[0] + kr, ix[1]..ix[1] + kc]);
+        (&window * kernel).sum()
+    })
 }
 
 fn new_eb() -> ExprBuilder<MatrixF32, NaOperAry1, NaOperAry2> {
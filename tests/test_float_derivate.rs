@@ -10,7 +10,7 @@ use rs_autograd::{
 use approx_eq::assert_approx_eq;
 
 mod utils;
-use utils::{assert_function_and_derivative_similar, FloatRange, Opts};
+use utils::{assert_function_and_derivative_similar, assert_functions_similar, FloatRange, Opts};
 
 #[test]
 fn compare_sin_cos() {
@@ -127,6 +127,95 @@ fn test_pow() {
     );
 }
 
+#[test]
+fn clamp() {
+    let input_range = FloatRange::new(-2.0, 2.0, 0.01);
+    let f = |x: f32| x.clamp(-1.0, 0.5);
+
+    let eb = new_eb();
+    let x = eb.new_variable("x");
+    let y = x.clamp((-1.0).as_const(&eb), 0.5.as_const(&eb));
+    let [x, y] = [x, y].map(|e| e.ident);
+    let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+    let mut df = |x_inp: f32| {
+        cg.reset_state_for_next_epoch();
+        cg.set_variable(&x, x_inp);
+        assert_eq!(f(x_inp), cg.forward(&y));
+        cg.backward(&y);
+        cg.adjoin(&x).unwrap()
+    };
+
+    assert_function_and_derivative_similar(
+        f,
+        &mut df,
+        &[Opts::InputRange(input_range), Opts::TestName("clamp")],
+    );
+}
+
+#[test]
+fn activations() {
+    // The grid is shifted so no point falls on a kink, where the finite difference is meaningless.
+    let input_range = FloatRange::new(-3.005, 3.0, 0.01);
+    let sigmoid = |x: f32| 1.0 / (1.0 + (-x).exp());
+    let gelu = |x: f32| 0.5 * x * (1.0 + (0.797_884_6 * (x + 0.044_715 * x.powi(3))).tanh());
+    let f = |x: f32| {
+        x.tanh() + sigmoid(x) * gelu(x) + (1.0 + x.exp()).ln()
+            - (if x > 0.0 { x } else { x.exp() - 1.0 })
+            + (if x > 0.0 { x } else { 0.1 * x }) * 2.0
+    };
+
+    let eb = new_eb();
+    let x = eb.new_variable("x");
+    let y = x.tanh() + x.sigmoid() * x.gelu() + x.softplus() - x.elu()
+        + x.leaky_relu(0.1) * 2.0.as_const(&eb);
+    let [x, y] = [x, y].map(|e| e.ident);
+    let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+    let mut df = |x_inp: f32| {
+        cg.reset_state_for_next_epoch();
+        cg.set_variable(&x, x_inp);
+        assert_approx_eq!(f(x_inp) as f64, cg.forward(&y) as f64, 1e-4);
+        cg.backward(&y);
+        cg.adjoin(&x).unwrap()
+    };
+
+    assert_functions_similar(
+        |x| (f(x + 1e-3) - f(x - 1e-3)) / 2e-3,
+        &mut df,
+        &[Opts::InputRange(input_range), Opts::TestName("activations")],
+    );
+}
+
+#[test]
+fn div_sqrt_atan2() {
+    // The grid is shifted so no point falls on a kink, where the finite difference is meaningless.
+    let input_range = FloatRange::new(-3.005, 3.0, 0.01);
+    let f = |x: f32| x.atan2(2.0) / (x.abs() + 1.0).sqrt() + (-x).exp().max(x) - x.min(0.5);
+
+    let eb = new_eb();
+    let x = eb.new_variable("x");
+    let one = 1.0.as_const(&eb);
+    let y = x.atan2(2.0.as_const(&eb)) / (x.abs() + one).sqrt() + (-x).exp().max(x)
+        - x.min(0.5.as_const(&eb));
+    let [x, y] = [x, y].map(|e| e.ident);
+    let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+    let mut df = |x_inp: f32| {
+        cg.reset_state_for_next_epoch();
+        cg.set_variable(&x, x_inp);
+        assert_approx_eq!(f(x_inp) as f64, cg.forward(&y) as f64, 1e-4);
+        cg.backward(&y);
+        cg.adjoin(&x).unwrap()
+    };
+
+    assert_functions_similar(
+        |x| (f(x + 1e-3) - f(x - 1e-3)) / 2e-3,
+        &mut df,
+        &[
+            Opts::InputRange(input_range),
+            Opts::TestName("div_sqrt_atan2"),
+        ],
+    );
+}
+
 fn new_eb() -> ExprBuilder<f32, FloatOperAry1, FloatOperAry2> {
     ExprBuilder::new()
 }
@@ -141,6 +141,50 @@ fn test_fit_simple_relu() {
     );
 }
 
+/// The same as [test_fit_simple_relu], with the explicit gradient lifecycle.
+#[test]
+fn test_fit_simple_relu_zero_grad() {
+    let target_poly = |x: f32| {
+        let x = x - 2.0;
+        3.0 * (if x > 0.0 { x } else { 0.0 })
+    };
+
+    let eb = new_eb();
+    let x = eb.new_variable("x");
+    let params = [1, 2].map(|i| eb.new_parameter(0.01 * (i as f32)));
+    let y = (x - params[0]).relu() * params[1];
+    let t = eb.new_variable("t");
+    let loss = (y - t).powi(2);
+
+    let input_range = FloatRange::new(-2.0, 6.0, 0.1);
+    let [x, y, t, loss] = [x, y, t, loss].map(|p| p.ident);
+    let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+
+    for _ in 0..50 {
+        cg.zero_grad();
+        for x_inp in input_range.into_iter() {
+            cg.reset_primal_of_variable(&x, x_inp);
+            cg.reset_primal_of_variable(&t, target_poly(x_inp));
+            cg.accumulate(&loss);
+        }
+        cg.step(0.1).unwrap();
+    }
+
+    let mut df = |x_inp: f32| {
+        cg.reset_state_for_next_input();
+        cg.reset_primal_of_variable(&x, x_inp);
+        cg.forward(&y)
+    };
+    assert_functions_similar(
+        target_poly,
+        &mut df,
+        &[
+            Opts::InputRange(input_range),
+            Opts::TestName("test_fit_simple_relu_zero_grad.csv"),
+        ],
+    );
+}
+
 /// This works but is very slow, like 10 minutes to finish with
 /// n_epochs = 10000 and learn_rate = 0.01.
 #[ignore]
@@ -0,0 +1,62 @@
+mod utils;
+
+use rs_autograd::{
+    compute::ComputGraph,
+    core_syntax::ExprBuilder,
+    float::{
+        calculator::FloatCalculator,
+        syntax::{FloatOperAry1, FloatOperAry2},
+    },
+    losses::Loss,
+};
+use utils::{assert_functions_similar, FloatRange, Opts};
+
+/// Logistic regression: learn the probability of `x > 1` with the fused binary cross-entropy.
+#[test]
+fn test_fit_logistic_bce() {
+    let target = |x: f32| if x > 1.0 { 1.0 } else { 0.0 };
+
+    let eb = new_eb();
+    let x = eb.new_variable("x");
+    let t = eb.new_variable("t");
+    let logit = x.linreg();
+    let p = logit.sigmoid();
+    let loss = logit.loss(t, Loss::BceWithLogits);
+
+    let input_range = FloatRange::new(-2.0, 4.0, 0.1);
+    let [x, t, p, loss] = [x, t, p, loss].map(|e| e.ident);
+    let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+
+    for _ in 0..300 {
+        cg.zero_grad();
+        for x_inp in input_range.into_iter() {
+            cg.reset_primal_of_variable(&x, x_inp);
+            cg.reset_primal_of_variable(&t, target(x_inp));
+            cg.accumulate(&loss);
+        }
+        cg.step(2.0).unwrap();
+    }
+
+    let mut df = |x_inp: f32| {
+        cg.reset_state_for_next_input();
+        cg.reset_primal_of_variable(&x, x_inp);
+        cg.forward(&p)
+    };
+    // Far from the boundary the probabilities are close to the labels.
+    let far = FloatRange::new(2.0, 4.0, 0.1);
+    assert_functions_similar(
+        target,
+        &mut df,
+        &[
+            Opts::InputRange(far),
+            Opts::TestName("test_fit_logistic_bce.csv"),
+            Opts::MaxRms(0.1),
+        ],
+    );
+    assert!(df(-1.0) < 0.1);
+    assert!(df(0.5) < 0.5 && df(1.5) > 0.5);
+}
+
+fn new_eb() -> ExprBuilder<f32, FloatOperAry1, FloatOperAry2> {
+    ExprBuilder::new()
+}
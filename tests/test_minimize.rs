@@ -0,0 +1,58 @@
+use rs_autograd::{
+    compute::ComputGraph,
+    core_syntax::ExprBuilder,
+    float::{
+        calculator::FloatCalculator,
+        syntax::{AsConst, FloatOperAry1, FloatOperAry2},
+    },
+    losses::Loss,
+    minimize::{minimize, Method, Opts},
+};
+
+/// Fitting a polynomial is hard with a fixed-step gradient descent, but a full-batch L-BFGS recovers the
+/// coefficients in a few dozen iterations.
+#[test]
+fn test_fit_polynomial_lbfgs() {
+    let target_poly = |x: f32| 0.5 * x.powi(3) - 2.0 * x.powi(2) + x + 3.0;
+
+    let eb = new_eb();
+    let coefs = [0, 1, 2, 3].map(|i| eb.new_named_parameter(&format!("c{}", i), 0.0));
+    let xs: Vec<f32> = (0..21).map(|i| -2.0 + 0.25 * i as f32).collect();
+    let mut loss = 0.0.as_const(&eb);
+    for &x in xs.iter() {
+        let x_c = x.as_const(&eb);
+        let y = coefs[0] + coefs[1] * x_c + coefs[2] * x_c.powi(2) + coefs[3] * x_c.powi(3);
+        loss += y.loss(target_poly(x).as_const(&eb), Loss::Mse);
+    }
+    loss /= xs.len() as f32;
+    let coefs = coefs.map(|c| c.ident);
+    let loss = loss.ident;
+    let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+
+    let result = minimize(&mut cg, &loss, &coefs, Method::Lbfgs(10), &[]);
+    assert!(result.converged, "{:?}", result);
+    assert!(result.iterations < 50, "{:?}", result);
+    let fitted = coefs.map(|c| cg.primal(&c));
+    for (fitted, expected) in fitted.iter().zip([3.0, 1.0, -2.0, 0.5]) {
+        assert!((fitted - expected).abs() < 1e-2, "{:?}", fitted);
+    }
+
+    // The same budget of gradient descent is far from the minimum.
+    let start = cg.snapshot_params();
+    for c in coefs.iter() {
+        cg.reset_primal_of_parameter(c, 0.0);
+    }
+    let gd = minimize(
+        &mut cg,
+        &loss,
+        &coefs,
+        Method::GradientDescent,
+        &[Opts::MaxIter(result.iterations)],
+    );
+    assert!(gd.loss > 100.0 * result.loss.max(1e-6), "{:?}", gd);
+    cg.restore_params(&start);
+}
+
+fn new_eb() -> ExprBuilder<f32, FloatOperAry1, FloatOperAry2> {
+    ExprBuilder::new()
+}
@@ -1,5 +1,6 @@
 mod utils;
 use ndarray as nd;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 use rs_autograd::{
     compute::ComputGraph,
     core_syntax::ExprBuilder,
@@ -51,9 +52,8 @@ fn sum_relu_func() {
         cg.forward(&y);
         cg.backward(&y);
 
-        // return sum and not .v(), since the node after m is a matrix, not single value, and x contributes to
-        // each field of that matrix.
-        cg.adjoin(&x).unwrap().m().unwrap().sum()
+        // x is broadcast to each field of the matrix after it, so its adjoin is summed over those fields.
+        cg.adjoin(&x).unwrap().v().unwrap()
     };
 
     assert_function_and_derivative_similar(
@@ -76,6 +76,164 @@ fn sum_relu_func() {
     }
 }
 
+/// The same piecewise function as in [sum_relu_func], modeled directly with the conditions.
+#[test]
+fn piecewise_select_func() {
+    let input_range = FloatRange::new(-2.0, 6.0, 0.1);
+    let f = |x: f32| {
+        if x < 0.0 {
+            0.0
+        } else if x <= 2.0 {
+            x
+        } else if x <= 4.0 {
+            2.0
+        } else {
+            10.0 - 2.0 * x
+        }
+    };
+    let df = |x: f32| {
+        if x < 0.0 {
+            0.0
+        } else if x <= 2.0 {
+            1.0
+        } else if x <= 4.0 {
+            0.0
+        } else {
+            -2.0
+        }
+    };
+
+    let eb = ExprBuilder::<MatrixF32, NaOperAry1, NaOperAry2>::new();
+    let x = eb.new_variable("x");
+    let [c0, c2, c4, c10] =
+        [0.0, 2.0, 4.0, 10.0].map(|c| eb.new_named_parameter(&format!("c{}", c), MatrixF32::V(c)));
+    let y = x
+        .lt(c0)
+        .select(c0, x.gt(c2).select(x.gt(c4).select(c10 - c2 * x, c2), x));
+    let [x, y] = [x, y].map(|p| p.ident);
+    let mut cg = ComputGraph::<MatrixF32, NaOperAry1, NaOperAry2>::new(eb, &MatrixCalculator);
+    for x_inp in input_range.into_iter() {
+        cg.reset_state_for_next_epoch();
+        cg.set_variable(&x, MatrixF32::V(x_inp));
+        assert_eq!(MatrixF32::V(f(x_inp)), cg.forward(&y), "x={}", x_inp);
+        cg.backward(&y);
+        // The gradient flows only through the chosen branch.
+        assert_eq!(
+            MatrixF32::V(df(x_inp)),
+            cg.adjoin(&x).unwrap(),
+            "x={}",
+            x_inp
+        );
+    }
+}
+
+/// A convolution followed by a dense layer, with all the layout operators in between. The gradients of the image
+/// and the kernel are compared with the central finite difference of each element.
+#[test]
+fn layout_ops_gradient() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut random_m =
+        |shape: &[usize]| nd::ArrayD::from_shape_fn(shape, |_| rng.gen_range(-1.0..1.0));
+    let img_m = random_m(&[4, 5]);
+    let k_m = random_m(&[2, 2]);
+
+    let eb = ExprBuilder::<MatrixF32, NaOperAry1, NaOperAry2>::new();
+    let img = eb.new_variable("img");
+    let k = eb.new_variable("k");
+    let w = eb.new_named_parameter("w", MatrixF32::new_m(random_m(&[38])));
+    let c = img.conv2d(k); // [3, 4]
+    let s = c.transpose().slice(&[1..4]); // [3, 3]
+    let st = s.stack(&[c.slice(&[0..3, 1..4])], 0); // [2, 3, 3]
+    let f = st.permute_axes(&[2, 0, 1]).flatten(); // [18]
+    let features = f.concat(&[img.reshape(&[20])], 0); // [38]
+    let y = w.matmul(features).powi(2);
+    let [img, k, y] = [img, k, y].map(|p| p.ident);
+    let mut cg = ComputGraph::<MatrixF32, NaOperAry1, NaOperAry2>::new(eb, &MatrixCalculator);
+
+    cg.set_variable(&img, MatrixF32::new_m(img_m.clone()));
+    cg.set_variable(&k, MatrixF32::new_m(k_m.clone()));
+    cg.forward(&y);
+    cg.backward(&y);
+    let gradients = [
+        (img, img_m.clone(), cg.adjoin(&img).unwrap()),
+        (k, k_m.clone(), cg.adjoin(&k).unwrap()),
+    ];
+
+    let h = 1e-2;
+    let mut value = |ident, m: nd::ArrayD<f32>| {
+        cg.reset_state_for_next_epoch();
+        cg.reset_primal_of_variable(&img, MatrixF32::new_m(img_m.clone()));
+        cg.reset_primal_of_variable(&k, MatrixF32::new_m(k_m.clone()));
+        cg.reset_primal_of_variable(&ident, MatrixF32::new_m(m));
+        cg.forward(&y).v().unwrap()
+    };
+    for (ident, m, adjoin) in gradients {
+        let adjoin = adjoin.m().unwrap();
+        assert_eq!(adjoin.shape(), m.shape());
+        for (ix, expected) in adjoin.indexed_iter() {
+            let (mut m_p, mut m_n) = (m.clone(), m.clone());
+            m_p[&ix] += h;
+            m_n[&ix] -= h;
+            let fd = (value(ident, m_p) - value(ident, m_n)) / (2.0 * h);
+            assert!(
+                (fd - expected).abs() < 1e-2 * (1.0 + fd.abs()),
+                "{} at {:?}: {} vs {}",
+                ident,
+                ix,
+                expected,
+                fd
+            );
+        }
+    }
+}
+
+/// Max and average pooling of a batch of 2 images, then global average pooling. The gradient of the images is
+/// compared with the central finite difference of each element. The pixels are distinct and 0.1 apart, so the
+/// maximum of no window changes within the finite difference step.
+#[test]
+fn pooling_gradient() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut pixels: Vec<f32> = (0..72).map(|i| i as f32 * 0.1 - 3.6).collect();
+    pixels.shuffle(&mut rng);
+    let img_m = nd::ArrayD::from_shape_vec(vec![2, 6, 6], pixels).unwrap();
+
+    let eb = ExprBuilder::<MatrixF32, NaOperAry1, NaOperAry2>::new();
+    let img = eb.new_variable("img");
+    let m = img.max_pool2d([3, 3], [1, 1]); // [2, 4, 4]
+    let a = img.avg_pool2d([2, 2], [2, 2]); // [2, 3, 3]
+    let g = m.max_pool2d([2, 2], [2, 2]).global_avg_pool2d() * a.global_avg_pool2d(); // [2]
+    let y = g.sum() + m.powi(2).sum() + a.avg_pool2d([2, 3], [1, 1]).powi(3).sum();
+    let [img, y] = [img, y].map(|p| p.ident);
+    let mut cg = ComputGraph::<MatrixF32, NaOperAry1, NaOperAry2>::new(eb, &MatrixCalculator);
+
+    cg.set_variable(&img, MatrixF32::new_m(img_m.clone()));
+    cg.forward(&y);
+    cg.backward(&y);
+    let adjoin = cg.adjoin(&img).unwrap();
+    let adjoin = adjoin.m().unwrap();
+    assert_eq!(adjoin.shape(), img_m.shape());
+
+    let h = 1e-2;
+    let mut value = |m: nd::ArrayD<f32>| {
+        cg.reset_state_for_next_epoch();
+        cg.reset_primal_of_variable(&img, MatrixF32::new_m(m));
+        cg.forward(&y).v().unwrap()
+    };
+    for (ix, expected) in adjoin.indexed_iter() {
+        let (mut m_p, mut m_n) = (img_m.clone(), img_m.clone());
+        m_p[&ix] += h;
+        m_n[&ix] -= h;
+        let fd = (value(m_p) - value(m_n)) / (2.0 * h);
+        assert!(
+            (fd - expected).abs() < 1e-2 * (1.0 + fd.abs()),
+            "at {:?}: {} vs {}",
+            ix,
+            expected,
+            fd
+        );
+    }
+}
+
 fn sh((a, b): (usize, usize)) -> nd::IxDyn {
     nd::IxDyn(&[a, b])
 }
@@ -5,6 +5,7 @@ use rand::{Rng, SeedableRng};
 use rs_autograd::{
     compute::ComputGraph,
     core_syntax::ExprBuilder,
+    losses::{Loss, Reduction},
     nar::{
         calculator::MatrixCalculator,
         syntax::{MatrixF32, NaOperAry1, NaOperAry2},
@@ -80,6 +81,99 @@ fn test_na_gradient_descent_sin() {
     );
 }
 
+/// Fit the weights and the bias of a dense layer `x · w + b` to the outputs of known ones, on a batch of 32
+/// samples. The bias is a row broadcast to all the samples.
+#[test]
+fn test_na_gradient_descent_dense_layer() {
+    let target_w = nd::arr2(&[[1.0, -2.0], [0.5, 0.0], [-1.5, 3.0]]);
+    let mut rng = StdRng::seed_from_u64(42);
+    let inputs = nd::Array2::from_shape_fn((32, 3), |_| rng.gen_range(-1.0..1.0));
+    let target_b = nd::arr1(&[0.5, -0.25]);
+    let targets = inputs.dot(&target_w) + &target_b;
+
+    let eb = new_eb();
+    let x = eb.new_variable("x");
+    let w = eb.new_named_parameter("w", MatrixF32::new_m(nd::ArrayD::zeros(sh((3, 2)))));
+    let t = eb.new_variable("t");
+    let b = eb.new_named_parameter("b", MatrixF32::new_m(nd::ArrayD::zeros(vec![2])));
+    let loss = (x.matmul(w) + b).loss(t, Loss::Mse, Reduction::Mean);
+
+    let [x, w, b, t, loss] = [x, w, b, t, loss].map(|p| p.ident);
+    let mut cg = ComputGraph::<MatrixF32, _, _>::new(eb, &MatrixCalculator);
+    cg.reset_primal_of_variable(&x, inputs.into());
+    cg.reset_primal_of_variable(&t, targets.into());
+
+    let mut total_loss = 0.0;
+    for _ in 0..500 {
+        cg.zero_grad();
+        total_loss = cg.accumulate(&loss).v().unwrap();
+        cg.step(0.5).unwrap();
+    }
+
+    assert!(total_loss < 1e-6, "loss {}", total_loss);
+    let fitted = cg.primal(&w);
+    let max_err = (fitted.m().unwrap() - &target_w.view().into_dyn())
+        .iter()
+        .fold(0.0_f32, |acc, e| acc.max(e.abs()));
+    assert!(max_err < 1e-3, "w\n{}\ntarget\n{}", fitted, target_w);
+    let fitted = cg.primal(&b);
+    assert_eq!(fitted.m().unwrap().shape(), [2]);
+    let max_err = (fitted.m().unwrap() - &target_b.view().into_dyn())
+        .iter()
+        .fold(0.0_f32, |acc, e| acc.max(e.abs()));
+    assert!(max_err < 1e-3, "b\n{}\ntarget\n{}", fitted, target_b);
+}
+
+/// Classify points of the plane into the 3 sectors around the origin with a linear softmax classifier, trained
+/// on the class indices with the fused softmax cross-entropy.
+#[test]
+fn test_na_gradient_descent_softmax_classifier() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let inputs = nd::Array2::from_shape_fn((60, 2), |_| rng.gen_range(-1.0..1.0));
+    let sector = |p: nd::ArrayView1<f32>| {
+        let angle = p[1].atan2(p[0]) + std::f32::consts::PI;
+        ((angle / (2.0 * std::f32::consts::PI / 3.0)) as usize).min(2) as f32
+    };
+    let labels: nd::Array1<f32> = inputs.rows().into_iter().map(sector).collect();
+
+    let eb = new_eb();
+    let x = eb.new_variable("x");
+    let w = eb.new_named_parameter("w", MatrixF32::new_m(nd::ArrayD::zeros(sh((2, 3)))));
+    let b = eb.new_named_parameter("b", MatrixF32::new_m(nd::ArrayD::zeros(vec![3])));
+    let y = eb.new_variable("y");
+    let logits = x.matmul(w) + b;
+    let loss = logits.softmax_cross_entropy(y, Reduction::Mean);
+
+    let [x, y, logits, loss] = [x, y, logits, loss].map(|p| p.ident);
+    let mut cg = ComputGraph::<MatrixF32, _, _>::new(eb, &MatrixCalculator);
+    cg.reset_primal_of_variable(&x, inputs.into());
+    cg.reset_primal_of_variable(&y, labels.clone().into());
+
+    let initial_loss = cg.accumulate(&loss).v().unwrap();
+    assert!((initial_loss - 3.0_f32.ln()).abs() < 1e-6);
+    let mut total_loss = initial_loss;
+    for _ in 0..500 {
+        cg.zero_grad();
+        total_loss = cg.accumulate(&loss).v().unwrap();
+        cg.step(1.0).unwrap();
+    }
+    assert!(total_loss < 0.3, "loss {}", total_loss);
+
+    let predicted = cg.forward(&logits);
+    let correct = predicted
+        .m()
+        .unwrap()
+        .rows()
+        .into_iter()
+        .zip(labels.iter())
+        .filter(|(row, label)| {
+            let argmax = (0..3).fold(0, |best, i| if row[i] > row[best] { i } else { best });
+            argmax as f32 == **label
+        })
+        .count();
+    assert!(correct >= 57, "{} of 60 correct", correct);
+}
+
 fn new_eb() -> ExprBuilder<MatrixF32, NaOperAry1, NaOperAry2> {
     ExprBuilder::new()
 }
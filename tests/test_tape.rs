@@ -0,0 +1,163 @@
+mod utils;
+
+use std::time::Instant;
+
+use rs_autograd::{
+    compute::ComputGraph,
+    core_syntax::ExprBuilder,
+    float::{
+        calculator::FloatCalculator,
+        syntax::{FloatOperAry1, FloatOperAry2},
+    },
+    tape::{SlotKind, Tape},
+};
+use utils::{assert_functions_similar, FloatRange, Opts};
+
+#[test]
+fn test_tape_same_as_graph() {
+    let eb = new_eb();
+    let x1 = eb.new_variable("x1");
+    let x2 = eb.new_variable("x2");
+    let p = eb.new_named_parameter("p", 0.5);
+    let unused = eb.new_variable("unused");
+    let y = ((x1 * x2).sin() + x1.powi(2) - x2.cos() * p).relu();
+    let _ = unused * x1;
+    let [x1, x2, p, unused, y] = [x1, x2, p, unused, y].map(|e| e.ident);
+
+    let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+    cg.reset_primal_of_variable(&x1, 2.0);
+    cg.reset_primal_of_variable(&x2, 0.3);
+    let mut tape = Tape::new(&cg, &y, &FloatCalculator);
+
+    assert_eq!(None, tape.slot(&unused));
+    assert_eq!(
+        Some(SlotKind::Parameter),
+        tape.slot(&p).map(|s| tape.slot_kind(s))
+    );
+    assert_eq!(y, tape.ident(tape.output_slot()));
+
+    let expected = cg.forward(&y);
+    cg.backward(&y);
+    assert_eq!(expected, tape.forward());
+    tape.backward();
+    for ident in [x1, x2, p, y] {
+        assert_eq!(cg.adjoin(&ident), tape.adjoin(&ident), "ident {}", ident);
+    }
+
+    // Re-run the tape with new input.
+    tape.reset_adjoins();
+    tape.set_variable(&x1, -1.0);
+    let expected = ((-1.0_f32 * 0.3).sin() + 1.0 - 0.3_f32.cos() * 0.5).max(0.0);
+    assert_eq!(expected, tape.forward());
+    assert_eq!(Some(-1.0), tape.primal(&x1));
+}
+
+#[test]
+fn test_tape_fit_simple_relu() {
+    let target_poly = |x: f32| {
+        let x = x - 2.0;
+        3.0 * (if x > 0.0 { x } else { 0.0 })
+    };
+
+    let eb = new_eb();
+    let x = eb.new_variable("x");
+    let params = [1, 2].map(|i| eb.new_parameter(0.01 * (i as f32)));
+    let y = (x - params[0]).relu() * params[1];
+    let t = eb.new_variable("t");
+    let loss = (y - t).powi(2);
+
+    let input_range = FloatRange::new(-2.0, 6.0, 0.1);
+    let [x, y, t, loss] = [x, y, t, loss].map(|p| p.ident);
+    let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+
+    let n_epochs = 50;
+    let learn_rate = 0.1;
+
+    let mut tape = Tape::new(&cg, &loss, &FloatCalculator);
+    let (x_slot, t_slot) = (tape.slot(&x).unwrap(), tape.slot(&t).unwrap());
+    for _ in 0..n_epochs {
+        for x_inp in input_range.into_iter() {
+            tape.set_variable_slot(x_slot, x_inp);
+            tape.set_variable_slot(t_slot, target_poly(x_inp));
+            tape.forward();
+            tape.backward();
+        }
+        tape.update_params_lr(learn_rate);
+    }
+    tape.store_params(&mut cg);
+
+    let mut df = |x_inp: f32| {
+        cg.reset_state_for_next_input();
+        cg.reset_primal_of_variable(&x, x_inp);
+        cg.forward(&y)
+    };
+    assert_functions_similar(
+        target_poly,
+        &mut df,
+        &[
+            Opts::InputRange(input_range),
+            Opts::TestName("test_tape_fit_simple_relu"),
+        ],
+    );
+}
+
+/// Train a sum of ReLUs both with the graph and with the tape, and compare the times. Run in release with
+/// `cargo test --release --test test_tape -- --ignored`.
+#[ignore]
+#[test]
+fn test_tape_speedup() {
+    let eb = new_eb();
+    let x = eb.new_variable("x");
+    let t = eb.new_variable("t");
+    let mut y = eb.new_parameter(0.0);
+    for i in 0..20 {
+        let knot = eb.new_parameter(-3.0 + 0.3 * i as f32);
+        let slope = eb.new_parameter(0.1);
+        y = y + (x - knot).relu() * slope;
+    }
+    let loss = (y - t).powi(2);
+    let [x, t, loss] = [x, t, loss].map(|p| p.ident);
+    let mut cg = ComputGraph::<f32, _, _>::new(eb, &FloatCalculator);
+    let input_range = FloatRange::new(-3.1, 3.2, 0.1);
+    let n_epochs = 100;
+
+    let start = Instant::now();
+    for _ in 0..n_epochs {
+        for x_inp in input_range.into_iter() {
+            cg.reset_state_for_next_input();
+            cg.reset_primal_of_variable(&x, x_inp);
+            cg.reset_primal_of_variable(&t, x_inp.sin());
+            cg.forward(&loss);
+            cg.backward(&loss);
+        }
+        cg.update_params_lr(0.001);
+    }
+    let graph_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut tape = Tape::new(&cg, &loss, &FloatCalculator);
+    let (x_slot, t_slot) = (tape.slot(&x).unwrap(), tape.slot(&t).unwrap());
+    for _ in 0..n_epochs {
+        for x_inp in input_range.into_iter() {
+            tape.set_variable_slot(x_slot, x_inp);
+            tape.set_variable_slot(t_slot, x_inp.sin());
+            tape.forward();
+            tape.backward();
+        }
+        tape.update_params_lr(0.001);
+    }
+    let tape_time = start.elapsed();
+
+    let speedup = graph_time.as_secs_f64() / tape_time.as_secs_f64();
+    assert!(
+        speedup >= 10.0,
+        "graph {:?}, tape {:?}, speedup {:.1}",
+        graph_time,
+        tape_time,
+        speedup
+    );
+}
+
+fn new_eb() -> ExprBuilder<f32, FloatOperAry1, FloatOperAry2> {
+    ExprBuilder::new()
+}
@@ -1,4 +1,4 @@
-use std::{cmp, fmt, fs::File, io::Write, ops};
+use std::{cmp, fmt, fs::File, io::Write, ops, path::Path};
 
 pub enum Opts<'a> {
     InputRange(FloatRange<f32>),
@@ -105,14 +105,16 @@ where
     }
 }
 
+/// Write the series to `file_name` under the target directory, so the dumps never land in the source tree.
 fn write_series_to_file(
     file_name: &str,
     x_values: &Vec<f32>,
     y1_values: &Vec<f32>,
     y2_values: &Vec<f32>,
 ) {
-    println!("Writing to file {}", file_name);
-    let mut f = File::create(file_name).unwrap();
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(file_name);
+    println!("Writing to file {}", path.display());
+    let mut f = File::create(path).unwrap();
     f.write(format!("i\tx\ty1\ty2\n").as_bytes()).unwrap();
     for i in 0..x_values.len() {
         f.write(